@@ -0,0 +1,359 @@
+//! Runs representative jobs against a simulated Grbl, to check the stream, check mode, probe,
+//! process and abort paths end to end without a machine. Build cnc-ctrl first:
+//!
+//! ```bash
+//! cargo build && cargo run --example full_job
+//! ```
+//!
+//! The simulator is the far end of a pseudo-terminal, which cnc-ctrl opens as its serial port.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serialport::{SerialPort, TTYPort};
+
+const BANNER: &str = "Grbl 1.1h ['$' for help]\r\n";
+
+// A 3 x 3 grid probed on a 50 mm pitch
+const PROBE_PROGRAM: &str = "\
+G21 G90
+G0 Z5
+G0 X0 Y0
+G38.2 Z-20 F100
+G0 Z5
+G0 X50
+G38.2 Z-20 F100
+G0 Z5
+G0 X100
+G38.2 Z-20 F100
+G0 Z5
+G0 Y50
+G38.2 Z-20 F100
+G0 Z5
+G0 X50
+G38.2 Z-20 F100
+G0 Z5
+G0 X0
+G38.2 Z-20 F100
+G0 Z5
+G0 Y100
+G38.2 Z-20 F100
+G0 Z5
+G0 X50
+G38.2 Z-20 F100
+G0 Z5
+G0 X100
+G38.2 Z-20 F100
+G0 Z5
+";
+
+// Grbl rejects G5.1, so the job aborts there
+const ABORT_PROGRAM: &str = "\
+G21 G90
+G0 X10
+G5.1 X20 Y20
+G0 X30
+";
+
+const JOB: &str = r#"logs:
+  verbose: false
+  save: false
+  path: "{dir}/logs/{%t}"
+serial:
+  port: "{port}"
+  baudrate: 115200
+  timeout_ms: 1000
+grbl:
+  rx_buffer_size_bytes: 128
+inputs:
+  signal:
+    pin: 17
+    debounce_ms: 50
+  fallback: console
+job:
+  name: full_job
+  repeat: once
+steps:
+{steps}"#;
+
+const PROBE_STEPS: &str = r#"  - type: gcode
+    name: probe
+    path: "{dir}/probe.nc"
+    wait_for_signal: false
+    check: true
+    probe:
+      save_path: "{dir}/points.csv"
+  - type: bash
+    name: process
+    command: "test $(wc -l < '{dir}/points.csv') -eq 10"
+"#;
+
+const ABORT_STEPS: &str = r#"  - type: gcode
+    name: abort
+    path: "{dir}/abort.nc"
+    wait_for_signal: false
+    check: false
+    on_error: abort
+"#;
+
+/// What the simulated controller was sent
+#[derive(Default)]
+struct Received {
+    lines: Vec<String>,
+    resets: usize,
+}
+
+/// Answers like Grbl 1.1: `ok` to each line, status reports to `?`, `[PRB:...]` to a probe
+/// against a gently sloping surface, and `error:20` to commands Grbl doesn't support
+struct Simulator {
+    position: (f64, f64, f64),
+    relative: bool,
+    check_mode: bool,
+    received: Arc<Mutex<Received>>,
+}
+
+impl Simulator {
+    fn surface(x: f64, y: f64) -> f64 {
+        -10.0 + 0.001 * x + 0.002 * y
+    }
+
+    fn respond(&mut self, line: &str) -> String {
+        self.received.lock().unwrap().lines.push(line.to_string());
+
+        match line {
+            "$I" => return "[VER:1.1h.20190825:]\r\n[OPT:V,15,128]\r\nok\r\n".to_string(),
+            "$C" => {
+                self.check_mode = !self.check_mode;
+                let state = if self.check_mode {
+                    "Enabled"
+                } else {
+                    "Disabled"
+                };
+                return format!("[MSG:{}]\r\nok\r\n", state);
+            }
+            _ if line.starts_with('$') => return "ok\r\n".to_string(),
+            _ => {}
+        }
+
+        let words: Vec<(char, f64)> = line
+            .split_whitespace()
+            .filter_map(|word| {
+                let mut chars = word.chars();
+                let letter = chars.next()?.to_ascii_uppercase();
+                Some((letter, chars.as_str().parse().ok()?))
+            })
+            .collect();
+        let has = |letter: char, value: f64| words.contains(&(letter, value));
+
+        if has('G', 5.1) {
+            return "error:20\r\n".to_string();
+        }
+        if has('G', 90.0) {
+            self.relative = false;
+        }
+        if has('G', 91.0) {
+            self.relative = true;
+        }
+        if self.check_mode {
+            return "ok\r\n".to_string();
+        }
+
+        let mut target = self.position;
+        for &(letter, value) in &words {
+            let axis = match letter {
+                'X' => &mut target.0,
+                'Y' => &mut target.1,
+                'Z' => &mut target.2,
+                _ => continue,
+            };
+            *axis = if self.relative { *axis + value } else { value };
+        }
+
+        if has('G', 38.2) {
+            let (x, y, _) = self.position;
+            let z = Self::surface(x, y).max(target.2);
+            let contact = z > target.2;
+            self.position = (x, y, z);
+            return format!(
+                "[PRB:{:.3},{:.3},{:.3},0.000,0.000:{}]\r\nok\r\n",
+                x, y, z, contact as u8
+            );
+        }
+
+        self.position = target;
+        "ok\r\n".to_string()
+    }
+
+    fn status(&self) -> String {
+        let (x, y, z) = self.position;
+        format!(
+            "<Idle|MPos:{:.3},{:.3},{:.3}|FS:0,0|WCO:0.000,0.000,0.000>\r\n",
+            x, y, z
+        )
+    }
+
+    fn serve(mut self, mut port: TTYPort, stop: Arc<AtomicBool>) {
+        let _ = port.write_all(BANNER.as_bytes());
+        let mut line = Vec::new();
+        let mut buffer = [0; 256];
+
+        while !stop.load(Ordering::Relaxed) {
+            let read = match port.read(&mut buffer) {
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+                Err(_) => return,
+            };
+
+            for &byte in &buffer[..read] {
+                let response = match byte {
+                    b'?' => self.status(),
+                    0x18 => {
+                        self.received.lock().unwrap().resets += 1;
+                        self.check_mode = false;
+                        line.clear();
+                        format!("\r\n{}", BANNER)
+                    }
+                    // Other realtime commands, e.g. feed hold, need no answer here
+                    b'!' | b'~' | 0x80.. => continue,
+                    b'\n' => {
+                        let text = String::from_utf8_lossy(&line).trim().to_string();
+                        line.clear();
+                        if text.is_empty() {
+                            continue;
+                        }
+                        self.respond(&text)
+                    }
+                    b'\r' => continue,
+                    byte => {
+                        line.push(byte);
+                        continue;
+                    }
+                };
+                if port.write_all(response.as_bytes()).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Runs cnc-ctrl with the job, against a fresh simulator
+fn run(
+    binary: &Path,
+    directory: &Path,
+    steps: &str,
+) -> Result<(bool, Received), Box<dyn std::error::Error>> {
+    let (mut master, slave) = TTYPort::pair()?;
+    master.set_timeout(Duration::from_millis(100))?;
+    let port = slave.name().ok_or("The pseudo-terminal has no name")?;
+
+    let dir = directory.to_string_lossy();
+    let config = directory.join("config.yml");
+    fs::write(
+        &config,
+        JOB.replace("{steps}", steps)
+            .replace("{port}", &port)
+            .replace("{dir}", &dir),
+    )?;
+
+    let received = Arc::new(Mutex::new(Received::default()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let simulator = Simulator {
+        position: (0.0, 0.0, 0.0),
+        relative: false,
+        check_mode: false,
+        received: received.clone(),
+    };
+    let serving = {
+        let stop = stop.clone();
+        thread::spawn(move || simulator.serve(master, stop))
+    };
+
+    let status = Command::new(binary)
+        .arg("--config")
+        .arg(&config)
+        .stdin(Stdio::null())
+        .status()?;
+
+    stop.store(true, Ordering::Relaxed);
+    let _ = serving.join();
+    drop(slave);
+
+    let received = std::mem::take(&mut *received.lock().unwrap());
+    Ok((status.success(), received))
+}
+
+fn full_job() -> Result<(), Box<dyn std::error::Error>> {
+    // target/<profile>/examples/full_job, next to target/<profile>/cnc-ctrl
+    let binary: PathBuf = std::env::current_exe()?
+        .parent()
+        .and_then(Path::parent)
+        .ok_or("Failed to find the target directory")?
+        .join("cnc-ctrl");
+    if !binary.exists() {
+        return Err(format!(
+            "'{}' doesn't exist, run `cargo build` first",
+            binary.display()
+        )
+        .into());
+    }
+
+    let directory = std::env::temp_dir().join(format!("cnc-ctrl-full-job-{}", std::process::id()));
+    fs::create_dir_all(&directory)?;
+    fs::write(directory.join("probe.nc"), PROBE_PROGRAM)?;
+    fs::write(directory.join("abort.nc"), ABORT_PROGRAM)?;
+    let steps = |steps: &str| steps.replace("{dir}", &directory.to_string_lossy());
+
+    println!("Streaming a probe grid, checked first, then processing its points");
+    let (success, received) = run(&binary, &directory, &steps(PROBE_STEPS))?;
+    if !success {
+        return Err("The probe job failed".into());
+    }
+    let probes = received
+        .lines
+        .iter()
+        .filter(|line| line.starts_with("G38.2"))
+        .count();
+    if probes != 18 {
+        return Err(format!(
+            "Expected 9 probes checked and 9 run, the simulator got {}",
+            probes
+        )
+        .into());
+    }
+    let points = fs::read_to_string(directory.join("points.csv"))?;
+    if points.lines().count() != 10 {
+        return Err(format!("Expected a header and 9 probe points, got:\n{}", points).into());
+    }
+
+    println!("Streaming a program Grbl rejects a line of, which aborts the job");
+    let (success, received) = run(&binary, &directory, &steps(ABORT_STEPS))?;
+    if success {
+        return Err("The job didn't fail on the rejected line".into());
+    }
+    if received.resets == 0 {
+        return Err("The job wasn't stopped with a soft reset".into());
+    }
+
+    fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match full_job() {
+        Ok(()) => {
+            println!("Full job passed");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Full job failed: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{self, IsTerminal};
+use std::sync::atomic::Ordering;
+
+use log::{info, warn};
+
+use crate::config::{AlarmAction, AlarmsConfig, CncConfig, Confirm, GrblConfig};
+use crate::controller::message::alarm_description;
+use crate::controller::serial::checked_stream;
+use crate::controller::{Controller, ControllerError};
+use crate::job::wait_for_signal;
+use crate::prompt;
+use crate::start_signal::StartSignal;
+use crate::tui;
+
+impl AlarmsConfig {
+    // Per-code policies take precedence over the alarm's class, which never limits retries
+    pub fn policy(&self, code: u8) -> (AlarmAction, Option<u32>) {
+        if let Some(policy) = self.codes.iter().find(|policy| policy.code == code) {
+            return (policy.action, policy.retries);
+        }
+
+        let action = match code {
+            1 | 2 => self.limit,
+            3 => self.reset,
+            4 | 5 => self.probe,
+            6..=9 => self.homing,
+            _ => self.other,
+        };
+
+        (action, None)
+    }
+}
+
+// `recoveries` is how many times this alarm has already been recovered from
+pub fn recover(
+    controller: &Controller,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+    code: u8,
+    recoveries: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (action, retries) = config.alarms.policy(code);
+
+    if let Some(retries) = retries
+        && recoveries >= retries
+    {
+        warn!(
+            "Alarm {} recurred after {} recovery attempt(s), giving up",
+            code, recoveries
+        );
+        return Err(ControllerError::AlarmError(code).into());
+    }
+
+    let command = match action {
+        AlarmAction::Abort => return Err(ControllerError::AlarmError(code).into()),
+        AlarmAction::Unlock => "$X",
+        AlarmAction::Home => "$H",
+        AlarmAction::Ask => match ask(controller, config, signal, code)? {
+            Some(command) => command,
+            None => return Err(ControllerError::AlarmError(code).into()),
+        },
+    };
+
+    warn!(
+        "Recovering from alarm {} ({}) with {}",
+        code,
+        alarm_description(code),
+        command
+    );
+
+    clear(controller, config, code, &[command])?;
+
+    info!("Recovered from alarm {}", code);
+
+    Ok(())
+}
+
+// The recovery the operator picked, or `None` to abort. The signal can only confirm, so it
+// recovers with whatever the alarm needs
+fn ask(
+    controller: &Controller,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+    code: u8,
+) -> Result<Option<&'static str>, Box<dyn std::error::Error>> {
+    match config.alarms.confirm {
+        Confirm::Console => {
+            if !tui::active() && !io::stdin().is_terminal() {
+                warn!(
+                    "Alarm {} needs an answer, but there's no terminal to ask on",
+                    code
+                );
+                return Ok(None);
+            }
+
+            let answer = prompt::ask(&format!(
+                "Alarm {} ({}): unlock ($X), home ($H) or abort? [u/h/A]",
+                code,
+                alarm_description(code)
+            ))?;
+            Ok(match answer.to_lowercase().as_str() {
+                "u" | "unlock" => Some("$X"),
+                "h" | "home" => Some("$H"),
+                _ => None,
+            })
+        }
+        Confirm::Signal => {
+            let command = if is_retryable(code) { "$X" } else { "$H" };
+            warn!(
+                "Alarm {} ({}): trigger the start signal to recover with {}, or stop the job",
+                code,
+                alarm_description(code),
+                command
+            );
+            Ok(wait_for_signal(controller, config, signal)?.then_some(command))
+        }
+    }
+}
+
+// Soft limit and probe alarms leave the machine position intact, so the program can be rerun
+pub fn is_retryable(code: u8) -> bool {
+    matches!(code, 2 | 4 | 5)
+}
+
+pub fn clear(
+    controller: &Controller,
+    config: &CncConfig,
+    code: u8,
+    commands: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Grbl resets when it alarms, emptying its buffers, so no response to an earlier line can
+    // still arrive, even after a response timeout
+    controller.flush_responses();
+    controller.alarm.store(0, Ordering::Relaxed);
+    controller.healthy.store(true, Ordering::Relaxed);
+
+    for &command in commands {
+        // Homing only responds once the cycle completes, so don't apply the response timeout
+        let grbl = match command {
+            "$H" => GrblConfig {
+                response_timeout_ms: None,
+                ..config.grbl.clone()
+            },
+            _ => config.grbl.clone(),
+        };
+
+        checked_stream(controller, vec![command], &grbl)
+            .map_err(|error| format!("Failed to recover from alarm {}: {}", code, error))?;
+    }
+
+    Ok(())
+}
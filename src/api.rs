@@ -0,0 +1,438 @@
+mod events;
+mod uploads;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::config::ApiConfig;
+use crate::controller::{Controller, Monitor};
+use crate::job::JobHandle;
+use crate::json::string;
+use crate::status;
+
+use events::Rates;
+use uploads::Uploads;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// A request's line and headers are only a few lines, anything bigger isn't meant for us
+const MAX_HEADERS: u64 = 16 * 1024;
+
+// A page with the status and the controls, for a browser without a client of its own
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>cnc-ctrl</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+button { font-size: 1.5em; margin: 0.3em; padding: 0.5em 1em; }
+pre { font-size: 1.2em; }
+</style>
+</head>
+<body>
+<pre id="status">Connecting...</pre>
+<button onclick="send('pause')">Pause</button>
+<button onclick="send('resume')">Resume</button>
+<button onclick="send('skip-wait')">Skip wait</button>
+<button onclick="send('spindle-stop')">Spindle stop</button>
+<button onclick="send('flood')">Flood</button>
+<button onclick="send('mist')">Mist</button>
+<button onclick="if (confirm('Abort the job?')) send('abort')">Abort</button>
+<script>
+const token = new URLSearchParams(location.search).get('token');
+const headers = token ? { Authorization: 'Bearer ' + token } : {};
+// Updates as the WebSocket sends the status or the fields that changed, reconnecting if it drops
+let status = {};
+function connect() {
+  const query = token ? '?token=' + encodeURIComponent(token) : '';
+  const events = new WebSocket((location.protocol == 'https:' ? 'wss://' : 'ws://') + location.host + '/events' + query);
+  events.onmessage = (message) => {
+    const event = JSON.parse(message.data);
+    if (event.type == 'status' || event.type == 'delta') {
+      status = event.type == 'status' ? event.status : Object.assign(status, event.status);
+      document.getElementById('status').textContent = JSON.stringify(status, null, 2);
+    }
+  };
+  events.onclose = () => {
+    document.getElementById('status').textContent = 'Disconnected';
+    setTimeout(connect, 2000);
+  };
+}
+async function send(action) {
+  const response = await fetch('/' + action, { method: 'POST', headers });
+  if (!response.ok) alert((await response.json()).error);
+}
+connect();
+</script>
+</body>
+</html>
+"#;
+
+/// The HTTP API's listener, bound before the job starts so a port in use fails straight away
+pub struct Server {
+    listener: TcpListener,
+    token: Option<String>,
+    monitor: Monitor,
+    rates: Rates,
+    uploads: Option<Uploads>,
+}
+
+pub fn bind(config: &ApiConfig, controller: &Controller) -> Result<Server, String> {
+    let listener = TcpListener::bind(&config.address).map_err(|error| {
+        format!(
+            "Failed to start HTTP API on '{}': {}",
+            config.address, error
+        )
+    })?;
+    let uploads = config.uploads.as_ref().map(Uploads::new).transpose()?;
+
+    info!("Serving HTTP API on {}", config.address);
+
+    Ok(Server {
+        listener,
+        token: config.token.clone(),
+        monitor: controller.monitor(),
+        rates: Rates {
+            interval: Duration::from_millis(config.stream_interval_ms),
+            snapshot_interval: Duration::from_millis(config.snapshot_interval_ms),
+        },
+        uploads,
+    })
+}
+
+impl Server {
+    /// Serves requests on threads of their own for as long as cnc-ctrl runs
+    pub fn serve(self, job: Arc<JobHandle>) {
+        let listener = self.listener;
+        let context = Arc::new(Context {
+            job,
+            token: self.token,
+            monitor: self.monitor,
+            rates: self.rates,
+            uploads: self.uploads,
+        });
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        warn!("Failed to accept HTTP API connection: {}", error);
+                        continue;
+                    }
+                };
+
+                let context = context.clone();
+                thread::spawn(move || {
+                    if let Err(error) = handle(stream, &context) {
+                        debug!("HTTP API request failed: {}", error);
+                    }
+                });
+            }
+        });
+    }
+}
+
+// Shared by the connections
+struct Context {
+    job: Arc<JobHandle>,
+    token: Option<String>,
+    monitor: Monitor,
+    rates: Rates,
+    uploads: Option<Uploads>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    websocket_key: Option<String>,
+    host: Option<String>,
+    origin: Option<String>,
+    // None if it was too big to read
+    body: Option<Vec<u8>>,
+}
+
+fn handle(mut stream: TcpStream, context: &Context) -> Result<(), Box<dyn std::error::Error>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let request = read_request(&stream)?;
+    let rates = context.rates.with_query(&request.query);
+
+    // Everything but the page itself needs the token, and a browser may only control the machine
+    // from a page served by us
+    let denied = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => None,
+        _ if !authorized(&request, context.token.as_deref()) => {
+            warn!("Unauthorized HTTP API request from {}", peer);
+            Some(error(401, "Missing or wrong token"))
+        }
+        ("POST" | "PUT", _) | ("GET", "/events") if !same_origin(&request) => {
+            warn!(
+                "Rejected HTTP API request from {} made by a page on {}",
+                peer,
+                request.origin.as_deref().unwrap_or_default()
+            );
+            Some(error(403, "Cross-origin requests aren't allowed"))
+        }
+        ("GET", "/events") => rates.as_ref().err().map(|message| error(400, message)),
+        _ => None,
+    };
+
+    if denied.is_none()
+        && let ("GET", "/events", Some(key), Ok(rates)) = (
+            request.method.as_str(),
+            request.path.as_str(),
+            &request.websocket_key,
+            rates,
+        )
+    {
+        debug!("WebSocket client {} connected", peer);
+        let result = events::stream(stream, key, &context.monitor, rates);
+        debug!("WebSocket client {} disconnected", peer);
+        return result;
+    }
+
+    // Actions run once the response is sent, an abort ends cnc-ctrl before it could be
+    let mut action: Option<fn(&JobHandle)> = None;
+    let (status, content_type, body) = match denied {
+        Some(denied) => denied,
+        None => match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => (200, "text/html; charset=utf-8", PAGE.to_string()),
+            ("GET", "/status") => (
+                200,
+                "application/json",
+                status::json(context.monitor.report().as_ref(), context.monitor.paused()),
+            ),
+            ("POST", path) if control(path).is_some() => {
+                info!("{} requested over HTTP by {}", &request.path[1..], peer);
+                action = control(path);
+                (200, "application/json", "{\"ok\": true}".to_string())
+            }
+            ("GET", "/events") => error(400, "Expected a WebSocket upgrade"),
+            (method, path) if path.starts_with("/uploads/") => match &context.uploads {
+                Some(uploads) => uploads.handle(
+                    method,
+                    &path["/uploads/".len()..],
+                    &request.query,
+                    request.body.as_deref(),
+                ),
+                None => error(404, "Uploads aren't enabled"),
+            },
+            (_, path) if matches!(path, "/" | "/status" | "/events") || control(path).is_some() => {
+                error(405, "Method not allowed")
+            }
+            _ => error(404, "Not found"),
+        },
+    };
+
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        422 => "Unprocessable Content",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    if let Some(action) = action {
+        action(&context.job);
+    }
+    Ok(())
+}
+
+// The job control a POST to `path` runs
+fn control(path: &str) -> Option<fn(&JobHandle)> {
+    match path {
+        "/pause" => Some(JobHandle::pause),
+        "/resume" => Some(JobHandle::resume),
+        "/skip-wait" => Some(JobHandle::skip_wait),
+        "/spindle-stop" => Some(JobHandle::toggle_spindle_stop),
+        "/flood" => Some(JobHandle::toggle_flood_coolant),
+        "/mist" => Some(JobHandle::toggle_mist_coolant),
+        "/abort" => Some(JobHandle::abort),
+        _ => None,
+    }
+}
+
+fn error(status: u16, message: &str) -> (u16, &'static str, String) {
+    (
+        status,
+        "application/json",
+        format!("{{\"error\": {}}}", string(message)),
+    )
+}
+
+// The token is taken from an `Authorization: Bearer` header, or a `token` query parameter for
+// the page's links
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+
+    let bearer = request
+        .authorization
+        .as_deref()
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    let query = request
+        .query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .map(percent_decode);
+
+    match (bearer, query) {
+        (Some(given), _) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+        (None, Some(given)) => constant_time_eq(&given, token.as_bytes()),
+        (None, None) => false,
+    }
+}
+
+// Query values are URL-encoded as a browser's `URLSearchParams` reads them, with `+` for a space.
+// A `%` not followed by two hex digits is kept as it is
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+    decoded
+}
+
+// Compares every byte whatever the first difference, so the time taken doesn't give the token away
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Browsers send `Origin` with cross-origin requests, other clients usually don't send it at all
+fn same_origin(request: &Request) -> bool {
+    let Some(origin) = &request.origin else {
+        return true;
+    };
+    let origin = origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"));
+
+    origin.is_some() && origin == request.host.as_deref()
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(stream);
+    let mut headers = (&mut reader).take(MAX_HEADERS);
+
+    let mut line = String::new();
+    headers.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(format!("Malformed request line '{}'", line.trim()).into());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization: None,
+        websocket_key: None,
+        host: None,
+        origin: None,
+        body: None,
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if headers.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            match name.trim().to_lowercase().as_str() {
+                "authorization" => request.authorization = Some(value.trim().to_string()),
+                "sec-websocket-key" => request.websocket_key = Some(value.trim().to_string()),
+                "host" => request.host = Some(value.trim().to_lowercase()),
+                "origin" => request.origin = Some(value.trim().to_lowercase()),
+                "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+
+    // Only uploads use the body, other bodies are read so closing the connection doesn't reset it
+    if content_length <= uploads::MAX_CHUNK {
+        let mut body = Vec::new();
+        reader.take(content_length).read_to_end(&mut body)?;
+        request.body = Some(body);
+    }
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str, authorization: Option<&str>) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/status".to_string(),
+            query: query.to_string(),
+            authorization: authorization.map(String::from),
+            websocket_key: None,
+            host: None,
+            origin: None,
+            body: None,
+        }
+    }
+
+    #[test]
+    fn query_tokens_are_decoded() {
+        let token = Some("a+b/c=d e");
+        assert!(authorized(&request("token=a%2Bb%2Fc%3Dd%20e", None), token));
+        assert!(authorized(&request("x=1&token=a%2bb/c%3dd+e", None), token));
+        assert!(!authorized(&request("token=a+b/c=d e", None), token));
+        assert!(!authorized(&request("", None), token));
+        assert!(authorized(&request("", Some("Bearer a+b/c=d e")), token));
+        assert!(authorized(&request("", None), None));
+    }
+
+    #[test]
+    fn malformed_escapes_are_kept() {
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz%4"), b"%zz%4");
+        assert_eq!(percent_decode("%e2%9c%93"), "\u{2713}".as_bytes());
+    }
+}
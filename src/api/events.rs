@@ -0,0 +1,330 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use base64::prelude::*;
+use crossbeam::channel::RecvTimeoutError;
+use sha1::{Digest, Sha1};
+
+use crate::controller::Monitor;
+use crate::json::string;
+use crate::status::{self, Event};
+
+// Appended to the client's key to accept the upgrade, from RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a read waits for the client, reads only check for pings and close frames
+const READ_POLL: Duration = Duration::from_millis(1);
+// Clients only send control frames, which are limited to 125 bytes
+const MAX_BUFFERED: usize = 4096;
+// Fastest a client can ask for the status
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// How often the status is sent to a client
+#[derive(Clone, Copy)]
+pub struct Rates {
+    pub interval: Duration,
+    /// How often the whole status is sent, with only the fields that changed sent in between
+    pub snapshot_interval: Duration,
+}
+
+impl Rates {
+    /// The rates a client asked for with `interval_ms` and `snapshot_interval_ms` in the query,
+    /// e.g. a dashboard on a phone over weak WiFi, and these rates otherwise
+    pub fn with_query(self, query: &str) -> Result<Rates, String> {
+        let mut rates = self;
+        for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            let rate = match name {
+                "interval_ms" => &mut rates.interval,
+                "snapshot_interval_ms" => &mut rates.snapshot_interval,
+                _ => continue,
+            };
+            let milliseconds = value
+                .parse()
+                .map_err(|_| format!("{} should be a number of milliseconds", name))?;
+            *rate = Duration::from_millis(milliseconds);
+        }
+        rates.interval = rates.interval.max(MIN_INTERVAL);
+        Ok(rates)
+    }
+}
+
+/// Upgrades the connection to a WebSocket, then sends the job's state and step as they change,
+/// probe results as they arrive and the status at `rates` until the client goes away
+pub fn stream(
+    mut stream: TcpStream,
+    key: &str,
+    monitor: &Monitor,
+    rates: Rates,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let accept = BASE64_STANDARD.encode(Sha1::digest(format!("{}{}", key.trim(), WEBSOCKET_GUID)));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    stream.set_read_timeout(Some(READ_POLL))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    // Subscribed before reading the state, so a change in between isn't missed
+    let events = status::subscribe();
+    send(&mut stream, &event(&Event::State(status::state())))?;
+
+    let mut received = Vec::new();
+    let mut next_status = Instant::now();
+    let mut next_snapshot = Instant::now();
+    let mut sent = Vec::new();
+    loop {
+        match events.recv_timeout(next_status.saturating_duration_since(Instant::now())) {
+            Ok(change) => send(&mut stream, &event(&change))?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let now = Instant::now();
+        if now >= next_status {
+            next_status = now + rates.interval;
+            let fields = status::fields(monitor.report().as_ref(), monitor.paused());
+            if now >= next_snapshot {
+                next_snapshot = now + rates.snapshot_interval;
+                send(
+                    &mut stream,
+                    &format!(
+                        "{{\"type\": \"status\", \"status\": {}}}",
+                        status::object(&fields)
+                    ),
+                )?;
+            } else if let Some(delta) = delta(&sent, &fields) {
+                send(
+                    &mut stream,
+                    &format!("{{\"type\": \"delta\", \"status\": {}}}", delta),
+                )?;
+            }
+            sent = fields;
+        }
+
+        if !receive(&mut stream, &mut received)? {
+            return Ok(());
+        }
+    }
+}
+
+// The fields that changed since the status last sent, nothing if none did
+fn delta(sent: &[(&str, String)], fields: &[(&'static str, String)]) -> Option<String> {
+    let changed: Vec<_> = fields
+        .iter()
+        .filter(|field| !sent.contains(field))
+        .cloned()
+        .collect();
+    (!changed.is_empty()).then(|| status::object(&changed))
+}
+
+fn event(event: &Event) -> String {
+    match event {
+        Event::State(state) => format!(
+            "{{\"type\": \"state\", \"state\": {}}}",
+            string(state.name())
+        ),
+        Event::Step(step) => format!("{{\"type\": \"step\", \"step\": {}}}", step.json()),
+        Event::Probe(probe) => format!("{{\"type\": \"probe\", \"probe\": {}}}", probe.json()),
+    }
+}
+
+fn send(stream: &mut TcpStream, text: &str) -> std::io::Result<()> {
+    stream.write_all(&frame(OPCODE_TEXT, text.as_bytes()))
+}
+
+// Answers pings and returns false once the client closed the connection
+fn receive(stream: &mut TcpStream, received: &mut Vec<u8>) -> std::io::Result<bool> {
+    let mut buffer = [0; 256];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(read) => received.extend_from_slice(&buffer[..read]),
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                break;
+            }
+            Err(error) => return Err(error),
+        }
+        if received.len() > MAX_BUFFERED {
+            return Ok(false);
+        }
+    }
+
+    while let Some((opcode, payload)) = next_frame(received) {
+        match opcode {
+            OPCODE_CLOSE => {
+                let _ = stream.write_all(&frame(OPCODE_CLOSE, &payload));
+                return Ok(false);
+            }
+            OPCODE_PING => stream.write_all(&frame(OPCODE_PONG, &payload))?,
+            _ => {}
+        }
+    }
+    Ok(true)
+}
+
+// Takes the first complete frame off `received`. Client frames are always masked
+fn next_frame(received: &mut Vec<u8>) -> Option<(u8, Vec<u8>)> {
+    let opcode = received.first()? & 0x0f;
+    let (length, start) = match received.get(1)? & 0x7f {
+        126 => (
+            u16::from_be_bytes(received.get(2..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        127 => (
+            u64::from_be_bytes(received.get(2..10)?.try_into().ok()?) as usize,
+            10,
+        ),
+        length => (length as usize, 2),
+    };
+    let mask = received.get(start..start + 4)?.to_vec();
+    let end = (start + 4).checked_add(length)?;
+    let payload = received
+        .get(start + 4..end)?
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ mask[index % 4])
+        .collect();
+
+    received.drain(..end);
+    Some((opcode, payload))
+}
+
+// A single unmasked frame, as sent by servers
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..126 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend((length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Masks a payload the way a client would
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = frame(opcode, payload);
+        let start = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        frame.splice(start..start, mask);
+        for (index, byte) in frame[start + 4..].iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+        frame
+    }
+
+    #[test]
+    fn frame_lengths() {
+        for (length, header) in [(125, 2), (126, 4), (65535, 4), (65536, 10)] {
+            let payload = vec![b'a'; length];
+            let frame = frame(OPCODE_TEXT, &payload);
+            assert_eq!(frame[0], 0x81);
+            assert_eq!(frame.len(), header + length);
+            match header {
+                2 => assert_eq!(frame[1] as usize, length),
+                4 => {
+                    assert_eq!(frame[1], 126);
+                    assert_eq!(u16::from_be_bytes([frame[2], frame[3]]) as usize, length);
+                }
+                _ => {
+                    assert_eq!(frame[1], 127);
+                    let bytes: [u8; 8] = frame[2..10].try_into().unwrap();
+                    assert_eq!(u64::from_be_bytes(bytes) as usize, length);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn next_frame_unmasks() {
+        for length in [0, 5, 125, 126, 65535, 65536] {
+            let payload: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let mut received = client_frame(OPCODE_PING, &payload);
+            assert_eq!(next_frame(&mut received), Some((OPCODE_PING, payload)));
+            assert!(received.is_empty());
+        }
+    }
+
+    #[test]
+    fn next_frame_waits_for_partial_frames() {
+        let whole = client_frame(OPCODE_CLOSE, b"bye");
+        for split in 0..whole.len() {
+            let mut received = whole[..split].to_vec();
+            assert_eq!(next_frame(&mut received), None);
+            assert_eq!(received.len(), split);
+
+            received.extend_from_slice(&whole[split..]);
+            assert_eq!(
+                next_frame(&mut received),
+                Some((OPCODE_CLOSE, b"bye".to_vec()))
+            );
+        }
+    }
+
+    #[test]
+    fn next_frame_leaves_following_frames() {
+        let mut received = client_frame(OPCODE_PING, b"one");
+        received.extend(client_frame(OPCODE_CLOSE, b""));
+        assert_eq!(
+            next_frame(&mut received),
+            Some((OPCODE_PING, b"one".to_vec()))
+        );
+        assert_eq!(next_frame(&mut received), Some((OPCODE_CLOSE, Vec::new())));
+        assert_eq!(next_frame(&mut received), None);
+    }
+
+    #[test]
+    fn deltas_only_have_changed_fields() {
+        let sent = vec![
+            ("state", "\"running\"".to_string()),
+            ("progress", "10.0".to_string()),
+        ];
+        let fields = vec![
+            ("state", "\"running\"".to_string()),
+            ("progress", "10.5".to_string()),
+        ];
+        assert_eq!(
+            delta(&sent, &fields).as_deref(),
+            Some("{\"progress\": 10.5}")
+        );
+        assert_eq!(delta(&fields, &fields), None);
+    }
+
+    #[test]
+    fn clients_set_their_rates() {
+        let rates = Rates {
+            interval: Duration::from_millis(500),
+            snapshot_interval: Duration::from_secs(10),
+        };
+
+        let asked = rates
+            .with_query("token=abc&interval_ms=2000&snapshot_interval_ms=0")
+            .unwrap();
+        assert_eq!(asked.interval, Duration::from_secs(2));
+        assert_eq!(asked.snapshot_interval, Duration::ZERO);
+        assert_eq!(rates.with_query("").unwrap().interval, rates.interval);
+        assert_eq!(
+            rates.with_query("interval_ms=1").unwrap().interval,
+            MIN_INTERVAL
+        );
+        assert!(rates.with_query("interval_ms=fast").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+
+use crate::config::UploadsConfig;
+use crate::json::string;
+
+use super::error;
+
+/// Largest chunk a single request can carry, so a request never needs more memory than this
+pub const MAX_CHUNK: u64 = 8 * 1024 * 1024;
+// Partial uploads are kept next to the finished ones under this extension
+const PARTIAL_EXTENSION: &str = "part";
+
+type Response = (u16, &'static str, String);
+
+/// Files uploaded in chunks over the HTTP API, e.g. a large program over shop WiFi that drops.
+/// An upload is appended to a partial file until its checksum is verified, then renamed
+pub struct Uploads {
+    directory: PathBuf,
+    max_size: u64,
+    // Chunks and completions are handled one at a time, so two can't interleave in one file
+    lock: Mutex<()>,
+}
+
+impl Uploads {
+    pub fn new(config: &UploadsConfig) -> Result<Self, String> {
+        let directory = PathBuf::from(crate::config::expand_path(&config.path));
+        fs::create_dir_all(&directory).map_err(|error| {
+            format!(
+                "Failed to create upload directory '{}': {}",
+                directory.display(),
+                error
+            )
+        })?;
+
+        Ok(Self {
+            directory,
+            max_size: config.max_size_mb * 1024 * 1024,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Handles a request to `/uploads/<name>`, with the body of a chunk if it had one
+    pub fn handle(&self, method: &str, name: &str, query: &str, body: Option<&[u8]>) -> Response {
+        if !valid_name(name) {
+            return error(400, "Names can only use letters, digits, '.', '-' and '_'");
+        }
+
+        let _lock = match self.lock.lock() {
+            Ok(lock) => lock,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let result = match method {
+            "GET" => self.status(name),
+            "PUT" => match body {
+                Some(body) => self.append(name, query, body),
+                None => Ok(error(
+                    413,
+                    &format!("Chunks can be at most {} bytes", MAX_CHUNK),
+                )),
+            },
+            "POST" => self.complete(name, query),
+            _ => Ok(error(405, "Method not allowed")),
+        };
+
+        result.unwrap_or_else(|error| self::error(500, &error.to_string()))
+    }
+
+    fn status(&self, name: &str) -> io::Result<Response> {
+        let (size, complete) = match fs::metadata(self.partial(name)) {
+            Ok(metadata) => (metadata.len(), false),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                match fs::metadata(self.directory.join(name)) {
+                    Ok(metadata) => (metadata.len(), true),
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                        return Ok(error_with_size(404, "No upload with this name", 0));
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+            Err(error) => return Err(error),
+        };
+
+        Ok(ok(name, size, complete))
+    }
+
+    // A chunk has to start where the partial file ends, so a chunk lost or sent twice is noticed.
+    // Offset 0 starts the upload over
+    fn append(&self, name: &str, query: &str, body: &[u8]) -> io::Result<Response> {
+        let Some(offset) = parameter(query, "offset").and_then(|offset| offset.parse::<u64>().ok())
+        else {
+            return Ok(error(400, "Expected an offset, e.g. ?offset=0"));
+        };
+
+        let path = self.partial(name);
+        let size = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => 0,
+            Err(error) => return Err(error),
+        };
+        if offset != 0 && offset != size {
+            return Ok(error_with_size(
+                409,
+                &format!("The upload is {} bytes, not {}", size, offset),
+                size,
+            ));
+        }
+        if offset + body.len() as u64 > self.max_size {
+            return Ok(error(
+                413,
+                &format!("Uploads can be at most {} bytes", self.max_size),
+            ));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(offset != 0)
+            .truncate(offset == 0)
+            .open(&path)?;
+        file.write_all(body)?;
+        file.sync_all()?;
+
+        Ok(ok(name, offset + body.len() as u64, false))
+    }
+
+    // A checksum that doesn't match means a chunk was corrupted, and it can't be told which, so
+    // the partial file is removed and the upload has to start over
+    fn complete(&self, name: &str, query: &str) -> io::Result<Response> {
+        let Some(expected) = parameter(query, "sha256") else {
+            return Ok(error(
+                400,
+                "Expected the file's checksum, e.g. ?sha256=<hex>",
+            ));
+        };
+
+        let path = self.partial(name);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(error_with_size(
+                    404,
+                    "No upload in progress with this name",
+                    0,
+                ));
+            }
+            Err(error) => return Err(error),
+        };
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+        let actual: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        if !actual.eq_ignore_ascii_case(expected) {
+            fs::remove_file(&path)?;
+            return Ok(error_with_size(
+                422,
+                &format!(
+                    "Checksum {} doesn't match the upload's, start it over",
+                    actual
+                ),
+                0,
+            ));
+        }
+
+        fs::rename(&path, self.directory.join(name))?;
+        Ok(ok(name, size, true))
+    }
+
+    fn partial(&self, name: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", name, PARTIAL_EXTENSION))
+    }
+}
+
+// Names become file names in the upload directory, so they can't leave it or be hidden
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 200
+        && !name.starts_with('.')
+        && !name.ends_with(&format!(".{}", PARTIAL_EXTENSION))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+fn parameter<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn ok(name: &str, size: u64, complete: bool) -> Response {
+    (
+        200,
+        "application/json",
+        format!(
+            "{{\"name\": {}, \"size\": {}, \"complete\": {}}}",
+            string(name),
+            size,
+            complete
+        ),
+    )
+}
+
+// Errors include the upload's size, so a client knows where to carry on from
+fn error_with_size(status: u16, message: &str, size: u64) -> Response {
+    (
+        status,
+        "application/json",
+        format!("{{\"error\": {}, \"size\": {}}}", string(message), size),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uploads(name: &str) -> Uploads {
+        let directory =
+            std::env::temp_dir().join(format!("cnc-ctrl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        Uploads::new(&UploadsConfig {
+            path: directory.to_string_lossy().into_owned(),
+            max_size_mb: 1,
+        })
+        .unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        Sha256::digest(data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_resume_where_the_upload_ends() {
+        let uploads = uploads("upload-resume");
+        let program = b"G21\nG90\nG0 X10\n";
+
+        assert_eq!(uploads.handle("GET", "part.nc", "", None).0, 404);
+        let put = |query: &str, body: &[u8]| uploads.handle("PUT", "part.nc", query, Some(body));
+        assert_eq!(put("offset=0", &program[..8]).0, 200);
+        // A chunk sent again after its response was lost
+        let (status, _, body) = put("offset=0", &program[..4]);
+        assert_eq!(status, 200);
+        assert!(body.contains("\"size\": 4"));
+        let (status, _, body) = put("offset=8", &program[4..]);
+        assert_eq!(status, 409);
+        assert!(body.contains("\"size\": 4"));
+        assert_eq!(put("offset=4", &program[4..]).0, 200);
+
+        let (_, _, body) = uploads.handle("GET", "part.nc", "", None);
+        assert!(body.contains("\"size\": 15, \"complete\": false"));
+        let query = format!("sha256={}", sha256(program));
+        assert_eq!(uploads.handle("POST", "part.nc", &query, None).0, 200);
+        assert_eq!(
+            fs::read(uploads.directory.join("part.nc")).unwrap(),
+            program
+        );
+        let (_, _, body) = uploads.handle("GET", "part.nc", "", None);
+        assert!(body.contains("\"complete\": true"));
+
+        fs::remove_dir_all(&uploads.directory).unwrap();
+    }
+
+    #[test]
+    fn mismatched_checksums_start_over() {
+        let uploads = uploads("upload-checksum");
+        uploads.handle("PUT", "part.nc", "offset=0", Some(b"G0 X1\n"));
+
+        let query = format!("sha256={}", sha256(b"G0 X2\n"));
+        assert_eq!(uploads.handle("POST", "part.nc", &query, None).0, 422);
+        assert_eq!(uploads.handle("GET", "part.nc", "", None).0, 404);
+        assert!(!uploads.directory.join("part.nc").exists());
+
+        fs::remove_dir_all(&uploads.directory).unwrap();
+    }
+
+    #[test]
+    fn limits_and_names() {
+        let uploads = uploads("upload-limits");
+        let megabyte = vec![b'\n'; 1024 * 1024];
+        assert_eq!(
+            uploads.handle("PUT", "a.nc", "offset=0", Some(&megabyte)).0,
+            200
+        );
+        assert_eq!(
+            uploads
+                .handle("PUT", "a.nc", "offset=1048576", Some(b"x"))
+                .0,
+            413
+        );
+        assert_eq!(uploads.handle("PUT", "a.nc", "offset=0", None).0, 413);
+        assert_eq!(uploads.handle("PUT", "a.nc", "", Some(b"x")).0, 400);
+
+        for name in ["../a.nc", ".hidden", "a.nc.part", "a b", ""] {
+            assert_eq!(uploads.handle("GET", name, "", None).0, 400, "{}", name);
+        }
+
+        fs::remove_dir_all(&uploads.directory).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{self, Sender};
+use log::{error, info, warn};
+
+use crate::config::{Edge, Level, PressConfig};
+use crate::gpio::Watch;
+use crate::job::JobHandle;
+use crate::start_signal::StartSignal;
+
+/// The signal button when it also takes long and double presses
+pub struct Button {
+    input: Box<dyn Watch>,
+    active: Level,
+    long: Duration,
+    double: Duration,
+    presses: Sender<()>,
+}
+
+impl Button {
+    /// The button, watched on both edges, and the start signal its short presses trigger
+    pub fn new(input: Box<dyn Watch>, active: Level, config: &PressConfig) -> (Self, StartSignal) {
+        let (tx, rx) = channel::unbounded();
+        let signal = StartSignal::Presses(input.pin(), rx);
+
+        let button = Self {
+            input,
+            active,
+            long: Duration::from_millis(config.long_ms),
+            double: Duration::from_millis(config.double_ms),
+            presses: tx,
+        };
+        (button, signal)
+    }
+
+    /// Decodes presses on a thread of its own. A long press aborts the job, a double press
+    /// pauses or resumes it, and a short press is passed on to the start signal
+    pub fn watch(mut self, job: Arc<JobHandle>) {
+        thread::spawn(move || {
+            if let Err(error) = self.decode(&job) {
+                error!("Signal button stopped: {}", error);
+            }
+        });
+    }
+
+    fn decode(&mut self, job: &JobHandle) -> Result<(), String> {
+        loop {
+            self.wait_for(true, None)?;
+
+            // Held down until the long press time is up
+            if !self.wait_for(false, Some(self.long))? {
+                warn!("Signal button held, aborting the job");
+                job.abort();
+                self.wait_for(false, None)?;
+                continue;
+            }
+
+            // A short press is only known to be one once no second press follows
+            if !self.wait_for(true, Some(self.double))? {
+                if self.presses.send(()).is_err() {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            self.wait_for(false, None)?;
+            info!("Signal button pressed twice");
+            job.toggle_pause();
+        }
+    }
+
+    // Waits for the button to be pressed (or released), returns false if `timeout` passes first
+    fn wait_for(&mut self, pressed: bool, timeout: Option<Duration>) -> Result<bool, String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                return Ok(false);
+            }
+
+            let Some(edge) = self.input.poll(remaining)? else {
+                continue;
+            };
+            let active = matches!(
+                (edge, self.active),
+                (Edge::Rising, Level::High) | (Edge::Falling, Level::Low)
+            );
+            if active == pressed {
+                return Ok(true);
+            }
+        }
+    }
+}
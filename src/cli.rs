@@ -0,0 +1,220 @@
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+/// Streams jobs to a Grbl controller, and runs one-off operations against the machine
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Override a config value for this run, e.g. `--set serial.port=/dev/ttyUSB1`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override, global = true)]
+    pub overrides: Vec<(String, String)>,
+
+    /// Use this machine profile, as if the config set `machine`
+    #[arg(long, value_name = "PROFILE", global = true)]
+    pub machine: Option<String>,
+
+    /// Config file to load instead of ~/.config/cnc-ctrl/config.yml, e.g. a machine's settings
+    /// without a job for one-off commands
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Running the job is the default, so its job file and options work without `run`
+    #[command(flatten)]
+    pub run: RunJob,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the job (the default)
+    Run(RunJob),
+    /// Check the job without a machine, or a G-code file in Grbl's check mode, then exit
+    #[command(alias = "validate")]
+    Check {
+        /// G-code file to check on the machine instead of the job
+        file: Option<String>,
+    },
+    /// Send lines to the controller typed on the terminal and print its responses
+    Console,
+    /// Print the machine's status and exit
+    Status,
+    /// Log the machine's status and messages as they change, without sending it anything else
+    Monitor {
+        /// How often to poll the status, in ms
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        interval_ms: u64,
+    },
+    /// Jog the machine and exit
+    Jog(JogArgs),
+    /// Home the machine and exit
+    Home,
+    /// List the serial ports, to find the one to set as `serial.port`
+    Ports {
+        /// Open each port to see whether Grbl answers
+        #[arg(long)]
+        probe: bool,
+
+        /// Baud rate to open the ports at when probing
+        #[arg(long, default_value_t = 115200)]
+        baudrate: u32,
+    },
+    /// Write an example config to ~/.config/cnc-ctrl
+    Init(InitArgs),
+    /// Collect the config, logs and controller info into an archive for a bug report
+    Report {
+        /// Where to write the archive (default: cnc-ctrl-report-<timestamp>.tar.gz)
+        output: Option<String>,
+    },
+    /// Measure the latency and throughput of the connection
+    Latency {
+        /// Number of queries to send (default: 100)
+        samples: Option<usize>,
+    },
+    /// Flash firmware to the controller
+    Flash {
+        /// Firmware to flash, a .hex or .bin file
+        firmware: String,
+    },
+    /// Run a maintenance job shipped with cnc-ctrl instead of the config's job
+    Maintenance {
+        /// Job to run: tram, backlash or calibrate (lists them if omitted)
+        name: Option<String>,
+    },
+}
+
+#[derive(Args, Default)]
+pub struct RunJob {
+    /// Job file to run, instead of the config file
+    #[arg(value_name = "JOB")]
+    pub job: Option<String>,
+
+    #[command(flatten)]
+    pub args: RunArgs,
+}
+
+#[derive(Args, Default)]
+pub struct RunArgs {
+    /// Show a terminal UI instead of the scrolling log
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Only run these steps
+    #[arg(long, value_name = "STEPS", value_delimiter = ',')]
+    pub only: Vec<String>,
+
+    /// Run every step except these
+    #[arg(long, value_name = "STEPS", value_delimiter = ',')]
+    pub skip: Vec<String>,
+
+    /// Start the first sequence at this step
+    #[arg(long, value_name = "STEP")]
+    pub from: Option<String>,
+}
+
+#[derive(Args)]
+#[command(group = clap::ArgGroup::new("axes").required(true).multiple(true))]
+pub struct JogArgs {
+    /// Distance to move on X, or the position with --absolute
+    #[arg(short, allow_negative_numbers = true, group = "axes")]
+    pub x: Option<f64>,
+
+    /// Distance to move on Y, or the position with --absolute
+    #[arg(short, allow_negative_numbers = true, group = "axes")]
+    pub y: Option<f64>,
+
+    /// Distance to move on Z, or the position with --absolute
+    #[arg(short, allow_negative_numbers = true, group = "axes")]
+    pub z: Option<f64>,
+
+    /// Feed rate per minute
+    #[arg(short, long)]
+    pub feed: f64,
+
+    /// Move to a position in work coordinates instead of by a distance
+    #[arg(long)]
+    pub absolute: bool,
+
+    /// Distances and the feed rate are in inches instead of mm
+    #[arg(long)]
+    pub inch: bool,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// Serial device of the controller (default: /dev/ttyUSB0)
+    #[arg(long, value_name = "PORT")]
+    pub serial: Option<String>,
+
+    /// Only serial connections are supported, so this is rejected
+    #[arg(long, value_name = "ADDRESS", hide = true)]
+    pub tcp: Option<String>,
+
+    /// Log probe contacts from the example G-code step to a CSV file
+    #[arg(long)]
+    pub with_probe_output: bool,
+
+    /// Overwrite existing files
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Parses the arguments into the config overrides, the config file to load if not the default,
+/// and the command, which is `run` without one
+pub fn parse() -> (Vec<(String, String)>, Option<String>, Command) {
+    let cli = Cli::parse();
+
+    let mut overrides = cli.overrides;
+    if let Some(machine) = cli.machine {
+        overrides.push(("machine".to_string(), machine));
+    }
+
+    let run = cli.run;
+    let mut config = cli.config;
+    let command = match cli.command {
+        None => Command::Run(run),
+        Some(_) if run.job.is_some() => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "a job file can't be given before a command",
+            )
+            .exit(),
+        Some(_)
+            if run.args.tui
+                || !run.args.only.is_empty()
+                || !run.args.skip.is_empty()
+                || run.args.from.is_some() =>
+        {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--tui, --only, --skip and --from only apply to `run`",
+                )
+                .exit()
+        }
+        Some(command) => command,
+    };
+    let command = match command {
+        Command::Run(RunJob { job: Some(_), .. }) if config.is_some() => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "a job file and --config can't be given together",
+            )
+            .exit(),
+        Command::Run(RunJob { job, args }) => {
+            config = config.or(job);
+            Command::Run(RunJob { job: None, args })
+        }
+        command => command,
+    };
+
+    (overrides, config, command)
+}
+
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid override '{}', expected key=value", value))?;
+    Ok((key.trim().to_string(), value.to_string()))
+}
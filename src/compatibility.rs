@@ -0,0 +1,154 @@
+use crate::config::{Axis, JobRequirements, MachineConfig};
+use crate::display;
+
+impl JobRequirements {
+    /// What the machine lacks to run the job, each with the requirement's key under
+    /// `job.requirements`. A machine without a `machine` section declares nothing
+    pub fn mismatches(&self, machine: Option<&MachineConfig>) -> Vec<(&'static str, String)> {
+        let mut mismatches = Vec::new();
+
+        for (key, axis, travel) in [
+            ("x", Axis::X, self.x),
+            ("y", Axis::Y, self.y),
+            ("z", Axis::Z, self.z),
+        ] {
+            let Some(travel) = travel.map(|travel| self.units.to_mm(travel)) else {
+                continue;
+            };
+            match machine.and_then(|machine| Some((machine.units, machine.axis(axis)?))) {
+                Some((units, limits)) if units.to_mm(limits.max - limits.min) >= travel => {}
+                Some((units, limits)) => mismatches.push((
+                    key,
+                    format!(
+                        "needs {} of {} travel, the machine has {}",
+                        display::length(travel),
+                        axis,
+                        display::length(units.to_mm(limits.max - limits.min))
+                    ),
+                )),
+                None => mismatches.push((
+                    key,
+                    format!(
+                        "needs {} of {} travel, the machine doesn't declare its travel (machine.{})",
+                        display::length(travel),
+                        axis,
+                        key
+                    ),
+                )),
+            }
+        }
+
+        if let Some(spindle) = &self.spindle {
+            match machine.and_then(|machine| machine.spindle.as_ref()) {
+                Some(range)
+                    if range.min_rpm <= spindle.min_rpm && spindle.max_rpm <= range.max_rpm => {}
+                Some(range) => mismatches.push((
+                    "spindle",
+                    format!(
+                        "needs {} to {} RPM, the machine's spindle runs {} to {} RPM",
+                        spindle.min_rpm, spindle.max_rpm, range.min_rpm, range.max_rpm
+                    ),
+                )),
+                None => mismatches.push((
+                    "spindle",
+                    "needs a spindle, the machine doesn't declare one (machine.spindle)"
+                        .to_string(),
+                )),
+            }
+        }
+
+        if self.probe && !machine.is_some_and(|machine| machine.probe) {
+            mismatches.push((
+                "probe",
+                "needs a probe, the machine doesn't declare one (machine.probe)".to_string(),
+            ));
+        }
+        if self.laser && !machine.is_some_and(|machine| machine.laser) {
+            mismatches.push((
+                "laser",
+                "needs a laser, the machine doesn't declare one (machine.laser)".to_string(),
+            ));
+        }
+
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AxisLimits, SpindleRange, Units};
+
+    fn machine() -> MachineConfig {
+        MachineConfig {
+            units: Units::Mm,
+            x: Some(AxisLimits {
+                min: -800.0,
+                max: 0.0,
+                max_feed: None,
+            }),
+            y: None,
+            z: None,
+            spindle: Some(SpindleRange {
+                min_rpm: 10000.0,
+                max_rpm: 24000.0,
+            }),
+            probe: true,
+            laser: false,
+        }
+    }
+
+    fn requirements() -> JobRequirements {
+        JobRequirements {
+            units: Units::Mm,
+            x: None,
+            y: None,
+            z: None,
+            spindle: None,
+            probe: false,
+            laser: false,
+        }
+    }
+
+    #[test]
+    fn capable_machines_have_no_mismatches() {
+        let requirements = JobRequirements {
+            units: Units::Inch,
+            x: Some(30.0),
+            spindle: Some(SpindleRange {
+                min_rpm: 12000.0,
+                max_rpm: 18000.0,
+            }),
+            probe: true,
+            ..requirements()
+        };
+        assert!(requirements.mismatches(Some(&machine())).is_empty());
+        assert!(self::requirements().mismatches(None).is_empty());
+    }
+
+    #[test]
+    fn each_mismatch_is_reported() {
+        let requirements = JobRequirements {
+            x: Some(900.0),
+            y: Some(100.0),
+            spindle: Some(SpindleRange {
+                min_rpm: 8000.0,
+                max_rpm: 18000.0,
+            }),
+            laser: true,
+            ..requirements()
+        };
+        let keys: Vec<&str> = requirements
+            .mismatches(Some(&machine()))
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, ["x", "y", "spindle", "laser"]);
+
+        let probe = JobRequirements {
+            probe: true,
+            ..self::requirements()
+        };
+        assert_eq!(probe.mismatches(None)[0].0, "probe");
+    }
+}
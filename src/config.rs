@@ -1,45 +1,712 @@
+mod include;
+mod profile;
+
+use std::collections::HashMap;
 use std::env;
+use std::fmt::{self, Write};
+use std::sync::OnceLock;
 
-use config::{Config, File};
+use chrono::Local;
+use chrono::format::{Item, StrftimeItems};
+use config::{Config, ConfigError, File, FileFormat, Map, Value, ValueKind};
+use regex::{Captures, Regex};
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+const BUILTIN_VARS: [&str; 5] = ["t", "count", "iteration", "step", "job"];
+
+// Set from `--config` or the job given to `run`
+static CONFIG_PATH: OnceLock<String> = OnceLock::new();
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CncConfig {
     pub logs: LogsConfig,
     pub serial: SerialConfig,
     pub grbl: GrblConfig,
     pub inputs: InputsConfig,
-    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub gpio: GpioConfig,
+    #[serde(default)]
+    pub flash: FlashConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub alarms: AlarmsConfig,
+    #[serde(default)]
+    pub counter: CounterConfig,
+    #[serde(default)]
+    pub requires: Vec<Requirement>,
+    pub machine: Option<MachineConfig>,
+    #[serde(default)]
+    pub interlocks: Vec<InterlockConfig>,
+    pub status: Option<StatusConfig>,
+    pub pendant: Option<PendantConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub api: Option<ApiConfig>,
+    pub notify: Option<NotifyConfig>,
+    #[serde(default)]
+    pub job: JobConfig,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    // Only required to run a job, one-off commands just need the machine's settings
+    #[serde(default)]
+    pub steps: Vec<StepConfig>,
+    // Set from the command line, kept so they can be logged with the run
+    #[serde(skip)]
+    pub overrides: Vec<(String, String)>,
+    // Name of the machine profile the config uses, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LogsConfig {
     pub verbose: bool,
     pub save: bool,
     pub path: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default = "default_progress_interval_s")]
+    pub progress_interval_s: u64,
+    pub operator: Option<OperatorLogConfig>,
+    #[serde(default = "default_repeat_window_ms")]
+    pub repeat_window_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OperatorLogConfig {
+    pub path: String,
+    #[serde(default)]
+    pub level: LogLevel,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SerialConfig {
     pub port: String,
     pub baudrate: u32,
     pub timeout_ms: u64,
+    #[serde(default)]
+    pub connect: ConnectConfig,
+    pub dump_traffic: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectConfig {
+    #[serde(default)]
+    pub max_wait_ms: u64,
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default)]
+    pub wait_for_device: bool,
+    #[serde(default)]
+    pub wait_for_banner: bool,
+    #[serde(default = "default_banner_timeout_ms")]
+    pub banner_timeout_ms: u64,
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        Self {
+            max_wait_ms: 0,
+            backoff_ms: default_backoff_ms(),
+            wait_for_device: false,
+            wait_for_banner: false,
+            banner_timeout_ms: default_banner_timeout_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GrblConfig {
     pub rx_buffer_size_bytes: usize,
+    pub response_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub flow_control: FlowControl,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowControl {
+    #[default]
+    CharacterCounting,
+    BufferReport,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    #[serde(default)]
+    pub units: Units,
+    pub x: Option<AxisLimits>,
+    pub y: Option<AxisLimits>,
+    pub z: Option<AxisLimits>,
+    // Capabilities checked against the job's requirements
+    pub spindle: Option<SpindleRange>,
+    #[serde(default)]
+    pub probe: bool,
+    #[serde(default)]
+    pub laser: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpindleRange {
+    pub min_rpm: f64,
+    pub max_rpm: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisLimits {
+    pub min: f64,
+    pub max: f64,
+    pub max_feed: Option<f64>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct InputsConfig {
     pub signal: InputPin,
+    pub pause: Option<InputPin>,
+    pub door: Option<DoorInput>,
+    pub fallback: Option<SignalFallback>,
+}
+
+// Used for the start signal when the GPIO pins can't be set up, e.g. when not running on a Pi
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalFallback {
+    Console,
+    File(String),
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct InputPin {
     pub pin: u8,
     pub debounce_ms: u64,
+    #[serde(default)]
+    pub pull: Pull,
+    #[serde(default = "default_active")]
+    pub active: Level,
+    pub edge: Option<Edge>,
+    // Long and double presses, only for the signal input
+    pub presses: Option<PressConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PressConfig {
+    #[serde(default = "default_long_press_ms")]
+    pub long_ms: u64,
+    #[serde(default = "default_double_press_ms")]
+    pub double_ms: u64,
+}
+
+impl InputPin {
+    /// The configured edge, or the one the input becomes active on
+    pub fn trigger_edge(&self) -> Edge {
+        self.edge.unwrap_or(match self.active {
+            Level::High => Edge::Rising,
+            Level::Low => Edge::Falling,
+        })
+    }
+}
+
+// Both edges are watched, opening sends Grbl's safety door command
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DoorInput {
+    pub pin: u8,
+    pub debounce_ms: u64,
+    #[serde(default)]
+    pub pull: Pull,
+    // Level of the input while the door is open
+    #[serde(default = "default_active")]
+    pub active: Level,
+    #[serde(default)]
+    pub resume_on_close: bool,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Pull {
+    #[default]
+    Up,
+    Down,
+    None,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GpioConfig {
+    #[serde(default)]
+    pub backend: GpioBackend,
+    // GPIO character device used by the gpiod backend, pins are line offsets on this chip
+    #[serde(default = "default_gpio_chip")]
+    pub chip: String,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        Self {
+            backend: GpioBackend::default(),
+            chip: default_gpio_chip(),
+        }
+    }
+}
+
+// The AVR board `flash` writes .hex files to with avrdude
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlashConfig {
+    #[serde(default = "default_flash_part")]
+    pub part: String,
+    #[serde(default = "default_flash_programmer")]
+    pub programmer: String,
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        Self {
+            part: default_flash_part(),
+            programmer: default_flash_programmer(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioBackend {
+    // Raspberry Pi GPIO through /dev/gpiomem, pins are BCM numbers
+    #[default]
+    Rppal,
+    // Any Linux GPIO chip through its character device, as used by libgpiod
+    Gpiod,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownConfig {
+    #[serde(default = "default_decel_ms")]
+    pub decel_ms: u64,
+    #[serde(default = "default_soft_reset")]
+    pub soft_reset: bool,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            decel_ms: default_decel_ms(),
+            soft_reset: default_soft_reset(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CounterConfig {
+    #[serde(default = "default_counter_start")]
+    pub start: u32,
+    #[serde(default = "default_per_sequence")]
+    pub per_sequence: bool,
+}
+
+impl Default for CounterConfig {
+    fn default() -> Self {
+        Self {
+            start: default_counter_start(),
+            per_sequence: default_per_sequence(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmsConfig {
+    #[serde(default)]
+    pub limit: AlarmAction,
+    #[serde(default)]
+    pub reset: AlarmAction,
+    #[serde(default)]
+    pub probe: AlarmAction,
+    #[serde(default)]
+    pub homing: AlarmAction,
+    #[serde(default)]
+    pub other: AlarmAction,
+    #[serde(default)]
+    pub codes: Vec<AlarmPolicy>,
+    // How the operator answers alarms with the `ask` action
+    #[serde(default)]
+    pub confirm: Confirm,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmPolicy {
+    pub code: u8,
+    pub action: AlarmAction,
+    pub retries: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmAction {
+    #[default]
+    Abort,
+    Unlock,
+    Home,
+    // Ask the operator whether to unlock, home or abort
+    Ask,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterlockConfig {
+    pub name: String,
+    pub output: InterlockOutput,
+    #[serde(default)]
+    pub before_ms: u64,
+    #[serde(default)]
+    pub after_ms: u64,
+}
+
+// Publishes the job's state and the machine's status, e.g. for a Home Assistant dashboard
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub address: String,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    #[serde(default = "default_mqtt_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_mqtt_retain")]
+    pub retain: bool,
+}
+
+// Serves the job's status and controls over HTTP, e.g. for a tablet next to the machine
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    #[serde(default = "default_api_address")]
+    pub address: String,
+    // Required by every endpoint but the page when set, and to listen beyond localhost
+    pub token: Option<String>,
+    // How often the WebSocket sends the machine's status
+    #[serde(default = "default_api_stream_interval_ms")]
+    pub stream_interval_ms: u64,
+    // How often the WebSocket sends the whole status rather than the fields that changed
+    #[serde(default = "default_api_snapshot_interval_ms")]
+    pub snapshot_interval_ms: u64,
+    pub uploads: Option<UploadsConfig>,
+}
+
+// Accepts files uploaded in chunks, e.g. programs for gcode steps to read by path
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadsConfig {
+    pub path: String,
+    #[serde(default = "default_uploads_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+// Sends a message to each channel when one of the events happens
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    #[serde(default = "default_notify_events")]
+    pub events: Vec<NotifyEvent>,
+    pub channels: Vec<NotifyChannel>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    // The job ended without an error
+    Success,
+    // The job ended with an error
+    Failure,
+    // A step failed with an alarm, whether or not it was recovered from
+    Alarm,
+    // A sequence of steps completed
+    Sequence,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum NotifyChannel {
+    Ntfy {
+        #[serde(default = "default_ntfy_server")]
+        server: String,
+        topic: String,
+        token: Option<String>,
+    },
+    Telegram {
+        bot_token: String,
+        chat_id: String,
+    },
+    Email {
+        // SMTP server as host:port
+        server: String,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    // Upgrade to TLS after connecting, usually on port 587
+    #[default]
+    Starttls,
+    // TLS from the start, usually on port 465
+    Tls,
+    // Plain text, e.g. to a relay on the local network
+    None,
+}
+
+// Jogs the machine from a rotary encoder while the job waits for the start signal
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PendantConfig {
+    pub encoder: EncoderConfig,
+    pub axes: Vec<PendantAxis>,
+    pub steps: Vec<PendantStep>,
+    pub feed: f64,
+    #[serde(default)]
+    pub units: Units,
+    // Pull and active level of the axis and step selector inputs
+    #[serde(default)]
+    pub pull: Pull,
+    #[serde(default = "default_active")]
+    pub active: Level,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncoderConfig {
+    pub a: u8,
+    pub b: u8,
+    #[serde(default)]
+    pub pull: Pull,
+    #[serde(default = "default_counts_per_detent")]
+    pub counts_per_detent: u32,
+    // Reverses the direction the encoder jogs in
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PendantAxis {
+    pub axis: Axis,
+    pub pin: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PendantStep {
+    pub distance: f64,
+    pub pin: u8,
+}
+
+// GPIO outputs driven high while the job is in each state, e.g. a tower light or buzzer
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StatusConfig {
+    pub idle: Option<u8>,
+    pub waiting: Option<u8>,
+    pub running: Option<u8>,
+    pub error: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InterlockOutput {
+    Gpio { pin: u8 },
+    Mcode { on: String, off: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "Map<String, Value>")]
+pub struct Requirement {
+    pub check: RequirementCheck,
+    pub max_wait_ms: u64,
+}
+
+impl TryFrom<Map<String, Value>> for Requirement {
+    type Error = ConfigError;
+
+    fn try_from(mut map: Map<String, Value>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            max_wait_ms: take(&mut map, "max_wait_ms")?
+                .unwrap_or_else(default_requirement_max_wait_ms),
+            check: RequirementCheck::deserialize(Value::from(map))?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RequirementCheck {
+    Tcp { address: String },
+    File { path: String },
+    Mqtt { address: String },
+    Gpio { pin: u8, level: Level },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Low,
+    High,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DisplayConfig {
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default = "default_display_precision")]
+    pub precision: usize,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            units: Units::default(),
+            precision: default_display_precision(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub repeat: Repeat,
+    pub report: Option<String>,
+    pub requirements: Option<JobRequirements>,
+    #[serde(flatten)]
+    pub hooks: HooksConfig,
+}
+
+// What the job needs of the machine it runs on, checked against the `machine` section
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobRequirements {
+    #[serde(default)]
+    pub units: Units,
+    // Travel needed along each axis
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+    pub spindle: Option<SpindleRange>,
+    #[serde(default)]
+    pub probe: bool,
+    #[serde(default)]
+    pub laser: bool,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Repeat {
+    Once,
+    Count(u32),
+    #[default]
+    Forever,
+    UntilSignal,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "Map<String, Value>")]
+pub struct StepConfig {
+    pub name: Option<String>,
+    pub before_wait: Vec<String>,
+    pub after_wait: Vec<String>,
+    pub step: Step,
+    pub hooks: HooksConfig,
+}
+
+// A flattened tagged enum sees every key of the map, so the common keys are taken out first
+// and the rest must all belong to the step type
+impl TryFrom<Map<String, Value>> for StepConfig {
+    type Error = ConfigError;
+
+    fn try_from(mut map: Map<String, Value>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: take(&mut map, "name")?,
+            before_wait: take(&mut map, "before_wait")?.unwrap_or_default(),
+            after_wait: take(&mut map, "after_wait")?.unwrap_or_default(),
+            hooks: HooksConfig {
+                on_success: take(&mut map, "on_success")?,
+                on_failure: take(&mut map, "on_failure")?,
+            },
+            step: Step::deserialize(Value::from(map))?,
+        })
+    }
+}
+
+fn take<T: DeserializeOwned>(
+    map: &mut Map<String, Value>,
+    key: &str,
+) -> Result<Option<T>, ConfigError> {
+    map.remove(key)
+        .map(|value| {
+            T::deserialize(value)
+                .map_err(|error| ConfigError::Message(format!("{}: {}", key, error)))
+        })
+        .transpose()
 }
 
 #[derive(Debug, Deserialize)]
@@ -49,68 +716,1060 @@ pub enum Step {
     Gcode(GcodeStepConfig),
     #[serde(rename = "bash")]
     Bash(BashStepConfig),
+    #[serde(rename = "calibrate")]
+    Calibrate(CalibrateStepConfig),
+    #[serde(rename = "backlash")]
+    Backlash(BacklashStepConfig),
+    #[serde(rename = "count")]
+    Count(CountStepConfig),
+    #[serde(rename = "jog")]
+    Jog(JogStepConfig),
+    #[serde(rename = "home")]
+    Home(HomeStepConfig),
+    #[serde(rename = "toolchange")]
+    Toolchange(ToolchangeStepConfig),
+    #[serde(rename = "gpio")]
+    Gpio(GpioStepConfig),
+    #[serde(rename = "wait")]
+    Wait(WaitStepConfig),
+    #[serde(rename = "http")]
+    Http(HttpStepConfig),
+    #[serde(rename = "set_offset")]
+    SetOffset(SetOffsetStepConfig),
+    #[serde(rename = "settings")]
+    Settings(SettingsStepConfig),
+    #[serde(rename = "repeat")]
+    Repeat(RepeatStepConfig),
+    #[serde(rename = "join")]
+    Join(JoinStepConfig),
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct GcodeStepConfig {
     pub path: String,
     pub probe: Option<ProbeConfig>,
+    pub responses: Option<ResponsesConfig>,
+    pub reports: Option<ReportsConfig>,
     #[serde(default = "default_wait_for_signal")]
     pub wait_for_signal: bool,
     #[serde(default = "default_check")]
     pub check: bool,
+    pub start_line: Option<usize>,
+    pub checkpoint: Option<String>,
+    #[serde(default)]
+    pub resume: bool,
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+    #[serde(default)]
+    pub qc: Vec<QcConfig>,
+    pub overrides: Option<OverridesConfig>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub dry_run_raise_z: Option<f64>,
+    pub retry: Option<RetryConfig>,
+    pub post: Option<PostPreset>,
+    #[serde(default)]
+    pub home_moves: HomeMovesPolicy,
+    #[serde(default)]
+    pub resume_moves: ResumeMovesConfig,
 }
 
+// How a program is picked up again from a later line
 #[derive(Debug, Deserialize)]
-pub struct ProbeConfig {
-    pub save_path: Option<String>,
+#[serde(deny_unknown_fields)]
+pub struct ResumeMovesConfig {
+    // Machine Z (mm) to retract to first, the top of the machine's Z travel if unset
+    pub safe_z: Option<f64>,
+    #[serde(default = "default_spindle_delay_s")]
+    pub spindle_delay_s: f64,
+}
+
+impl Default for ResumeMovesConfig {
+    fn default() -> Self {
+        Self {
+            safe_z: None,
+            spindle_delay_s: default_spindle_delay_s(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeMovesPolicy {
+    #[default]
+    Allow,
+    Block,
+    Rewrite,
+    Check,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PostPreset {
+    Fusion360,
+    CarbideCreate,
+    Lightburn,
+    Flatcam,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct BashStepConfig {
-    pub command: String,
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    pub attempts: u32,
+    #[serde(default = "default_retry_home")]
+    pub home: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverridesConfig {
+    pub feed: Option<u16>,
+    pub rapid: Option<u16>,
+    pub spindle: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QcConfig {
+    pub line: usize,
+    pub snapshot: Option<String>,
     #[serde(default)]
-    pub wait_for_signal: bool,
+    pub prompt: bool,
 }
 
-fn default_wait_for_signal() -> bool {
-    true
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    #[default]
+    Skip,
+    Abort,
+    Hold,
+    Ask,
 }
 
-fn default_check() -> bool {
-    true
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponsesConfig {
+    pub save_path: String,
 }
 
-impl CncConfig {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = Self::get_config_path()?;
-        let settings = Config::builder()
-            .add_source(File::with_name(&config_path))
-            .build()?;
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportsConfig {
+    pub save_path: String,
+    #[serde(default = "default_report_interval_ms")]
+    pub interval_ms: u64,
+}
 
-        let config: CncConfig = settings.try_deserialize()?;
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeConfig {
+    pub save_path: Option<String>,
+    #[serde(default)]
+    pub format: ProbeFormat,
+    #[serde(default)]
+    pub coordinates: ProbeCoordinates,
+    pub summary_path: Option<String>,
+    #[serde(default = "default_require_contact")]
+    pub require_contact: bool,
+    pub z_min: Option<f64>,
+    pub z_max: Option<f64>,
+    pub max_deviation: Option<f64>,
+    #[serde(default)]
+    pub on_outlier: OutlierPolicy,
+}
 
-        Ok(config)
-    }
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeFormat {
+    // Machine and work position and contact flag per line
+    #[default]
+    Csv,
+    // Every point with whether the probe made contact
+    Json,
+    // Z of each point in rows and columns, for autolevelling
+    Grid,
+    // Point clouds of the contacts, for MeshLab or CloudCompare
+    Ply,
+    Xyz,
+}
 
-    fn get_config_path() -> Result<String, Box<dyn std::error::Error>> {
-        let home_dir = env::home_dir().ok_or("Failed to get home directory")?;
-        let config_path = home_dir.join(".config").join("cnc-ctrl").join("config.yml");
+// Coordinates used by the formats that only have room for one position per point
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeCoordinates {
+    #[default]
+    Machine,
+    Work,
+}
 
-        Ok(config_path.to_string_lossy().to_string())
-    }
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum OutlierPolicy {
+    #[default]
+    Warn,
+    Discard,
+    Abort,
 }
 
-pub fn expand_path(path: &str) -> String {
-    if path.starts_with('~') {
-        if let Some(home_dir) = env::home_dir() {
-            let home_str = home_dir.to_string_lossy();
-            return path.replacen('~', &home_str, 1);
-        }
-    }
-    path.to_string()
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BashStepConfig {
+    pub command: BashCommand,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+    #[serde(default)]
+    pub background: bool,
+    pub capture: Option<String>,
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub cwd: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
 }
 
-pub fn apply_template(text: &str, timestamp: &str) -> String {
-    text.replace("{%t}", timestamp)
+// A string is run by the shell, a list is run directly as the program and its arguments
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum BashCommand {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JoinStepConfig {
+    #[serde(default)]
+    pub kill: bool,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalibrateStepConfig {
+    pub axis: Axis,
+    pub distance: f64,
+    pub feed: f64,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default = "default_wait_for_signal")]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BacklashStepConfig {
+    pub axes: Vec<Axis>,
+    pub distance: f64,
+    pub feed: f64,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default = "default_wait_for_signal")]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CountStepConfig {
+    #[serde(default = "default_count_amount")]
+    pub amount: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JogStepConfig {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+    pub feed: f64,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default = "default_relative")]
+    pub relative: bool,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HomeStepConfig {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+    #[serde(default = "default_home_tolerance")]
+    pub tolerance: f64,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolchangeStepConfig {
+    pub park: PositionConfig,
+    pub probe: Option<ToolProbeConfig>,
+    #[serde(default)]
+    pub confirm: Confirm,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GpioStepConfig {
+    pub pin: u8,
+    pub action: GpioAction,
+    #[serde(default = "default_pulse_ms")]
+    pub pulse_ms: u64,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioAction {
+    Set,
+    Clear,
+    Pulse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaitStepConfig {
+    pub duration_ms: Option<u64>,
+    pub until: Option<WaitCondition>,
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum WaitCondition {
+    Idle,
+    Gpio { pin: u8, level: Level },
+    File { path: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpStepConfig {
+    pub url: String,
+    #[serde(default)]
+    pub method: HttpMethod,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub body: Option<String>,
+    #[serde(default = "default_http_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetOffsetStepConfig {
+    #[serde(default)]
+    pub wcs: Wcs,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+    #[serde(default)]
+    pub from_probe: bool,
+    #[serde(default)]
+    pub units: Units,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+pub enum Wcs {
+    #[default]
+    G54,
+    G55,
+    G56,
+    G57,
+    G58,
+    G59,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsStepConfig {
+    pub path: String,
+    #[serde(default)]
+    pub verify: bool,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepeatStepConfig {
+    pub count: u32,
+    pub steps: Vec<StepConfig>,
+    #[serde(default)]
+    pub wait_for_signal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PositionConfig {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub z: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolProbeConfig {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub depth: f64,
+    pub feed: f64,
+    pub reference: f64,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Confirm {
+    #[default]
+    Signal,
+    Console,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::X => write!(f, "X"),
+            Axis::Y => write!(f, "Y"),
+            Axis::Z => write!(f, "Z"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Mm,
+    #[serde(alias = "in")]
+    Inch,
+}
+
+impl Units {
+    // Lengths and feed rates (per minute) are converted alike
+    pub fn to_mm(self, value: f64) -> f64 {
+        match self {
+            Units::Mm => value,
+            Units::Inch => value * 25.4,
+        }
+    }
+
+    // Converts a value in mm to these units
+    pub fn convert_mm(self, value: f64) -> f64 {
+        match self {
+            Units::Mm => value,
+            Units::Inch => value / 25.4,
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Units::Mm => write!(f, "mm"),
+            Units::Inch => write!(f, "in"),
+        }
+    }
+}
+
+fn default_wait_for_signal() -> bool {
+    true
+}
+
+fn default_check() -> bool {
+    true
+}
+
+fn default_retry_home() -> bool {
+    true
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+fn default_banner_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_requirement_max_wait_ms() -> u64 {
+    30000
+}
+
+fn default_progress_interval_s() -> u64 {
+    10
+}
+
+fn default_repeat_window_ms() -> u64 {
+    10000
+}
+
+fn default_display_precision() -> usize {
+    3
+}
+
+fn default_home_tolerance() -> f64 {
+    0.1
+}
+
+fn default_http_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_pulse_ms() -> u64 {
+    500
+}
+
+fn default_relative() -> bool {
+    true
+}
+
+fn default_gpio_chip() -> String {
+    "/dev/gpiochip0".to_string()
+}
+
+fn default_spindle_delay_s() -> f64 {
+    3.0
+}
+
+fn default_flash_part() -> String {
+    "atmega328p".to_string()
+}
+
+fn default_flash_programmer() -> String {
+    "arduino".to_string()
+}
+
+fn default_counts_per_detent() -> u32 {
+    4
+}
+
+fn default_long_press_ms() -> u64 {
+    1500
+}
+
+fn default_double_press_ms() -> u64 {
+    400
+}
+
+fn default_mqtt_client_id() -> String {
+    "cnc-ctrl".to_string()
+}
+
+fn default_mqtt_topic() -> String {
+    "cnc-ctrl".to_string()
+}
+
+fn default_mqtt_interval_ms() -> u64 {
+    5000
+}
+
+fn default_mqtt_retain() -> bool {
+    true
+}
+
+fn default_api_address() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
+fn default_api_stream_interval_ms() -> u64 {
+    500
+}
+
+fn default_api_snapshot_interval_ms() -> u64 {
+    10000
+}
+
+fn default_uploads_max_size_mb() -> u64 {
+    1024
+}
+
+fn default_notify_events() -> Vec<NotifyEvent> {
+    vec![NotifyEvent::Failure, NotifyEvent::Alarm]
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+fn default_report_interval_ms() -> u64 {
+    250
+}
+
+fn default_require_contact() -> bool {
+    true
+}
+
+fn default_active() -> Level {
+    Level::High
+}
+
+fn default_counter_start() -> u32 {
+    1
+}
+
+fn default_per_sequence() -> bool {
+    true
+}
+
+fn default_count_amount() -> u32 {
+    1
+}
+
+fn default_decel_ms() -> u64 {
+    2000
+}
+
+fn default_soft_reset() -> bool {
+    true
+}
+
+impl CncConfig {
+    /// Loads the config files, then applies `overrides` (key, value) on top. A `job` (YAML) takes
+    /// the place of the files' job and steps, keeping the machine's settings
+    pub fn load(
+        overrides: &[(String, String)],
+        job: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let files = Self::files()?;
+
+        // Overrides are given as strings, which serde converts for most options. Options of steps
+        // and other tagged types aren't converted, so an override a string is rejected for is
+        // typed and the config loaded again
+        let mut typed = vec![false; overrides.len()];
+        let (root, mut config, profile) = loop {
+            let builder = files.iter().fold(Config::builder(), |builder, file| {
+                builder.add_source(File::with_name(file))
+            });
+            // Sources are merged key by key, so the config's job is taken out rather than merged
+            let builder = match job {
+                Some(job) => {
+                    let mut base: Map<String, Value> = builder.build()?.try_deserialize()?;
+                    base.remove("job");
+                    base.remove("steps");
+                    base.into_iter()
+                        .try_fold(Config::builder(), |builder, (key, value)| {
+                            builder.set_default(key, value)
+                        })?
+                        .add_source(File::from_str(job, FileFormat::Yaml))
+                }
+                None => builder,
+            };
+            let settings = overrides
+                .iter()
+                .zip(&typed)
+                .try_fold(builder, |builder, ((key, value), &typed)| {
+                    let value = match typed {
+                        true => override_value(value),
+                        false => ValueKind::String(value.clone()),
+                    };
+                    builder.set_override(override_path(key), value)
+                })?
+                .build()?;
+
+            let mut root: Map<String, Value> = settings.try_deserialize()?;
+            root.remove("include");
+            let profile = profile::resolve_machine(&mut root)?;
+            include::resolve_library(&mut root)?;
+
+            let root = Value::from(root);
+            match CncConfig::deserialize(root.clone()) {
+                Ok(config) => break (root, config, profile),
+                Err(error) => match rejected_override(&error, overrides, &typed) {
+                    Some(index) => typed[index] = true,
+                    None => return Err(locate_unknown_key(&root, error)),
+                },
+            }
+        };
+        config.overrides = overrides.to_vec();
+        config.profile = profile;
+
+        // The merged values rather than the files, so comments are left out and overrides are in
+        let mut strings = Vec::new();
+        collect_strings(&root, &mut strings);
+        config.check_templates(&strings)?;
+
+        Ok(config)
+    }
+
+    /// The config file and the files it includes, in the order they are merged
+    pub fn files() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        include::files(&Self::get_config_path()?)
+    }
+
+    // Placeholders are only replaced when a step runs, so catch typos before the job starts
+    fn check_templates(&self, strings: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut captures = Vec::new();
+        collect_captures(&self.steps, &mut captures);
+
+        for name in captures.iter().chain(self.vars.keys()) {
+            if BUILTIN_VARS.contains(&name.as_str()) {
+                return Err(format!("'{}' is a built-in template variable", name).into());
+            }
+        }
+
+        let placeholder = Regex::new(r"\{%([^{}]+)\}").unwrap();
+        for found in strings
+            .iter()
+            .flat_map(|text| placeholder.captures_iter(text))
+        {
+            let name = &found[1];
+
+            let known = if let Some(format) = name.strip_prefix("date:") {
+                !StrftimeItems::new(format).any(|item| item == Item::Error)
+            } else if name.starts_with("env:") {
+                true
+            } else if name == "job" {
+                if self.job.name.is_none() {
+                    return Err(format!("'{}' is used, but job.name isn't set", &found[0]).into());
+                }
+                true
+            } else {
+                BUILTIN_VARS.contains(&name)
+                    || self.vars.contains_key(name)
+                    || captures.iter().any(|capture| capture == name)
+            };
+
+            if !known {
+                return Err(format!("Unknown template variable '{}'", &found[0]).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the config from `path` instead of the default, must be called before `load`
+    pub fn set_path(path: &str) {
+        let _ = CONFIG_PATH.set(expand_path(path));
+    }
+
+    pub fn get_config_path() -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(path) = CONFIG_PATH.get() {
+            return Ok(path.clone());
+        }
+
+        let home_dir = env::home_dir().ok_or("Failed to get home directory")?;
+        let config_path = home_dir.join(".config").join("cnc-ctrl").join("config.yml");
+
+        Ok(config_path.to_string_lossy().to_string())
+    }
+}
+
+/// Expands a leading `~` and `$VAR`, `${VAR}` or `${VAR:-default}` environment variables
+pub fn expand_path(path: &str) -> String {
+    expand_env(&expand_home(path))
+}
+
+pub fn expand_home(path: &str) -> String {
+    if path.starts_with('~')
+        && let Some(home_dir) = env::home_dir()
+    {
+        let home_str = home_dir.to_string_lossy();
+        return path.replacen('~', &home_str, 1);
+    }
+    path.to_string()
+}
+
+// Unset variables without a default are left as written, so a literal `$` doesn't need escaping
+fn expand_env(text: &str) -> String {
+    let variable =
+        Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}|([A-Za-z_][A-Za-z0-9_]*))")
+            .unwrap();
+
+    variable
+        .replace_all(text, |captures: &Captures| {
+            let name = captures.get(1).or(captures.get(3)).unwrap().as_str();
+            match (env::var(name), captures.get(2)) {
+                (Ok(value), _) => value,
+                (Err(_), Some(default)) => default.as_str().to_string(),
+                (Err(_), None) => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+// Accept `steps.0.check` as well as the config crate's `steps[0].check`
+fn override_path(key: &str) -> String {
+    let mut path = String::new();
+    for segment in key.split('.') {
+        if segment.parse::<usize>().is_ok() {
+            let _ = write!(path, "[{}]", segment);
+        } else {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(segment);
+        }
+    }
+    path
+}
+
+// Every string value in the config, at any depth
+fn collect_strings<'a>(value: &'a Value, strings: &mut Vec<&'a str>) {
+    match &value.kind {
+        ValueKind::String(text) => strings.push(text),
+        ValueKind::Table(table) => {
+            for value in table.values() {
+                collect_strings(value, strings);
+            }
+        }
+        ValueKind::Array(values) => {
+            for value in values {
+                collect_strings(value, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The first override still a string whose value `error` rejects as one
+fn rejected_override(
+    error: &ConfigError,
+    overrides: &[(String, String)],
+    typed: &[bool],
+) -> Option<usize> {
+    let message = error.to_string();
+    (0..overrides.len()).find(|&index| {
+        !typed[index] && message.contains(&format!("invalid type: string {:?}", overrides[index].1))
+    })
+}
+
+// Values are typed like YAML scalars
+fn override_value(value: &str) -> ValueKind {
+    if let Ok(value) = value.parse::<bool>() {
+        ValueKind::Boolean(value)
+    } else if let Ok(value) = value.parse::<i64>() {
+        ValueKind::I64(value)
+    } else if let Ok(value) = value.parse::<f64>() {
+        ValueKind::Float(value)
+    } else {
+        ValueKind::String(value.to_string())
+    }
+}
+
+// Serde doesn't say where an unknown key is, so find it in the raw config
+fn locate_unknown_key(root: &Value, error: ConfigError) -> Box<dyn std::error::Error> {
+    let message = error.to_string();
+    let Some(key) = message
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next())
+    else {
+        return error.into();
+    };
+
+    let mut paths = Vec::new();
+    find_key(root, key, "", &mut paths);
+
+    if paths.is_empty() {
+        return error.into();
+    }
+    paths.sort();
+
+    format!("Unknown key '{}' ({})", paths.join("', '"), message).into()
+}
+
+fn find_key(value: &Value, key: &str, path: &str, paths: &mut Vec<String>) {
+    match &value.kind {
+        ValueKind::Table(table) => {
+            for (name, value) in table {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                if name == key {
+                    paths.push(path.clone());
+                }
+                find_key(value, key, &path, paths);
+            }
+        }
+        ValueKind::Array(array) => {
+            for (index, value) in array.iter().enumerate() {
+                find_key(value, key, &format!("{}[{}]", path, index), paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn collect_captures(steps: &[StepConfig], captures: &mut Vec<String>) {
+    for step in steps {
+        match &step.step {
+            Step::Bash(BashStepConfig {
+                capture: Some(name),
+                ..
+            }) => captures.push(name.clone()),
+            Step::Repeat(repeat) => collect_captures(&repeat.steps, captures),
+            _ => {}
+        }
+    }
+}
+
+pub struct TemplateVars {
+    pub timestamp: String,
+    pub count: u32,
+    /// Iteration of the innermost repeat step, 0 outside of one
+    pub iteration: u32,
+    /// Name of the running step, or its number if it has no name
+    pub step: String,
+    /// Job name, values from `vars` and the output of bash steps with `capture`, by variable name
+    pub captures: HashMap<String, String>,
+}
+
+impl TemplateVars {
+    pub fn new(config: &CncConfig, count: u32) -> Self {
+        let mut captures = config.vars.clone();
+        if let Some(name) = &config.job.name {
+            captures.insert("job".to_string(), name.clone());
+        }
+
+        Self {
+            timestamp: Local::now().format("%Y%m%d_%H%M%S").to_string(),
+            count,
+            iteration: 0,
+            step: String::new(),
+            captures,
+        }
+    }
+}
+
+pub fn apply_template(text: &str, vars: &TemplateVars) -> String {
+    let placeholder = Regex::new(r"\{%([^{}]+)\}").unwrap();
+
+    placeholder
+        .replace_all(text, |found: &Captures| {
+            let name = &found[1];
+            match name {
+                "t" => vars.timestamp.clone(),
+                "count" => vars.count.to_string(),
+                "iteration" => vars.iteration.to_string(),
+                "step" => vars.step.clone(),
+                _ => {
+                    if let Some(format) = name.strip_prefix("date:") {
+                        // Invalid formats are rejected when the config is loaded, but don't panic
+                        let mut date = String::new();
+                        match write!(date, "{}", Local::now().format(format)) {
+                            Ok(()) => date,
+                            Err(_) => found[0].to_string(),
+                        }
+                    } else if let Some(variable) = name.strip_prefix("env:") {
+                        env::var(variable).unwrap_or_default()
+                    } else {
+                        // Captures not set yet are left as they are
+                        vars.captures
+                            .get(name)
+                            .cloned()
+                            .unwrap_or_else(|| found[0].to_string())
+                    }
+                }
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_variables_are_expanded() {
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            env::set_var("CNC_CTRL_TEST_DIR", "/srv/jobs");
+            env::remove_var("CNC_CTRL_TEST_UNSET");
+        }
+
+        assert_eq!(
+            expand_env("$CNC_CTRL_TEST_DIR/a.gcode"),
+            "/srv/jobs/a.gcode"
+        );
+        assert_eq!(expand_env("${CNC_CTRL_TEST_DIR}x"), "/srv/jobsx");
+        assert_eq!(expand_env("${CNC_CTRL_TEST_UNSET:-~/cnc}/a"), "~/cnc/a");
+        assert_eq!(expand_env("${CNC_CTRL_TEST_DIR:-/tmp}"), "/srv/jobs");
+        assert_eq!(
+            expand_env("$CNC_CTRL_TEST_UNSET/a"),
+            "$CNC_CTRL_TEST_UNSET/a"
+        );
+        assert_eq!(expand_env("cost $5 $(date) $$"), "cost $5 $(date) $$");
+    }
+
+    #[test]
+    fn overrides_are_typed_by_their_option() {
+        let dir = env::temp_dir().join(format!("cnc-ctrl-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        std::fs::write(
+            &path,
+            "logs: { verbose: false, save: false, path: logs }\n\
+             serial: { port: /dev/null, baudrate: 115200, timeout_ms: 1000 }\n\
+             grbl: { rx_buffer_size_bytes: 128 }\n\
+             inputs: { signal: { pin: 17, debounce_ms: 50 } }\n\
+             steps:\n  - { type: gcode, name: cut, path: part.gcode, wait_for_signal: false }\n",
+        )
+        .unwrap();
+        CncConfig::set_path(&path.to_string_lossy());
+
+        let set = |pairs: &[(&str, &str)]| {
+            let overrides: Vec<_> = pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            CncConfig::load(&overrides, None).unwrap()
+        };
+
+        let config = set(&[
+            ("job.name", "007"),
+            ("vars.pin", "1e3"),
+            ("steps.0.start_line", "81"),
+            ("serial.baudrate", "9600"),
+        ]);
+        assert_eq!(config.job.name.as_deref(), Some("007"));
+        assert_eq!(config.vars["pin"], "1e3");
+        assert_eq!(config.serial.baudrate, 9600);
+        let Step::Gcode(step) = &config.steps[0].step else {
+            panic!("expected a gcode step");
+        };
+        assert_eq!(step.start_line, Some(81));
+
+        let overrides = [("steps.0.start_line".to_string(), "first".to_string())];
+        assert!(CncConfig::load(&overrides, None).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::Path;
+
+use config::{Config, File, Map, Value, ValueKind};
+use serde::Deserialize;
+
+use super::expand_path;
+
+#[derive(Deserialize)]
+struct Includes {
+    #[serde(default)]
+    include: Vec<String>,
+}
+
+/// The config file and every file it includes, in the order they are merged.
+/// Included files come before the file including them, so the including file takes precedence.
+pub fn files(path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    collect(path, &mut Vec::new(), &mut files)?;
+    Ok(files)
+}
+
+fn collect(
+    path: &str,
+    chain: &mut Vec<String>,
+    files: &mut Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The same file can be reached by different paths, e.g. `./a.yml` and `a.yml` or a symlink.
+    // Paths without the extension are left as they are for `File::with_name` to complete
+    let path = &fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string());
+    if chain.iter().any(|included| included == path) {
+        return Err(format!("Include cycle: {} -> {}", chain.join(" -> "), path).into());
+    }
+
+    let includes: Includes = Config::builder()
+        .add_source(File::with_name(path))
+        .build()
+        .and_then(Config::try_deserialize)
+        .map_err(|error| format!("Failed to read '{}': {}", path, error))?;
+
+    // Includes are relative to the file that includes them
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    chain.push(path.to_string());
+    for include in includes.include {
+        let include = directory.join(expand_path(&include));
+        collect(&include.to_string_lossy(), chain, files)?;
+    }
+    chain.pop();
+
+    // A shared fragment can be included by several files, but is only merged once
+    if !files.iter().any(|file| file == path) {
+        files.push(path.to_string());
+    }
+
+    Ok(())
+}
+
+/// Replaces steps with a `use` key by the named step from `library`, with the step's other
+/// keys overriding the library's
+pub fn resolve_library(root: &mut Map<String, Value>) -> Result<(), Box<dyn std::error::Error>> {
+    let library = match root.remove("library") {
+        Some(library) => library.into_table()?,
+        None => Map::new(),
+    };
+
+    if let Some(steps) = root.get_mut("steps") {
+        resolve_steps(steps, "steps", &library)?;
+    }
+
+    Ok(())
+}
+
+fn resolve_steps(
+    steps: &mut Value,
+    key: &str,
+    library: &Map<String, Value>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ValueKind::Array(steps) = &mut steps.kind else {
+        return Ok(());
+    };
+
+    for (index, step) in steps.iter_mut().enumerate() {
+        let key = format!("{}[{}]", key, index);
+        let ValueKind::Table(table) = &mut step.kind else {
+            continue;
+        };
+
+        if let Some(name) = table.remove("use") {
+            let name = name.into_string()?;
+            let mut resolved = library
+                .get(&name)
+                .ok_or(format!("{}.use: '{}' isn't in the library", key, name))?
+                .clone()
+                .into_table()?;
+            resolved.extend(std::mem::take(table));
+            *table = resolved;
+        }
+
+        // Repeat steps can use library steps too
+        if let Some(nested) = table.get_mut("steps") {
+            resolve_steps(nested, &format!("{}.steps", key), library)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> std::path::PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("cnc-ctrl-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn shared_includes_are_merged_once() {
+        let directory = directory("include-shared");
+        fs::write(directory.join("shared.yml"), "grbl: {}\n").unwrap();
+        fs::write(directory.join("machine.yml"), "include: [./shared.yml]\n").unwrap();
+        fs::write(
+            directory.join("job.yml"),
+            "include: [machine.yml, shared.yml]\n",
+        )
+        .unwrap();
+
+        let files = files(&directory.join("job.yml").to_string_lossy()).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| Path::new(file).file_name().unwrap().to_string_lossy())
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(names, ["shared.yml", "machine.yml", "job.yml"]);
+    }
+
+    #[test]
+    fn self_includes_are_cycles() {
+        let directory = directory("include-cycle");
+        fs::write(directory.join("job.yml"), "include: [./job.yml]\n").unwrap();
+        fs::write(directory.join("a.yml"), "include: [b.yml]\n").unwrap();
+        fs::write(
+            directory.join("b.yml"),
+            format!(
+                "include: [../{}/a.yml]\n",
+                directory.file_name().unwrap().to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let job = files(&directory.join("job.yml").to_string_lossy());
+        let pair = files(&directory.join("a.yml").to_string_lossy());
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(job.unwrap_err().to_string().starts_with("Include cycle"));
+        assert!(pair.unwrap_err().to_string().starts_with("Include cycle"));
+    }
+
+    #[test]
+    fn library_steps_are_resolved() {
+        let mut root: Map<String, Value> = Config::builder()
+            .add_source(config::File::from_str(
+                "library:\n  cut: { type: gcode, path: part.gcode, check: true }\n\
+                 steps:\n  - { use: cut, check: false }\n  \
+                 - { type: repeat, steps: [{ use: cut }] }\n",
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        resolve_library(&mut root).unwrap();
+
+        let steps = root["steps"].clone().into_array().unwrap();
+        let first = steps[0].clone().into_table().unwrap();
+        assert_eq!(first["path"].clone().into_string().unwrap(), "part.gcode");
+        assert!(!first["check"].clone().into_bool().unwrap());
+        let nested = steps[1].clone().into_table().unwrap()["steps"]
+            .clone()
+            .into_array()
+            .unwrap();
+        let nested = nested[0].clone().into_table().unwrap();
+        assert!(nested["check"].clone().into_bool().unwrap());
+        assert!(!root.contains_key("library"));
+
+        let mut unknown: Map<String, Value> = Map::new();
+        unknown.insert(
+            "steps".into(),
+            Value::from(vec![Value::from(Map::from([(
+                "use".to_string(),
+                Value::from("drill"),
+            )]))]),
+        );
+        assert!(resolve_library(&mut unknown).is_err());
+    }
+}
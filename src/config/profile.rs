@@ -0,0 +1,50 @@
+use config::{Map, Value, ValueKind};
+
+/// Merges the machine profile named by `machine` from `machines` into the config,
+/// returning its name. Settings in the config itself take precedence over the profile's.
+pub fn resolve_machine(
+    root: &mut Map<String, Value>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let machines = match root.remove("machines") {
+        Some(machines) => machines.into_table()?,
+        None => Map::new(),
+    };
+
+    // A table is a machine defined in place rather than by name
+    let name = match root.get("machine").map(|machine| &machine.kind) {
+        Some(ValueKind::String(name)) => name.clone(),
+        _ => return Ok(None),
+    };
+    root.remove("machine");
+
+    let profile = machines
+        .get(&name)
+        .ok_or_else(|| {
+            let mut names: Vec<&str> = machines.keys().map(String::as_str).collect();
+            names.sort();
+            format!(
+                "machine: no profile named '{}' (profiles: {})",
+                name,
+                names.join(", ")
+            )
+        })?
+        .clone()
+        .into_table()?;
+
+    let overrides = std::mem::replace(root, profile);
+    merge(root, overrides);
+
+    Ok(Some(name))
+}
+
+// Tables are merged key by key, anything else in `overrides` replaces the value in `base`
+fn merge(base: &mut Map<String, Value>, overrides: Map<String, Value>) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key).map(|base| &mut base.kind), value.kind) {
+            (Some(ValueKind::Table(base)), ValueKind::Table(table)) => merge(base, table),
+            (_, kind) => {
+                base.insert(key, Value::new(None, kind));
+            }
+        }
+    }
+}
@@ -1,23 +1,36 @@
 pub mod command;
 pub mod message;
+mod repeat;
 pub mod serial;
 
-use log::{debug, error};
+use log::{debug, error, info};
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, BufRead, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::Duration;
 use std::{sync::Arc, thread};
 
 use crossbeam::channel;
 
-use command::Command;
-use message::{Message, Push, Response};
+use crate::config::{Axis, GrblConfig};
+
+use command::{Command, Realtime};
+use message::{Message, Push, Report, Response, alarm_description};
+use repeat::RepeatFilter;
+use serial::checked_stream;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ControllerError {
     ParseError { message: String, input: String },
     GcodeError(i32, Response),
     SerialError(String),
+    StoppedError { acknowledged: i32, sent: i32 },
+    TimeoutError { line: i32, timeout: Duration },
+    AlarmError(u8),
+    ProbeError { line: i32, message: String },
 }
 
 impl std::error::Error for ControllerError {}
@@ -34,6 +47,27 @@ impl fmt::Display for ControllerError {
             ControllerError::SerialError(message) => {
                 write!(f, "Serial error: {}", message)
             }
+            ControllerError::StoppedError { acknowledged, sent } => {
+                write!(
+                    f,
+                    "Stopped after {} of {} sent lines were executed",
+                    acknowledged, sent
+                )
+            }
+            ControllerError::TimeoutError { line, timeout } => {
+                write!(
+                    f,
+                    "Line {}: No response within {} ms",
+                    line,
+                    timeout.as_millis()
+                )
+            }
+            ControllerError::AlarmError(code) => {
+                write!(f, "Alarm {}: {}", code, alarm_description(*code))
+            }
+            ControllerError::ProbeError { line, message } => {
+                write!(f, "Line {}: {}", line, message)
+            }
         }
     }
 }
@@ -42,6 +76,19 @@ pub struct Controller {
     pub prio_serial_channel: Option<(channel::Sender<Command>, channel::Receiver<Push>)>,
     pub serial_channel: Option<(channel::Sender<Command>, channel::Receiver<Response>)>,
     pub running: Arc<AtomicBool>,
+    pub paused: Arc<AtomicBool>,
+    pub stopping: Arc<AtomicBool>,
+    /// Set to end the wait for the start signal or a wait step early
+    pub skip_wait: Arc<AtomicBool>,
+    pub healthy: Arc<AtomicBool>,
+    /// Last alarm code reported by Grbl, 0 while no alarm is active
+    pub alarm: Arc<AtomicU8>,
+    /// Machine position of the last successful probe contact
+    pub last_probe: Arc<Mutex<Option<(f64, f64, f64)>>>,
+    /// Number of times each `[MSG:]` message and alarm was received, including repeats not logged
+    pub messages: Arc<Mutex<HashMap<String, u64>>>,
+    /// Last status report received, with the last work offset reported if it had none
+    pub last_report: Arc<Mutex<Option<Report>>>,
 
     serial_handles: Option<(thread::JoinHandle<()>, thread::JoinHandle<()>)>,
 }
@@ -53,10 +100,23 @@ impl Controller {
             serial_channel: None,
             serial_handles: None,
             running: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            skip_wait: Arc::new(AtomicBool::new(false)),
+            healthy: Arc::new(AtomicBool::new(true)),
+            alarm: Arc::new(AtomicU8::new(0)),
+            last_probe: Arc::new(Mutex::new(None)),
+            messages: Arc::new(Mutex::new(HashMap::new())),
+            last_report: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub fn start(&mut self, serial: Box<dyn serialport::SerialPort>, verbose_logging: bool) {
+    pub fn start(
+        &mut self,
+        serial: Box<dyn serialport::SerialPort>,
+        verbose_logging: bool,
+        repeat_window: Duration,
+    ) {
         let mut writer = io::BufWriter::new(serial.try_clone().unwrap());
         let mut reader = io::BufReader::new(serial.try_clone().unwrap());
 
@@ -68,6 +128,10 @@ impl Controller {
 
         let send_running = self.running.clone();
         let recv_running = self.running.clone();
+        let recv_alarm = self.alarm.clone();
+        let recv_last_probe = self.last_probe.clone();
+        let recv_last_report = self.last_report.clone();
+        let mut repeats = RepeatFilter::new(repeat_window, self.messages.clone());
 
         self.running.store(true, Ordering::Relaxed);
 
@@ -117,17 +181,54 @@ impl Controller {
                 let _ = reader.read_line(&mut response).or_else(log_err);
                 let message = Message::from(response.trim());
 
-                if verbose_logging {
+                repeats.flush_expired();
+
+                // Controllers can send the same message hundreds of times a second, so don't log repeats
+                let logged = match &message {
+                    Message::Push(Push::Alarm(code)) => repeats.check(&format!("ALARM:{}", code)),
+                    Message::Unknown(text) if text.starts_with("[MSG:") => repeats.check(text),
+                    _ => true,
+                };
+
+                if verbose_logging && logged {
                     debug!("Serial (RECV) < {}", message);
                 }
 
                 match message {
+                    Message::Push(Push::Alarm(code)) => {
+                        if logged {
+                            error!("Grbl alarm {}: {}", code, alarm_description(code));
+                        }
+                        recv_alarm.store(code, Ordering::Relaxed);
+                        let _ = prio_recv_tx.try_send(Push::Alarm(code));
+                    }
                     Message::Push(push) => {
+                        if let Push::Report(report) = &push
+                            && let Ok(mut last_report) = recv_last_report.lock()
+                        {
+                            let mut report = report.clone();
+                            if report.wco.is_none() {
+                                report.wco = last_report.as_ref().and_then(|last| last.wco);
+                            }
+                            *last_report = Some(report);
+                        }
                         let _ = prio_recv_tx.try_send(push);
                     }
                     Message::Response(res) => {
+                        if let Response::Probe {
+                            coords,
+                            success: true,
+                            ..
+                        } = res
+                            && let Ok(mut last_probe) = recv_last_probe.lock()
+                        {
+                            *last_probe = Some(coords);
+                        }
                         recv_tx.send(res).unwrap();
                     }
+                    Message::Unknown(text) if logged && text.starts_with("[MSG:") => {
+                        info!("Grbl: {}", text);
+                    }
                     _ => continue,
                 }
             }
@@ -138,6 +239,114 @@ impl Controller {
         self.serial_handles = Some((send_handle, recv_handle));
     }
 
+    pub fn realtime(&self, command: Realtime) -> Result<(), ControllerError> {
+        let Some((prio_serial_tx, _)) = &self.prio_serial_channel else {
+            return Err(ControllerError::SerialError(
+                "Controller not started".to_string(),
+            ));
+        };
+
+        prio_serial_tx.send(command.into()).map_err(|error| {
+            ControllerError::SerialError(format!("Failed to send {:?}: {}", command, error))
+        })
+    }
+
+    pub fn set_feed_override(&self, percent: u16) -> Result<(), ControllerError> {
+        Realtime::feed_override(percent)
+            .into_iter()
+            .try_for_each(|command| self.realtime(command))
+    }
+
+    pub fn set_spindle_override(&self, percent: u16) -> Result<(), ControllerError> {
+        Realtime::spindle_override(percent)
+            .into_iter()
+            .try_for_each(|command| self.realtime(command))
+    }
+
+    pub fn set_rapid_override(&self, percent: u16) -> Result<(), ControllerError> {
+        let command = Realtime::rapid_override(percent).ok_or_else(|| {
+            ControllerError::SerialError(format!(
+                "Invalid rapid override {}%, must be 100, 50 or 25",
+                percent
+            ))
+        })?;
+
+        self.realtime(command)
+    }
+
+    pub fn jog(
+        &self,
+        moves: &[(Axis, f64)],
+        feed: f64,
+        relative: bool,
+        grbl: &GrblConfig,
+    ) -> Result<(), ControllerError> {
+        let distance_mode = if relative { "G91" } else { "G90" };
+        let targets = moves
+            .iter()
+            .map(|(axis, distance)| format!("{}{}", axis, distance))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let command = format!("$J={} G21 {} F{}", distance_mode, targets, feed);
+
+        checked_stream(self, vec![&command], grbl).map(|_| ())
+    }
+
+    pub fn cancel_jog(&self) -> Result<(), ControllerError> {
+        self.realtime(Realtime::JogCancel)
+    }
+
+    pub fn check_alarm(&self) -> Result<(), ControllerError> {
+        match self.alarm.load(Ordering::Relaxed) {
+            0 => Ok(()),
+            code => Err(ControllerError::AlarmError(code)),
+        }
+    }
+
+    /// Whether the machine is held, by us or by Grbl (e.g. the safety door), so it won't
+    /// acknowledge lines until cycle start
+    pub fn held(&self) -> bool {
+        if self.paused.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let last_report = match self.last_report.lock() {
+            Ok(report) => report,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        last_report
+            .as_ref()
+            .is_some_and(|report| matches!(report.state().split(':').next(), Some("Hold" | "Door")))
+    }
+
+    /// Soft resets Grbl, which empties its buffers, so responses to lines sent before can no
+    /// longer arrive and the connection is trusted again after a response timeout
+    pub fn soft_reset(&self) -> Result<(), ControllerError> {
+        self.realtime(Realtime::SoftReset)?;
+        thread::sleep(RESET_DELAY);
+        self.flush_responses();
+        self.healthy.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn flush_responses(&self) {
+        if let Some((_, serial_rx)) = &self.serial_channel {
+            while serial_rx.try_recv().is_ok() {}
+        }
+    }
+
+    /// Reads the machine's status from another thread, e.g. to publish it
+    pub fn monitor(&self) -> Monitor {
+        Monitor {
+            poll: self
+                .prio_serial_channel
+                .as_ref()
+                .map(|(prio_serial_tx, _)| prio_serial_tx.clone()),
+            last_report: self.last_report.clone(),
+            paused: self.paused.clone(),
+        }
+    }
+
     pub fn stop(&mut self) {
         if let Some((send_handle, recv_handle)) = self.serial_handles.take() {
             self.running.store(false, Ordering::Relaxed);
@@ -151,6 +360,39 @@ impl Controller {
     }
 }
 
+// Time for Grbl to restart after a soft reset, answering nothing until it has
+const RESET_DELAY: Duration = Duration::from_millis(500);
+// Time for a polled status report to arrive
+const MONITOR_REPORT_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+pub struct Monitor {
+    poll: Option<channel::Sender<Command>>,
+    last_report: Arc<Mutex<Option<Report>>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl Monitor {
+    /// Polls a status report and returns the latest one received. Pushed reports are left to
+    /// whoever is waiting for them, the report is read from the controller's last report
+    pub fn report(&self) -> Option<Report> {
+        if let Some(poll) = &self.poll
+            && poll.send(Realtime::StatusReport.into()).is_ok()
+        {
+            thread::sleep(MONITOR_REPORT_WAIT);
+        }
+
+        match self.last_report.lock() {
+            Ok(report) => report.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
 impl Drop for Controller {
     fn drop(&mut self) {
         if self.running.load(Ordering::Relaxed) {
@@ -13,3 +13,101 @@ impl fmt::Display for Command {
         }
     }
 }
+
+// The Grbl 1.1 realtime commands cnc-ctrl issues
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum Realtime {
+    SoftReset = 0x18,
+    StatusReport = b'?',
+    CycleStart = b'~',
+    FeedHold = b'!',
+    SafetyDoor = 0x84,
+    JogCancel = 0x85,
+    FeedOverrideReset = 0x90,
+    FeedOverrideCoarsePlus = 0x91,
+    FeedOverrideCoarseMinus = 0x92,
+    FeedOverrideFinePlus = 0x93,
+    FeedOverrideFineMinus = 0x94,
+    RapidOverrideReset = 0x95,
+    RapidOverrideMedium = 0x96,
+    RapidOverrideLow = 0x97,
+    SpindleOverrideReset = 0x99,
+    SpindleOverrideCoarsePlus = 0x9A,
+    SpindleOverrideCoarseMinus = 0x9B,
+    SpindleOverrideFinePlus = 0x9C,
+    SpindleOverrideFineMinus = 0x9D,
+    ToggleSpindleStop = 0x9E,
+    ToggleFloodCoolant = 0xA0,
+    ToggleMistCoolant = 0xA1,
+}
+
+impl Realtime {
+    // Overrides can only be stepped, so reset to 100% then step by 10% and 1% to reach the target
+    fn override_sequence(
+        percent: u16,
+        reset: Realtime,
+        coarse: (Realtime, Realtime),
+        fine: (Realtime, Realtime),
+    ) -> Vec<Realtime> {
+        let delta = percent as i32 - 100;
+        let (coarse_step, fine_step) = if delta >= 0 {
+            (coarse.0, fine.0)
+        } else {
+            (coarse.1, fine.1)
+        };
+
+        let mut sequence = vec![reset];
+        sequence.extend(std::iter::repeat_n(
+            coarse_step,
+            (delta.abs() / 10) as usize,
+        ));
+        sequence.extend(std::iter::repeat_n(fine_step, (delta.abs() % 10) as usize));
+        sequence
+    }
+
+    pub fn feed_override(percent: u16) -> Vec<Realtime> {
+        Self::override_sequence(
+            percent.clamp(10, 200),
+            Realtime::FeedOverrideReset,
+            (
+                Realtime::FeedOverrideCoarsePlus,
+                Realtime::FeedOverrideCoarseMinus,
+            ),
+            (
+                Realtime::FeedOverrideFinePlus,
+                Realtime::FeedOverrideFineMinus,
+            ),
+        )
+    }
+
+    pub fn spindle_override(percent: u16) -> Vec<Realtime> {
+        Self::override_sequence(
+            percent.clamp(10, 200),
+            Realtime::SpindleOverrideReset,
+            (
+                Realtime::SpindleOverrideCoarsePlus,
+                Realtime::SpindleOverrideCoarseMinus,
+            ),
+            (
+                Realtime::SpindleOverrideFinePlus,
+                Realtime::SpindleOverrideFineMinus,
+            ),
+        )
+    }
+
+    pub fn rapid_override(percent: u16) -> Option<Realtime> {
+        match percent {
+            100 => Some(Realtime::RapidOverrideReset),
+            50 => Some(Realtime::RapidOverrideMedium),
+            25 => Some(Realtime::RapidOverrideLow),
+            _ => None,
+        }
+    }
+}
+
+impl From<Realtime> for Command {
+    fn from(value: Realtime) -> Self {
+        Command::Realtime(value as u8)
+    }
+}
@@ -39,7 +39,23 @@ pub enum Response {
     Probe {
         raw: String,
         coords: (f64, f64, f64),
+        success: bool,
     },
+    Setting {
+        id: u16,
+        value: String,
+    },
+    // A startup block from `$N`: $N0=G54
+    StartupLine {
+        index: u8,
+        line: String,
+    },
+    Offset {
+        raw: String,
+        name: String,
+        coords: (f64, f64, f64),
+    },
+    Build(String),
 }
 
 impl fmt::Display for Response {
@@ -48,6 +64,10 @@ impl fmt::Display for Response {
             Response::Ok => write!(f, "ok"),
             Response::Error(code) => write!(f, "error:{}", code),
             Response::Probe { raw, .. } => write!(f, "{}", raw),
+            Response::Setting { id, value } => write!(f, "${}={}", id, value),
+            Response::StartupLine { index, line } => write!(f, "$N{}={}", index, line),
+            Response::Offset { raw, .. } => write!(f, "{}", raw),
+            Response::Build(raw) => write!(f, "{}", raw),
         }
     }
 }
@@ -65,7 +85,8 @@ impl TryFrom<&str> for Response {
             })?;
             Ok(Response::Error(error_code))
         } else if value.starts_with("[PRB:") {
-            let regex = Regex::new(r"^\[PRB:([+-]?\d+\.\d+),([+-]?\d+\.\d+),([+-]?\d+\.\d+),([+-]?\d+\.\d+),([+-]?\d+\.\d+):([01])\]$").unwrap();
+            // Grbl reports every axis it was built with, only X, Y and Z are kept
+            let regex = Regex::new(r"^\[PRB:([+-]?\d+\.\d+),([+-]?\d+\.\d+),([+-]?\d+\.\d+)(?:,[+-]?\d+\.\d+)*:([01])\]$").unwrap();
 
             if let Some(captures) = regex.captures(value) {
                 let x = captures[1]
@@ -90,6 +111,7 @@ impl TryFrom<&str> for Response {
                 Ok(Response::Probe {
                     raw: value.to_string(),
                     coords: (x, y, z),
+                    success: &captures[4] == "1",
                 })
             } else {
                 Err(ControllerError::ParseError {
@@ -97,6 +119,81 @@ impl TryFrom<&str> for Response {
                     input: value.to_string(),
                 })
             }
+        } else if let Some(captures) = Regex::new(
+            r"^\[(G5[4-9]|G28|G30|G92):([+-]?\d+\.\d+),([+-]?\d+\.\d+),([+-]?\d+\.\d+)[^\]]*\]$",
+        )
+        .unwrap()
+        .captures(value)
+        {
+            // Work coordinate offset from `$#`: [G54:0.000,0.000,0.000]
+            let coord = |index: usize| {
+                captures[index]
+                    .parse::<f64>()
+                    .map_err(|_| ControllerError::ParseError {
+                        message: "Invalid offset coordinate".to_string(),
+                        input: value.to_string(),
+                    })
+            };
+
+            Ok(Response::Offset {
+                raw: value.to_string(),
+                name: captures[1].to_string(),
+                coords: (coord(2)?, coord(3)?, coord(4)?),
+            })
+        } else if let Some(captures) =
+            Regex::new(r"^\[TLO:([+-]?\d+\.\d+)(?:,([+-]?\d+\.\d+),([+-]?\d+\.\d+))?\]$")
+                .unwrap()
+                .captures(value)
+        {
+            // Tool length offset from `$#`, along Z: [TLO:0.000], or per axis from grblHAL
+            let coords = captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|coord| coord.as_str().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ControllerError::ParseError {
+                    message: "Invalid tool length offset".to_string(),
+                    input: value.to_string(),
+                })?;
+
+            Ok(Response::Offset {
+                raw: value.to_string(),
+                name: "TLO".to_string(),
+                coords: match coords[..] {
+                    [x, y, z] => (x, y, z),
+                    _ => (0.0, 0.0, coords[0]),
+                },
+            })
+        } else if value.starts_with("[VER:") || value.starts_with("[OPT:") {
+            // Build info from `$I`: [VER:1.1h.20190825:] and [OPT:V,15,128]
+            Ok(Response::Build(value.to_string()))
+        } else if let Some((index, line)) = value
+            .strip_prefix("$N")
+            .and_then(|line| line.split_once('='))
+        {
+            let index = index.parse().map_err(|_| ControllerError::ParseError {
+                message: "Invalid startup line number".to_string(),
+                input: value.to_string(),
+            })?;
+
+            Ok(Response::StartupLine {
+                index,
+                line: line.to_string(),
+            })
+        } else if let Some((id, setting)) = value
+            .strip_prefix('$')
+            .and_then(|setting| setting.split_once('='))
+        {
+            let id = id.parse().map_err(|_| ControllerError::ParseError {
+                message: "Invalid setting number".to_string(),
+                input: value.to_string(),
+            })?;
+
+            Ok(Response::Setting {
+                id,
+                value: setting.to_string(),
+            })
         } else {
             Err(ControllerError::ParseError {
                 message: "Not a valid response".to_string(),
@@ -108,12 +205,14 @@ impl TryFrom<&str> for Response {
 
 pub enum Push {
     Report(Report),
+    Alarm(u8),
 }
 
 impl fmt::Display for Push {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Push::Report(report) => write!(f, "{}", report.raw),
+            Push::Alarm(code) => write!(f, "ALARM:{}", code),
         }
     }
 }
@@ -122,20 +221,95 @@ impl TryFrom<&str> for Push {
     type Error = ControllerError;
 
     fn try_from(value: &str) -> Result<Self, ControllerError> {
+        if let Some(code) = value.strip_prefix("ALARM:") {
+            let alarm_code = code.parse().map_err(|_| ControllerError::ParseError {
+                message: "Invalid alarm code".to_string(),
+                input: value.to_string(),
+            })?;
+            return Ok(Push::Alarm(alarm_code));
+        }
+
         let report = Report::try_from(value)?;
         Ok(Push::Report(report))
     }
 }
 
+pub fn alarm_description(code: u8) -> &'static str {
+    match code {
+        1 => "Hard limit triggered",
+        2 => "Soft limit exceeded",
+        3 => "Reset while in motion",
+        4 => "Probe not in expected initial state",
+        5 => "Probe failed to make contact",
+        6 => "Homing reset",
+        7 => "Safety door opened during homing",
+        8 => "Homing failed to pull off switch",
+        9 => "Homing failed to find switch",
+        _ => "Unknown alarm",
+    }
+}
+
+pub fn error_description(code: u8) -> &'static str {
+    match code {
+        1 => "Expected a command letter",
+        2 => "Bad number format",
+        3 => "Invalid '$' statement",
+        4 => "Negative value",
+        5 => "Homing is disabled",
+        6 => "Step pulse must be longer than 3 microseconds",
+        7 => "EEPROM read failed, settings restored to defaults",
+        8 => "'$' command only valid when idle",
+        9 => "G-code locked out during alarm or jog",
+        10 => "Soft limits need homing enabled",
+        11 => "Line too long",
+        12 => "Step rate too high",
+        13 => "Safety door opened",
+        14 => "Line too long for EEPROM",
+        15 => "Jog target exceeds machine travel",
+        16 => "Invalid jog command",
+        17 => "Laser mode needs PWM output",
+        20 => "Unsupported G-code command",
+        21 => "Conflicting commands from the same modal group",
+        22 => "Feed rate not set",
+        23 => "Command needs an integer value",
+        24 => "More than one command using axis words",
+        25 => "Repeated word",
+        26 => "Command needs axis words",
+        27 => "Line number out of range",
+        28 => "Command is missing a P or L word",
+        29 => "Unsupported work coordinate system",
+        30 => "G53 needs G0 or G1 motion",
+        31 => "Axis words found while motion is cancelled (G80)",
+        32 => "Arc has no axis words in the selected plane",
+        33 => "Invalid motion target",
+        34 => "Invalid arc radius",
+        35 => "Arc needs an offset word in the selected plane",
+        36 => "Unused words in block",
+        37 => "Tool length offset not on the configured axis",
+        38 => "Tool number too high",
+        _ => "Unknown error",
+    }
+}
+
+#[derive(Clone)]
 pub struct Report {
     pub raw: String,
     pub status: Option<Status>,
     pub mpos: Option<(f32, f32, f32)>,
+    pub wpos: Option<(f32, f32, f32)>,
+    pub wco: Option<(f32, f32, f32)>,
     pub bf: Option<(usize, usize)>,
+    pub feed: Option<f32>,
+    pub spindle: Option<f32>,
+    pub ov: Option<(u8, u8, u8)>,
 }
 
+#[derive(Clone)]
 pub enum Status {
     Idle,
+    Run,
+    Hold(u8),
+    Alarm,
     Home,
     Jog,
     Unknown,
@@ -143,8 +317,13 @@ pub enum Status {
 
 impl From<&str> for Status {
     fn from(value: &str) -> Self {
-        match value {
+        let (state, substate) = value.split_once(':').unwrap_or((value, "0"));
+
+        match state {
             "Idle" => Status::Idle,
+            "Run" => Status::Run,
+            "Hold" => Status::Hold(substate.parse().unwrap_or(0)),
+            "Alarm" => Status::Alarm,
             "Home" => Status::Home,
             "Jog" => Status::Jog,
             _ => Status::Unknown,
@@ -152,12 +331,39 @@ impl From<&str> for Status {
     }
 }
 
+impl Report {
+    /// Machine position, from the work position and offset if only that was reported
+    pub fn machine_position(&self) -> Option<(f32, f32, f32)> {
+        self.mpos.or_else(|| {
+            let ((x, y, z), (wx, wy, wz)) = (self.wpos?, self.wco?);
+            Some((x + wx, y + wy, z + wz))
+        })
+    }
+
+    /// Work position, from the machine position and offset if only that was reported
+    pub fn work_position(&self) -> Option<(f32, f32, f32)> {
+        self.wpos.or_else(|| {
+            let ((x, y, z), (wx, wy, wz)) = (self.mpos?, self.wco?);
+            Some((x - wx, y - wy, z - wz))
+        })
+    }
+
+    /// The state as Grbl reported it, e.g. `Hold:0`
+    pub fn state(&self) -> &str {
+        self.raw
+            .trim_start_matches('<')
+            .split(['|', '>'])
+            .next()
+            .unwrap_or_default()
+    }
+}
+
 impl TryFrom<&str> for Report {
     type Error = ControllerError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let regex = Regex::new(r"^<([A-Za-z]+)(\|[^>]*)*>$").unwrap();
-        if !regex.is_match(&value) {
+        let regex = Regex::new(r"^<([A-Za-z]+)(:\d+)?(\|[^>]*)*>$").unwrap();
+        if !regex.is_match(value) {
             return Err(ControllerError::ParseError {
                 message: "Not a valid realtime report".to_string(),
                 input: value.to_string(),
@@ -171,7 +377,12 @@ impl TryFrom<&str> for Report {
             raw: value.to_string(),
             status: Some(Status::from(parts[0])),
             mpos: None,
+            wpos: None,
+            wco: None,
             bf: None,
+            feed: None,
+            spindle: None,
+            ov: None,
         };
 
         for part in &parts[1..] {
@@ -185,6 +396,26 @@ impl TryFrom<&str> for Report {
                         coords[2].parse().unwrap_or(0.0),
                     ));
                 }
+            } else if let Some(pos_str) = part.strip_prefix("WPos:") {
+                // Work position, reported instead of MPos depending on $10: WPos:0.000,0.000,0.000
+                let coords: Vec<&str> = pos_str.split(",").collect();
+                if coords.len() >= 3 {
+                    report.wpos = Some((
+                        coords[0].parse().unwrap_or(0.0),
+                        coords[1].parse().unwrap_or(0.0),
+                        coords[2].parse().unwrap_or(0.0),
+                    ));
+                }
+            } else if let Some(wco_str) = part.strip_prefix("WCO:") {
+                // Work coordinate offset: WCO:0.000,0.000,0.000
+                let coords: Vec<&str> = wco_str.split(",").collect();
+                if coords.len() >= 3 {
+                    report.wco = Some((
+                        coords[0].parse().unwrap_or(0.0),
+                        coords[1].parse().unwrap_or(0.0),
+                        coords[2].parse().unwrap_or(0.0),
+                    ));
+                }
             } else if let Some(buf_str) = part.strip_prefix("Bf:") {
                 // Buffer state: Bf:15,128
                 let buf_parts: Vec<&str> = buf_str.split(",").collect();
@@ -194,9 +425,117 @@ impl TryFrom<&str> for Report {
                         buf_parts[1].parse().unwrap_or(0),
                     ));
                 }
+            } else if let Some(fs_str) = part.strip_prefix("FS:") {
+                // Feed and spindle speed: FS:500,8000
+                let fs_parts: Vec<&str> = fs_str.split(",").collect();
+                report.feed = fs_parts[0].parse().ok();
+                report.spindle = fs_parts.get(1).and_then(|speed| speed.parse().ok());
+            } else if let Some(feed_str) = part.strip_prefix("F:") {
+                // Feed only, without variable spindle: F:500
+                report.feed = feed_str.parse().ok();
+            } else if let Some(ov_str) = part.strip_prefix("Ov:") {
+                // Feed, rapid and spindle overrides in percent: Ov:100,100,100
+                let ov_parts: Vec<&str> = ov_str.split(",").collect();
+                if ov_parts.len() >= 3 {
+                    report.ov = Some((
+                        ov_parts[0].parse().unwrap_or(100),
+                        ov_parts[1].parse().unwrap_or(100),
+                        ov_parts[2].parse().unwrap_or(100),
+                    ));
+                }
             }
         }
 
         Ok(report)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &str) -> Vec<Response> {
+        lines
+            .lines()
+            .map(|line| Response::try_from(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn settings_and_startup_lines() {
+        let responses = parse("$0=10\n$32=1\n$110=5000.000\nok");
+        assert!(matches!(&responses[0], Response::Setting { id: 0, value } if value == "10"));
+        assert!(matches!(&responses[1], Response::Setting { id: 32, value } if value == "1"));
+        assert!(
+            matches!(&responses[2], Response::Setting { id: 110, value } if value == "5000.000")
+        );
+        assert!(matches!(responses[3], Response::Ok));
+
+        let responses = parse("$N0=G54 G21\n$N1=\nok");
+        assert!(
+            matches!(&responses[0], Response::StartupLine { index: 0, line } if line == "G54 G21")
+        );
+        assert!(
+            matches!(&responses[1], Response::StartupLine { index: 1, line } if line.is_empty())
+        );
+        assert_eq!(responses[0].to_string(), "$N0=G54 G21");
+    }
+
+    #[test]
+    fn offsets_and_probe() {
+        let responses = parse(
+            "[G54:-10.000,-20.000,-30.000]\n[G28:0.000,0.000,-1.000]\n[G92:0.000,0.000,0.000]\n\
+             [TLO:1.500]\n[PRB:-5.000,-6.000,-7.250:1]\nok",
+        );
+        let offsets: Vec<(&str, (f64, f64, f64))> = responses
+            .iter()
+            .filter_map(|response| match response {
+                Response::Offset { name, coords, .. } => Some((name.as_str(), *coords)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                ("G54", (-10.0, -20.0, -30.0)),
+                ("G28", (0.0, 0.0, -1.0)),
+                ("G92", (0.0, 0.0, 0.0)),
+                ("TLO", (0.0, 0.0, 1.5)),
+            ]
+        );
+        assert!(matches!(
+            responses[4],
+            Response::Probe {
+                coords: (-5.0, -6.0, -7.25),
+                success: true,
+                ..
+            }
+        ));
+
+        // grblHAL reports every axis it was built with
+        let responses = parse("[TLO:0.100,0.200,0.300]\n[PRB:1.000,2.000,3.000,4.000,5.000:0]");
+        assert!(matches!(
+            responses[0],
+            Response::Offset {
+                coords: (0.1, 0.2, 0.3),
+                ..
+            }
+        ));
+        assert!(matches!(
+            responses[1],
+            Response::Probe {
+                coords: (1.0, 2.0, 3.0),
+                success: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn build_info() {
+        let responses = parse("[VER:1.1h.20190825:]\n[OPT:V,15,128]\nok");
+        assert!(matches!(&responses[0], Response::Build(build) if build == "[VER:1.1h.20190825:]"));
+        assert!(matches!(&responses[1], Response::Build(build) if build == "[OPT:V,15,128]"));
+        assert!(matches!(responses[2], Response::Ok));
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+struct Repeat {
+    text: String,
+    started: Instant,
+    repeats: u64,
+}
+
+/// Collapses a message repeated within the window into one log line and a summary
+pub struct RepeatFilter {
+    window: Duration,
+    last: Option<Repeat>,
+    counts: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl RepeatFilter {
+    pub fn new(window: Duration, counts: Arc<Mutex<HashMap<String, u64>>>) -> Self {
+        Self {
+            window,
+            last: None,
+            counts,
+        }
+    }
+
+    // Counts every message, but returns false for repeats that shouldn't be logged
+    pub fn check(&mut self, text: &str) -> bool {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(text.to_string()).or_default() += 1;
+        }
+
+        if self.window.is_zero() {
+            return true;
+        }
+
+        match &mut self.last {
+            Some(last) if last.text == text && last.started.elapsed() < self.window => {
+                last.repeats += 1;
+                false
+            }
+            _ => {
+                self.flush();
+                self.last = Some(Repeat {
+                    text: text.to_string(),
+                    started: Instant::now(),
+                    repeats: 0,
+                });
+                true
+            }
+        }
+    }
+
+    /// Logs the repeats of the last message once its window has passed
+    pub fn flush_expired(&mut self) {
+        if self
+            .last
+            .as_ref()
+            .is_some_and(|last| last.started.elapsed() >= self.window)
+        {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(last) = self.last.take()
+            && last.repeats > 0
+        {
+            warn!(
+                "Message '{}' repeated {} times in {:.0} s",
+                last.text,
+                last.repeats,
+                last.started.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,15 +1,21 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::error;
+use crossbeam::channel;
+use log::{error, warn};
 
-use super::command::Command;
-use super::message::{Push, Report, Response};
+use crate::config::{FlowControl, GrblConfig};
+
+use super::command::{Command, Realtime};
+use super::message::{Push, Report, Response, Status};
 use super::{Controller, ControllerError};
 
+const SETTINGS_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub fn wait_for_report<F: Fn(&Report) -> bool>(
     controller: &Controller,
     predicate: Option<F>,
@@ -23,10 +29,10 @@ pub fn wait_for_report<F: Fn(&Report) -> bool>(
     let polling = Arc::new(AtomicBool::new(true));
     let running = controller.running.clone();
 
-    Ok(thread::scope(|scope| {
+    thread::scope(|scope| {
         scope.spawn(|| {
             while polling.load(Ordering::Relaxed) {
-                if let Err(error) = prio_serial_tx.send(Command::Realtime(b'?')) {
+                if let Err(error) = prio_serial_tx.send(Realtime::StatusReport.into()) {
                     error!("Failed to poll status report: {}", error);
                 }
 
@@ -37,16 +43,26 @@ pub fn wait_for_report<F: Fn(&Report) -> bool>(
         while running.load(Ordering::Relaxed) {
             match prio_serial_rx.recv() {
                 Ok(Push::Report(report)) => {
-                    if let Some(matcher) = &predicate {
-                        if !matcher(&report) {
-                            continue;
-                        }
+                    if let Err(error) = controller.check_alarm() {
+                        polling.store(false, Ordering::Relaxed);
+                        return Err(error);
+                    }
+
+                    if let Some(matcher) = &predicate
+                        && !matcher(&report)
+                    {
+                        continue;
                     }
 
                     polling.store(false, Ordering::Relaxed);
                     return Ok(Some(report));
                 }
+                Ok(Push::Alarm(code)) => {
+                    polling.store(false, Ordering::Relaxed);
+                    return Err(ControllerError::AlarmError(code));
+                }
                 Err(error) => {
+                    polling.store(false, Ordering::Relaxed);
                     return Err(ControllerError::SerialError(format!(
                         "Failed to wait for status report: {}",
                         error
@@ -56,19 +72,152 @@ pub fn wait_for_report<F: Fn(&Report) -> bool>(
         }
 
         Ok(None)
-    })?)
+    })
+}
+
+/// Hooks into `buffered_stream`, e.g. to drive progress displays while a program is streamed.
+///
+/// Line counts are relative to the start of the stream. `on_ack` and `on_error` are passed the
+/// acknowledged line count, the sent line count and the acknowledged line's size in bytes, and
+/// can abort the stream by returning an error.
+pub trait StreamObserver {
+    fn on_line_sent(&mut self, _sent: i32, _line: &str) {}
+
+    fn on_ack(
+        &mut self,
+        _acknowledged: i32,
+        _sent: i32,
+        _bytes: usize,
+        _response: &Response,
+    ) -> Result<(), ControllerError> {
+        Ok(())
+    }
+
+    fn on_error(
+        &mut self,
+        _acknowledged: i32,
+        _sent: i32,
+        _bytes: usize,
+        _response: &Response,
+    ) -> Result<(), ControllerError> {
+        Ok(())
+    }
+
+    // Responses other than `ok` and `error:N`, such as probe results, arrive before their line's ack
+    fn on_response(
+        &mut self,
+        _acknowledged: i32,
+        _response: &Response,
+    ) -> Result<(), ControllerError> {
+        Ok(())
+    }
+
+    fn on_push(&mut self, _push: &Push) {}
+
+    /// How often to poll status reports while streaming, each passed to `on_push`. Reports are
+    /// only polled as flow control needs them without one
+    fn report_interval(&self) -> Option<Duration> {
+        None
+    }
 }
 
-pub fn buffered_stream(
+impl StreamObserver for () {}
+
+/// Pairs an acknowledgement closure with one for the other responses
+pub struct WithResponses<A, R> {
+    pub on_ack: A,
+    pub on_response: R,
+}
+
+impl<A, R> StreamObserver for WithResponses<A, R>
+where
+    A: FnMut(i32, i32, usize, &Response) -> Result<(), ControllerError>,
+    R: FnMut(i32, &Response) -> Result<(), ControllerError>,
+{
+    fn on_ack(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        (self.on_ack)(acknowledged, sent, bytes, response)
+    }
+
+    fn on_error(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        (self.on_ack)(acknowledged, sent, bytes, response)
+    }
+
+    fn on_response(
+        &mut self,
+        acknowledged: i32,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        (self.on_response)(acknowledged, response)
+    }
+}
+
+// A closure is called for every acknowledgement, both `ok` and `error:N`
+impl<F: FnMut(i32, i32, usize, &Response) -> Result<(), ControllerError>> StreamObserver for F {
+    fn on_ack(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        self(acknowledged, sent, bytes, response)
+    }
+
+    fn on_error(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        self(acknowledged, sent, bytes, response)
+    }
+}
+
+// Lines are pulled from `gcode` as they are sent. Only responses other than `ok` are returned, so
+// memory use doesn't grow with the length of the program
+pub fn buffered_stream<S, O>(
     controller: &Controller,
-    gcode: Vec<&str>,
-    rx_buffer_size: usize,
-) -> Result<Vec<(i32, Response)>, ControllerError> {
+    gcode: impl IntoIterator<Item = S>,
+    grbl: &GrblConfig,
+    mut observer: O,
+) -> Result<Vec<(i32, Response)>, ControllerError>
+where
+    S: AsRef<str>,
+    O: StreamObserver,
+{
     let Some((serial_tx, serial_rx)) = controller.serial_channel.clone() else {
         return Err(ControllerError::SerialError(
             "Controller not started".to_string(),
         ));
     };
+    let Some((_, prio_serial_rx)) = controller.prio_serial_channel.clone() else {
+        return Err(ControllerError::SerialError(
+            "Controller not started".to_string(),
+        ));
+    };
+
+    controller.check_alarm()?;
+
+    if !controller.healthy.load(Ordering::Relaxed) {
+        return Err(ControllerError::SerialError(
+            "Controller is unhealthy after an unanswered command".to_string(),
+        ));
+    }
+
+    let response_timeout = Cell::new(grbl.response_timeout_ms.map(Duration::from_millis));
 
     let mut queued_bytes = VecDeque::new();
     let mut responses = Vec::new();
@@ -76,30 +225,212 @@ pub fn buffered_stream(
     let mut sent = 0;
     let mut received = 0;
 
-    let mut receive =
-        |received: &mut i32, queued_bytes: &mut VecDeque<usize>| -> Result<(), ControllerError> {
-            let response = serial_rx.recv().map_err(|error| {
-                ControllerError::SerialError(format!("Failed to wait for response: {}", error))
-            })?;
+    let last_report = Cell::new(Instant::now());
 
-            if let Response::Ok | Response::Error(_) = response {
-                queued_bytes.pop_front();
-                *received += 1;
+    let request_report = |observer: &mut O| -> Result<Report, ControllerError> {
+        // A report left over from an earlier request would be stale
+        while let Ok(push) = prio_serial_rx.try_recv() {
+            observer.on_push(&push);
+            if let Push::Alarm(code) = push {
+                return Err(ControllerError::AlarmError(code));
             }
+        }
 
-            responses.push((*received, response));
+        loop {
+            controller.check_alarm()?;
+            controller.realtime(Realtime::StatusReport)?;
+
+            let push = match prio_serial_rx.recv_timeout(Duration::from_millis(250)) {
+                Ok(push) => push,
+                Err(channel::RecvTimeoutError::Timeout) => continue,
+                Err(error) => {
+                    return Err(ControllerError::SerialError(format!(
+                        "Failed to wait for status report: {}",
+                        error
+                    )));
+                }
+            };
+
+            observer.on_push(&push);
+
+            match push {
+                Push::Report(report) => {
+                    last_report.set(Instant::now());
+                    return Ok(report);
+                }
+                Push::Alarm(code) => return Err(ControllerError::AlarmError(code)),
+            }
+        }
+    };
+
+    let poll_report = |observer: &mut O| -> Result<(), ControllerError> {
+        if let Some(interval) = observer.report_interval()
+            && last_report.get().elapsed() >= interval
+        {
+            request_report(observer)?;
+        }
+        Ok(())
+    };
+
+    // Returns whether a response was received, only blocking for one when `wait` is set
+    let mut receive = |observer: &mut O,
+                       received: &mut i32,
+                       queued_bytes: &mut VecDeque<usize>,
+                       sent: i32,
+                       wait: bool|
+     -> Result<bool, ControllerError> {
+        let mut waiting = Instant::now();
+        let poll_interval = if wait {
+            Duration::from_millis(100)
+        } else {
+            Duration::ZERO
+        };
+
+        let response = loop {
+            // Grbl locks out further G-code once in alarm, so abort instead of waiting on it
+            controller.check_alarm()?;
+            poll_report(observer)?;
 
-            Ok(())
+            match serial_rx.recv_timeout(poll_interval) {
+                Ok(response) => break response,
+                Err(channel::RecvTimeoutError::Timeout) if !wait => return Ok(false),
+                Err(channel::RecvTimeoutError::Timeout) => {
+                    // Once a stop is requested, stop waiting as soon as Grbl goes quiet
+                    if controller.stopping.load(Ordering::Relaxed) {
+                        return Err(ControllerError::StoppedError {
+                            acknowledged: *received,
+                            sent,
+                        });
+                    }
+
+                    // Grbl doesn't acknowledge anything during a hold with the planner full, so
+                    // the wait only counts while the machine is free to run
+                    if controller.held() {
+                        waiting = Instant::now();
+                    }
+
+                    // A late response would be matched to the wrong line, so stop trusting the connection
+                    if let Some(timeout) = response_timeout.get()
+                        && waiting.elapsed() >= timeout
+                    {
+                        controller.healthy.store(false, Ordering::Relaxed);
+                        return Err(ControllerError::TimeoutError {
+                            line: *received + 1,
+                            timeout,
+                        });
+                    }
+                }
+                Err(error) => {
+                    return Err(ControllerError::SerialError(format!(
+                        "Failed to wait for response: {}",
+                        error
+                    )));
+                }
+            }
         };
 
+        if let Response::Ok | Response::Error(_) = response {
+            let bytes = queued_bytes.pop_front().unwrap_or(0);
+            *received += 1;
+
+            if let Response::Error(_) = response {
+                observer.on_error(*received, sent, bytes, &response)?;
+            } else {
+                observer.on_ack(*received, sent, bytes, &response)?;
+            }
+        } else {
+            observer.on_response(*received, &response)?;
+        }
+
+        if !matches!(response, Response::Ok) {
+            responses.push((*received, response));
+        }
+
+        Ok(true)
+    };
+
+    let mut rx_free = 0;
+    let mut sent_since_report = 0;
+
     for raw_line in gcode {
-        let line = raw_line.trim();
+        // Hold back new blocks while feed hold is active so the planner doesn't refill
+        while controller.paused.load(Ordering::Relaxed)
+            && !controller.stopping.load(Ordering::Relaxed)
+            && controller.running.load(Ordering::Relaxed)
+        {
+            poll_report(&mut observer)?;
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        if controller.stopping.load(Ordering::Relaxed) {
+            break;
+        }
+
+        poll_report(&mut observer)?;
+
+        let line = raw_line.as_ref().trim();
+        let settings_write = is_settings_write(line);
+
+        // EEPROM writes block Grbl, so only write settings once everything before them is done
+        if settings_write {
+            while sent > received {
+                receive(&mut observer, &mut received, &mut queued_bytes, sent, true)?;
+            }
+
+            if !matches!(request_report(&mut observer)?.status, Some(Status::Idle)) {
+                warn!("Writing '{}' while the machine is not idle", line);
+            }
+        }
+
+        // A line that can't fit in the buffer would be waited on forever
+        let too_long = move |capacity: usize| {
+            ControllerError::SerialError(format!(
+                "Line {} is {} bytes, more than fit in the controller's {} byte RX buffer",
+                sent + 1,
+                line.len() + 1,
+                capacity
+            ))
+        };
+        if let FlowControl::CharacterCounting = grbl.flow_control
+            && line.len() + 1 >= grbl.rx_buffer_size_bytes.saturating_sub(1)
+        {
+            return Err(too_long(grbl.rx_buffer_size_bytes));
+        }
 
         queued_bytes.push_back(line.len() + 1);
         sent += 1;
 
-        while queued_bytes.iter().sum::<usize>() >= rx_buffer_size - 1 {
-            receive(&mut received, &mut queued_bytes)?;
+        match grbl.flow_control {
+            FlowControl::CharacterCounting => {
+                while queued_bytes.iter().sum::<usize>()
+                    >= grbl.rx_buffer_size_bytes.saturating_sub(1)
+                {
+                    receive(&mut observer, &mut received, &mut queued_bytes, sent, true)?;
+                }
+            }
+            FlowControl::BufferReport => {
+                while sent_since_report + line.len() + 1 >= rx_free {
+                    while receive(&mut observer, &mut received, &mut queued_bytes, sent, false)? {}
+
+                    rx_free = match request_report(&mut observer)?.bf {
+                        Some((_, rx_free)) => rx_free,
+                        None => {
+                            return Err(ControllerError::SerialError(
+                                "Status reports don't include buffer state (Bf:), check $10"
+                                    .to_string(),
+                            ));
+                        }
+                    };
+                    sent_since_report = 0;
+
+                    // With every earlier line acknowledged the buffer is as empty as it gets
+                    if received == sent - 1 && line.len() + 1 >= rx_free {
+                        return Err(too_long(rx_free));
+                    }
+                }
+
+                sent_since_report += line.len() + 1;
+            }
         }
 
         serial_tx
@@ -107,11 +438,168 @@ pub fn buffered_stream(
             .map_err(|error| {
                 ControllerError::SerialError(format!("Failed to send G-code command: {}", error))
             })?;
+
+        observer.on_line_sent(sent, line);
+
+        if settings_write {
+            response_timeout.set(
+                response_timeout
+                    .get()
+                    .map(|timeout| timeout.max(SETTINGS_WRITE_TIMEOUT)),
+            );
+            while sent > received {
+                receive(&mut observer, &mut received, &mut queued_bytes, sent, true)?;
+            }
+            response_timeout.set(grbl.response_timeout_ms.map(Duration::from_millis));
+        }
     }
 
     while sent > received {
-        receive(&mut received, &mut queued_bytes)?;
+        receive(&mut observer, &mut received, &mut queued_bytes, sent, true)?;
+    }
+
+    if controller.stopping.load(Ordering::Relaxed) {
+        return Err(ControllerError::StoppedError {
+            acknowledged: received,
+            sent,
+        });
     }
 
     Ok(responses)
 }
+
+// `$N=`, `$Nx=`, `$I=` and `$RST=` are stored in EEPROM, unlike `$J=` jogs
+fn is_settings_write(line: &str) -> bool {
+    line.starts_with('$') && line.contains('=') && !line.starts_with("$J=")
+}
+
+pub fn checked_stream(
+    controller: &Controller,
+    gcode: Vec<&str>,
+    grbl: &GrblConfig,
+) -> Result<Vec<(i32, Response)>, ControllerError> {
+    buffered_stream(
+        controller,
+        gcode,
+        grbl,
+        |line, _, _, response: &Response| match response {
+            Response::Error(_) => Err(ControllerError::GcodeError(line, response.clone())),
+            _ => Ok(()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Acknowledges the oldest line whenever nothing arrives for a millisecond, so the stream fills
+    // the buffer as far as its flow control lets it. Returns the most bytes ever waiting
+    fn fake_grbl(controller: &mut Controller, rx_buffer_bytes: usize) -> thread::JoinHandle<usize> {
+        let (serial_tx, serial_rx) = channel::bounded::<Command>(0);
+        let (response_tx, response_rx) = channel::unbounded();
+        let (prio_tx, prio_rx) = channel::bounded::<Command>(0);
+        let (push_tx, push_rx) = channel::unbounded();
+        controller.serial_channel = Some((serial_tx, response_rx));
+        controller.prio_serial_channel = Some((prio_tx, push_rx));
+
+        thread::spawn(move || {
+            let mut waiting = VecDeque::new();
+            let mut most = 0;
+            loop {
+                if let Ok(Command::Realtime(b'?')) = prio_rx.try_recv() {
+                    let free = rx_buffer_bytes - waiting.iter().sum::<usize>();
+                    let report = format!("<Idle|MPos:0.000,0.000,0.000|Bf:15,{}>", free);
+                    let _ = push_tx.send(Push::try_from(report.as_str()).unwrap());
+                }
+                match serial_rx.recv_timeout(Duration::from_millis(1)) {
+                    Ok(Command::Gcode(line)) => {
+                        waiting.push_back(line.len() + 1);
+                        most = most.max(waiting.iter().sum());
+                    }
+                    Ok(Command::Realtime(_)) => {}
+                    Err(channel::RecvTimeoutError::Timeout) => {
+                        if waiting.pop_front().is_some() {
+                            let _ = response_tx.send(Response::Ok);
+                        }
+                    }
+                    Err(channel::RecvTimeoutError::Disconnected) => return most,
+                }
+            }
+        })
+    }
+
+    fn grbl(flow_control: FlowControl, rx_buffer_size_bytes: usize) -> GrblConfig {
+        GrblConfig {
+            rx_buffer_size_bytes,
+            response_timeout_ms: Some(1000),
+            flow_control,
+        }
+    }
+
+    fn program() -> Vec<String> {
+        (0..60)
+            .map(|line| format!("G1 X{} F{}", line * 37 % 1000, 100 + line))
+            .collect()
+    }
+
+    #[test]
+    fn character_counting_stays_within_the_buffer() {
+        let mut controller = Controller::new();
+        let fake = fake_grbl(&mut controller, 48);
+
+        let grbl = grbl(FlowControl::CharacterCounting, 48);
+        let responses = checked_stream(&controller, vec!["G21", "G90"], &grbl).unwrap();
+        assert!(responses.is_empty());
+        buffered_stream(
+            &controller,
+            program(),
+            &grbl,
+            |_, _, _, _: &Response| Ok(()),
+        )
+        .unwrap();
+        drop(controller);
+
+        // A byte is kept free, as Grbl's streaming example does
+        let most = fake.join().unwrap();
+        assert!(most > 24 && most < 47, "{} bytes were waiting", most);
+    }
+
+    #[test]
+    fn buffer_report_stays_within_the_free_space() {
+        let mut controller = Controller::new();
+        let fake = fake_grbl(&mut controller, 48);
+
+        let grbl = grbl(FlowControl::BufferReport, 48);
+        buffered_stream(
+            &controller,
+            program(),
+            &grbl,
+            |_, _, _, _: &Response| Ok(()),
+        )
+        .unwrap();
+        drop(controller);
+
+        let most = fake.join().unwrap();
+        assert!(most > 24 && most < 48, "{} bytes were waiting", most);
+    }
+
+    #[test]
+    fn lines_longer_than_the_buffer_are_rejected() {
+        let line = "G1 X100.000 Y100.000 Z-1.000 F500";
+        for flow_control in [FlowControl::CharacterCounting, FlowControl::BufferReport] {
+            let mut controller = Controller::new();
+            let fake = fake_grbl(&mut controller, 24);
+
+            let error = checked_stream(&controller, vec!["G21", line], &grbl(flow_control, 24))
+                .unwrap_err();
+            drop(controller);
+            fake.join().unwrap();
+            assert!(
+                error.to_string().contains("Line 2 is 34 bytes"),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::OnceLock;
+
+use crate::config::DisplayConfig;
+
+static DISPLAY: OnceLock<DisplayConfig> = OnceLock::new();
+
+// Set once the config is loaded, messages before then use the defaults
+pub fn init(config: &DisplayConfig) {
+    let _ = DISPLAY.set(config.clone());
+}
+
+fn config() -> DisplayConfig {
+    DISPLAY.get().cloned().unwrap_or_default()
+}
+
+/// Formats a length given in mm, e.g. `12.345 mm`
+pub fn length(mm: f64) -> String {
+    let config = config();
+    format!(
+        "{:.*} {}",
+        config.precision,
+        config.units.convert_mm(mm),
+        config.units
+    )
+}
+
+/// Formats a position given in mm, e.g. `X1.000 Y2.000 Z-3.000 mm`
+pub fn position((x, y, z): (f64, f64, f64)) -> String {
+    let config = config();
+    let value = |mm| format!("{:.*}", config.precision, config.units.convert_mm(mm));
+    format!("X{} Y{} Z{} {}", value(x), value(y), value(z), config.units)
+}
+
+/// Formats a feed rate given in mm/min, e.g. `1000.0 mm/min`
+pub fn feed(mm_per_min: f64) -> String {
+    let config = config();
+    // Feeds don't need the same precision as positions
+    let precision = config.precision.saturating_sub(2);
+    format!(
+        "{:.*} {}/min",
+        precision,
+        config.units.convert_mm(mm_per_min),
+        config.units
+    )
+}
@@ -0,0 +1,133 @@
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+use serialport::SerialPortType;
+
+use crate::config::{CncConfig, GrblConfig};
+use crate::controller::Controller;
+use crate::controller::message::Response;
+use crate::controller::serial::checked_stream;
+
+// Opening the port resets most boards, so give the bootloader time to hand over to Grbl
+const BOOT_DELAY: Duration = Duration::from_secs(2);
+const INFO_TIMEOUT_MS: u64 = 5000;
+
+pub fn flash(config: &CncConfig, firmware: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !Path::new(firmware).exists() {
+        return Err(format!("Firmware file '{}' does not exist", firmware).into());
+    }
+
+    let port = find_port(config)?;
+    info!("Flashing '{}' to the controller on '{}'", firmware, port);
+
+    match build_info(config, &port) {
+        Ok(build) => info!("Current firmware: {}", build.join(" ")),
+        Err(error) => warn!("Failed to read current firmware info: {}", error),
+    }
+
+    let mut command = if firmware.ends_with(".bin") {
+        // SAM3X boards such as the Arduino Due
+        let mut command = Command::new("bossac");
+        command.args(["-p", &port, "-e", "-w", "-v", "-b", firmware, "-R"]);
+        command
+    } else {
+        // AVR boards, by default ATmega328P boards with the Arduino bootloader (Uno, Nano)
+        let mut command = Command::new("avrdude");
+        command.args([
+            "-p",
+            &config.flash.part,
+            "-c",
+            &config.flash.programmer,
+            "-P",
+            &port,
+            "-b",
+            "115200",
+            "-D",
+            "-U",
+            &format!("flash:w:{}:i", firmware),
+        ]);
+        command
+    };
+
+    let status = command
+        .status()
+        .map_err(|error| format!("Failed to run {:?}: {}", command.get_program(), error))?;
+    if !status.success() {
+        return Err(format!("{:?} failed with {}", command.get_program(), status).into());
+    }
+
+    let build = build_info(config, &port)
+        .map_err(|error| format!("Failed to verify flashed firmware: {}", error))?;
+    info!("Flashed firmware: {}", build.join(" "));
+
+    Ok(())
+}
+
+// Use the configured port if it exists, otherwise the only USB serial port connected
+fn find_port(config: &CncConfig) -> Result<String, Box<dyn std::error::Error>> {
+    if Path::new(&config.serial.port).exists() {
+        return Ok(config.serial.port.clone());
+    }
+
+    let ports: Vec<String> = serialport::available_ports()?
+        .into_iter()
+        .filter(|port| matches!(port.port_type, SerialPortType::UsbPort(_)))
+        .map(|port| port.port_name)
+        .collect();
+
+    match ports.as_slice() {
+        [port] => {
+            info!(
+                "'{}' not found, using detected port '{}'",
+                config.serial.port, port
+            );
+            Ok(port.clone())
+        }
+        [] => Err(format!(
+            "'{}' not found and no USB serial ports detected",
+            config.serial.port
+        )
+        .into()),
+        _ => Err(format!(
+            "'{}' not found and several USB serial ports detected: {}",
+            config.serial.port,
+            ports.join(", ")
+        )
+        .into()),
+    }
+}
+
+fn build_info(config: &CncConfig, port: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let serial = serialport::new(port, config.serial.baudrate)
+        .timeout(Duration::from_millis(config.serial.timeout_ms))
+        .open()?;
+    thread::sleep(BOOT_DELAY);
+
+    let mut controller = Controller::new();
+    controller.start(
+        serial,
+        config.logs.verbose,
+        Duration::from_millis(config.logs.repeat_window_ms),
+    );
+
+    let grbl = GrblConfig {
+        response_timeout_ms: Some(INFO_TIMEOUT_MS),
+        ..config.grbl.clone()
+    };
+    let result = checked_stream(&controller, vec!["$I"], &grbl);
+
+    controller.stop();
+
+    let build = result?
+        .into_iter()
+        .filter_map(|(_, response)| match response {
+            Response::Build(build) => Some(build),
+            _ => None,
+        })
+        .collect();
+
+    Ok(build)
+}
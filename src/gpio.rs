@@ -0,0 +1,58 @@
+mod gpiod;
+mod rppal;
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::{Edge, GpioBackend, GpioConfig, Level, Pull};
+
+static GPIO: OnceLock<GpioConfig> = OnceLock::new();
+
+/// Access to the GPIO pins of the board cnc-ctrl runs on
+pub trait Backend {
+    /// Claims `pin` to read its level
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn Input>, Box<dyn std::error::Error>>;
+
+    /// Claims `pin` to wait for `edge`, edges within `debounce` of the last one are ignored
+    fn watch(
+        &self,
+        pin: u8,
+        pull: Pull,
+        edge: Edge,
+        debounce: Duration,
+    ) -> Result<Box<dyn Watch>, Box<dyn std::error::Error>>;
+
+    /// Claims `pin` as an output, starting low. The level is kept once the output is dropped,
+    /// so accessories stay switched until they are switched again
+    fn output(&self, pin: u8) -> Result<Box<dyn Output>, Box<dyn std::error::Error>>;
+}
+
+pub trait Input: Send {
+    fn read(&self) -> Result<Level, Box<dyn std::error::Error>>;
+}
+
+pub trait Watch: Send {
+    fn pin(&self) -> u8;
+
+    /// Waits up to `timeout` for an edge, or forever without one, returns the edge seen
+    fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<Edge>, String>;
+}
+
+pub trait Output: Send {
+    fn set(&mut self, level: Level) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+// Set once the config is loaded, GPIO used before then goes through the default backend
+pub fn init(config: &GpioConfig) {
+    let _ = GPIO.set(config.clone());
+}
+
+/// Opens the configured backend, failing if the board doesn't support it
+pub fn open() -> Result<Box<dyn Backend>, Box<dyn std::error::Error>> {
+    let config = GPIO.get().cloned().unwrap_or_default();
+
+    Ok(match config.backend {
+        GpioBackend::Rppal => Box::new(rppal::Rppal::new()?),
+        GpioBackend::Gpiod => Box::new(gpiod::Gpiod::new(&config.chip)?),
+    })
+}
@@ -0,0 +1,168 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel::{self, Receiver, RecvTimeoutError};
+use gpio_cdev::{Chip, EventRequestFlags, EventType, LineHandle, LineRequestFlags};
+use log::warn;
+
+use crate::config::{Edge, Level, Pull};
+
+use super::{Backend, Input, Output, Watch};
+
+const CONSUMER: &str = "cnc-ctrl";
+
+// Requesting lines needs the chip mutably, the backend is shared immutably
+pub struct Gpiod(Mutex<Chip>);
+
+impl Gpiod {
+    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let chip =
+            Chip::new(path).map_err(|error| format!("Failed to open '{}': {}", path, error))?;
+        Ok(Self(Mutex::new(chip)))
+    }
+
+    fn line(&self, pin: u8) -> Result<gpio_cdev::Line, Box<dyn std::error::Error>> {
+        let mut chip = match self.0.lock() {
+            Ok(chip) => chip,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        chip.get_line(pin.into())
+            .map_err(|error| format!("Failed to get GPIO line {}: {}", pin, error).into())
+    }
+}
+
+// The character device can't set bias, so `pull` is left to the device tree. Inputs default to
+// a pull-up, which would otherwise go missing without a word
+fn warn_pull(pin: u8, pull: Pull) {
+    let pull = match pull {
+        Pull::Up => "up",
+        Pull::Down => "down",
+        Pull::None => return,
+    };
+    warn!(
+        "GPIO line {}: the gpiod backend can't set pull {}, the input floats unless the device tree or wiring biases it. Set `pull: none` once it does",
+        pin, pull
+    );
+}
+
+impl Backend for Gpiod {
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn Input>, Box<dyn std::error::Error>> {
+        warn_pull(pin, pull);
+        let handle = self
+            .line(pin)?
+            .request(LineRequestFlags::INPUT, 0, CONSUMER)
+            .map_err(|error| format!("Failed to request GPIO line {}: {}", pin, error))?;
+        Ok(Box::new(GpiodLine(handle)))
+    }
+
+    fn watch(
+        &self,
+        pin: u8,
+        pull: Pull,
+        edge: Edge,
+        debounce: Duration,
+    ) -> Result<Box<dyn Watch>, Box<dyn std::error::Error>> {
+        warn_pull(pin, pull);
+        let flags = match edge {
+            Edge::Rising => EventRequestFlags::RISING_EDGE,
+            Edge::Falling => EventRequestFlags::FALLING_EDGE,
+            Edge::Both => EventRequestFlags::BOTH_EDGES,
+        };
+        let mut events = self
+            .line(pin)?
+            .events(LineRequestFlags::INPUT, flags, CONSUMER)
+            .map_err(|error| format!("Failed to request events on GPIO line {}: {}", pin, error))?;
+
+        // Reading events blocks, so they are read on their own thread and polled from a channel
+        let (tx, rx) = channel::unbounded();
+        thread::spawn(move || {
+            let debounce_ns = debounce.as_nanos() as u64;
+            let mut last: Option<u64> = None;
+
+            loop {
+                let event = match events.get_event() {
+                    Ok(event) => event,
+                    Err(error) => {
+                        let _ = tx.send(Err(error.to_string()));
+                        return;
+                    }
+                };
+
+                if last.is_some_and(|last| event.timestamp().saturating_sub(last) < debounce_ns) {
+                    continue;
+                }
+                last = Some(event.timestamp());
+
+                let edge = match event.event_type() {
+                    EventType::RisingEdge => Edge::Rising,
+                    EventType::FallingEdge => Edge::Falling,
+                };
+                if tx.send(Ok(edge)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Box::new(GpiodWatch { pin, events: rx }))
+    }
+
+    fn output(&self, pin: u8) -> Result<Box<dyn Output>, Box<dyn std::error::Error>> {
+        let handle = self
+            .line(pin)?
+            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)
+            .map_err(|error| format!("Failed to request GPIO line {}: {}", pin, error))?;
+        Ok(Box::new(GpiodLine(handle)))
+    }
+}
+
+struct GpiodLine(LineHandle);
+
+impl Input for GpiodLine {
+    fn read(&self) -> Result<Level, Box<dyn std::error::Error>> {
+        Ok(if self.0.get_value()? == 1 {
+            Level::High
+        } else {
+            Level::Low
+        })
+    }
+}
+
+impl Output for GpiodLine {
+    fn set(&mut self, level: Level) -> Result<(), Box<dyn std::error::Error>> {
+        self.0.set_value(match level {
+            Level::High => 1,
+            Level::Low => 0,
+        })?;
+        Ok(())
+    }
+}
+
+struct GpiodWatch {
+    pin: u8,
+    events: Receiver<Result<Edge, String>>,
+}
+
+impl Watch for GpiodWatch {
+    fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<Edge>, String> {
+        let event = match timeout {
+            Some(timeout) => match self.events.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err("event reader stopped".to_string()),
+            },
+            None => self
+                .events
+                .recv()
+                .unwrap_or_else(|_| Err("event reader stopped".to_string())),
+        };
+
+        event
+            .map(Some)
+            .map_err(|error| format!("Failed to poll GPIO line {}: {}", self.pin, error))
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use rppal::gpio::{Gpio, InputPin, OutputPin, Pin, Trigger};
+
+use crate::config::{Edge, Level, Pull};
+
+use super::{Backend, Input, Output, Watch};
+
+pub struct Rppal(Gpio);
+
+impl Rppal {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self(Gpio::new()?))
+    }
+
+    fn get(&self, pin: u8) -> Result<Pin, Box<dyn std::error::Error>> {
+        self.0
+            .get(pin)
+            .map_err(|error| format!("Failed to get GPIO pin {}: {}", pin, error).into())
+    }
+
+    fn get_input(&self, pin: u8, pull: Pull) -> Result<InputPin, Box<dyn std::error::Error>> {
+        let pin = self.get(pin)?;
+        Ok(match pull {
+            Pull::Up => pin.into_input_pullup(),
+            Pull::Down => pin.into_input_pulldown(),
+            Pull::None => pin.into_input(),
+        })
+    }
+}
+
+impl Backend for Rppal {
+    fn input(&self, pin: u8, pull: Pull) -> Result<Box<dyn Input>, Box<dyn std::error::Error>> {
+        Ok(Box::new(self.get_input(pin, pull)?))
+    }
+
+    fn watch(
+        &self,
+        pin: u8,
+        pull: Pull,
+        edge: Edge,
+        debounce: Duration,
+    ) -> Result<Box<dyn Watch>, Box<dyn std::error::Error>> {
+        let mut input = self.get_input(pin, pull)?;
+        input
+            .set_interrupt(edge.into(), Some(debounce))
+            .map_err(|error| format!("Failed to set interrupt on GPIO {}: {}", pin, error))?;
+        Ok(Box::new(input))
+    }
+
+    fn output(&self, pin: u8) -> Result<Box<dyn Output>, Box<dyn std::error::Error>> {
+        let mut output = self.get(pin)?.into_output_low();
+        output.set_reset_on_drop(false);
+        Ok(Box::new(output))
+    }
+}
+
+impl Input for InputPin {
+    fn read(&self) -> Result<Level, Box<dyn std::error::Error>> {
+        Ok(if self.is_high() {
+            Level::High
+        } else {
+            Level::Low
+        })
+    }
+}
+
+impl Watch for InputPin {
+    fn pin(&self) -> u8 {
+        InputPin::pin(self)
+    }
+
+    fn poll(&mut self, timeout: Option<Duration>) -> Result<Option<Edge>, String> {
+        let event = self
+            .poll_interrupt(true, timeout)
+            .map_err(|error| format!("Failed to poll GPIO {}: {}", InputPin::pin(self), error))?;
+
+        Ok(event.map(|event| match event.trigger {
+            Trigger::FallingEdge => Edge::Falling,
+            _ => Edge::Rising,
+        }))
+    }
+}
+
+impl Output for OutputPin {
+    fn set(&mut self, level: Level) -> Result<(), Box<dyn std::error::Error>> {
+        match level {
+            Level::High => self.set_high(),
+            Level::Low => self.set_low(),
+        }
+        Ok(())
+    }
+}
+
+impl From<Edge> for Trigger {
+    fn from(edge: Edge) -> Self {
+        match edge {
+            Edge::Rising => Trigger::RisingEdge,
+            Edge::Falling => Trigger::FallingEdge,
+            Edge::Both => Trigger::Both,
+        }
+    }
+}
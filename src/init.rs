@@ -0,0 +1,102 @@
+use std::fs;
+use std::path::Path;
+
+use log::info;
+
+use crate::cli::InitArgs;
+
+const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+
+const MACHINE_TEMPLATE: &str = r#"# Settings shared by every job on this machine, included from the job file
+logs:
+  verbose: false              # Log serial traffic
+  save: true                  # Save logs to file
+  path: "~/cnc/logs/{%t}"     # Log file path ({%t} is the time the sequence started)
+
+serial:
+  port: "{port}"              # Serial device of the controller
+  baudrate: 115200
+  timeout_ms: 1000
+
+grbl:
+  rx_buffer_size_bytes: 1024  # 1024 for grblHAL, 128 for Grbl 1.1 on AVR
+
+inputs:
+  signal:                     # Start button (BCM GPIO number)
+    pin: 17
+    debounce_ms: 50
+  fallback: console           # Press Enter instead when GPIO is unavailable, e.g. on the bench
+
+# machine:                    # Travel limits for moves cnc-ctrl generates (optional)
+#   x: { min: -800, max: 0 }
+#   y: { min: -800, max: 0 }
+#   z: { min: -120, max: 0 }
+"#;
+
+const JOB_TEMPLATE: &str = r#"# Job configuration, see the README for every option
+include:
+  - machine.yml               # Connection and machine settings
+
+job:
+  name: example
+  repeat: forever             # once, forever, until_signal or { count: N }
+
+steps:
+  - type: home                # Home the machine at the start of each sequence
+    name: home
+    wait_for_signal: true
+
+  - type: gcode               # Stream a G-code program
+    name: cut
+    path: "~/cnc/part.gcode"  # Change this to your program
+    check: true               # Check the program in Grbl's check mode first
+{probe}
+  - type: count               # Count a finished part
+"#;
+
+const PROBE_TEMPLATE: &str = r#"    probe:                    # Log probe contacts to a CSV file
+      save_path: "~/cnc/probe/{%t}.csv"
+"#;
+
+/// Writes a commented job file and the machine settings it includes to `directory`
+pub fn init(directory: &Path, options: &InitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if options.tcp.is_some() {
+        return Err("Only serial connections are supported".into());
+    }
+
+    let machine =
+        MACHINE_TEMPLATE.replace("{port}", options.serial.as_deref().unwrap_or(DEFAULT_PORT));
+    let job = JOB_TEMPLATE.replace(
+        "{probe}",
+        if options.with_probe_output {
+            PROBE_TEMPLATE
+        } else {
+            ""
+        },
+    );
+
+    let files = [
+        (directory.join("machine.yml"), machine),
+        (directory.join("config.yml"), job),
+    ];
+
+    // Check every file first, so nothing is written if one would be overwritten
+    if !options.force
+        && let Some((path, _)) = files.iter().find(|(path, _)| path.exists())
+    {
+        return Err(format!(
+            "'{}' already exists, use --force to overwrite it",
+            path.display()
+        )
+        .into());
+    }
+
+    fs::create_dir_all(directory)?;
+    for (path, contents) in &files {
+        fs::write(path, contents)
+            .map_err(|error| format!("Failed to write '{}': {}", path.display(), error))?;
+        info!("Wrote '{}'", path.display());
+    }
+
+    Ok(())
+}
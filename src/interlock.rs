@@ -0,0 +1,138 @@
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::config::{CncConfig, InterlockConfig, InterlockOutput, Level};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Status};
+use crate::controller::serial::{checked_stream, wait_for_report};
+use crate::gpio::{self, Output};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Outputs switched on around streaming, switched off again with `release`
+pub struct Interlocks<'a> {
+    engaged: Vec<(&'a InterlockConfig, Option<Box<dyn Output>>)>,
+}
+
+// Switch every interlock on, then give each its lead time before streaming starts
+pub fn engage<'a>(
+    controller: &Controller,
+    config: &'a CncConfig,
+) -> Result<Interlocks<'a>, Box<dyn std::error::Error>> {
+    let mut interlocks = Interlocks {
+        engaged: Vec::new(),
+    };
+
+    for interlock in &config.interlocks {
+        info!("Switching on {}", interlock.name);
+
+        match switch_on(controller, config, interlock) {
+            Ok(pin) => interlocks.engaged.push((interlock, pin)),
+            Err(error) => {
+                interlocks.release(controller, config, false);
+                return Err(format!("Failed to switch on {}: {}", interlock.name, error).into());
+            }
+        }
+    }
+
+    let before = config
+        .interlocks
+        .iter()
+        .map(|interlock| interlock.before_ms)
+        .max()
+        .unwrap_or_default();
+
+    if before > 0 {
+        info!("Waiting {} ms for interlocks before streaming", before);
+
+        let end = Instant::now() + Duration::from_millis(before);
+        while Instant::now() < end {
+            if controller.stopping.load(Ordering::Relaxed) {
+                interlocks.release(controller, config, false);
+                return Err("Stopped while waiting for interlocks".into());
+            }
+            thread::sleep(POLL_INTERVAL.min(end.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    Ok(interlocks)
+}
+
+impl Interlocks<'_> {
+    // Keep each interlock on for its run-on time after the machine stops, then switch it off
+    pub fn release(mut self, controller: &Controller, config: &CncConfig, completed: bool) {
+        if self.engaged.is_empty() {
+            return;
+        }
+
+        // Motion continues after the last line is acknowledged, so time the run-on from Idle
+        if completed
+            && let Err(error) = wait_for_report(
+                controller,
+                Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+            )
+        {
+            error!("Failed to wait for the machine to stop: {}", error);
+        }
+
+        let stopped = Instant::now();
+        self.engaged
+            .sort_by_key(|(interlock, _)| interlock.after_ms);
+
+        for (interlock, pin) in self.engaged {
+            let remaining =
+                Duration::from_millis(interlock.after_ms).saturating_sub(stopped.elapsed());
+            if !remaining.is_zero() {
+                info!(
+                    "Keeping {} on for another {} ms",
+                    interlock.name,
+                    remaining.as_millis()
+                );
+                thread::sleep(remaining);
+            }
+
+            info!("Switching off {}", interlock.name);
+            if let Err(error) = switch_off(controller, config, interlock, pin) {
+                error!("Failed to switch off {}: {}", interlock.name, error);
+            }
+        }
+    }
+}
+
+fn switch_on(
+    controller: &Controller,
+    config: &CncConfig,
+    interlock: &InterlockConfig,
+) -> Result<Option<Box<dyn Output>>, Box<dyn std::error::Error>> {
+    match &interlock.output {
+        InterlockOutput::Gpio { pin } => {
+            let mut output = gpio::open()?.output(*pin)?;
+            output.set(Level::High)?;
+            Ok(Some(output))
+        }
+        InterlockOutput::Mcode { on, .. } => {
+            checked_stream(controller, vec![on], &config.grbl)?;
+            Ok(None)
+        }
+    }
+}
+
+fn switch_off(
+    controller: &Controller,
+    config: &CncConfig,
+    interlock: &InterlockConfig,
+    pin: Option<Box<dyn Output>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(mut output) = pin {
+        output.set(Level::Low)?;
+    }
+
+    if let InterlockOutput::Mcode { off, .. } = &interlock.output {
+        checked_stream(controller, vec![off], &config.grbl)?;
+    }
+
+    Ok(())
+}
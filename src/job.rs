@@ -0,0 +1,441 @@
+mod report;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel;
+use log::{error, info, warn};
+use serialport::SerialPort;
+
+use crate::alarm;
+use crate::config::{CncConfig, NotifyEvent, Repeat, TemplateVars};
+use crate::controller::Controller;
+use crate::controller::command::Realtime;
+use crate::json_log;
+use crate::notify;
+use crate::pendant;
+use crate::selection::Selection;
+use crate::snapshot::Snapshot;
+use crate::start_signal::StartSignal;
+use crate::status::{self, State};
+use crate::steps;
+
+use report::SequenceReport;
+
+pub use report::{record_lines, record_probes, record_qc};
+
+/// Controls a job started with `start`, which runs its sequences on a separate thread
+pub struct JobHandle {
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    stopping: Arc<AtomicBool>,
+    skip_wait: Arc<AtomicBool>,
+    finishing: Arc<AtomicBool>,
+    repeat: Repeat,
+    serial: Mutex<Box<dyn SerialPort>>,
+    decel: Duration,
+    soft_reset: bool,
+    done: channel::Receiver<Result<(), String>>,
+}
+
+impl JobHandle {
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::Relaxed) {
+            warn!("Pausing streaming (feed hold)");
+            self.send(Realtime::FeedHold);
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::Relaxed) {
+            info!("Resuming streaming (cycle start)");
+            self.send(Realtime::CycleStart);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_pause(&self) {
+        if self.paused() {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    /// Stops the spindle during a hold, or starts it again. Grbl only toggles it while held and
+    /// restores it on resuming, so nothing is sent otherwise
+    pub fn toggle_spindle_stop(&self) {
+        if self.paused() {
+            info!("Toggling spindle stop");
+            self.send(Realtime::ToggleSpindleStop);
+        } else {
+            warn!("Ignoring spindle stop, the spindle can only be stopped while held");
+        }
+    }
+
+    pub fn toggle_flood_coolant(&self) {
+        info!("Toggling flood coolant");
+        self.send(Realtime::ToggleFloodCoolant);
+    }
+
+    pub fn toggle_mist_coolant(&self) {
+        info!("Toggling mist coolant");
+        self.send(Realtime::ToggleMistCoolant);
+    }
+
+    /// Sends Grbl's safety door command when the door opens. When it closes, cycle start is sent
+    /// if `resume_on_close` is set and the job wasn't paused before
+    pub fn door(&self, open: bool, resume_on_close: bool) {
+        if open {
+            warn!("Safety door opened");
+            self.send(Realtime::SafetyDoor);
+        } else if resume_on_close && !self.paused.load(Ordering::Relaxed) {
+            info!("Safety door closed, resuming (cycle start)");
+            self.send(Realtime::CycleStart);
+        } else {
+            info!("Safety door closed");
+        }
+    }
+
+    /// Ends a job repeating `until_signal` once the current sequence completes
+    pub fn finish(&self) {
+        if self.repeat != Repeat::UntilSignal {
+            warn!(
+                "Ignoring finish request, the job only finishes on request with repeat: until_signal"
+            );
+        } else if !self.finishing.swap(true, Ordering::Relaxed) {
+            info!("Finishing the job once the current sequence completes");
+        }
+    }
+
+    /// Starts the next step without waiting for the start signal, or ends a running wait step
+    pub fn skip_wait(&self) {
+        info!("Skipping the wait");
+        self.skip_wait.store(true, Ordering::Relaxed);
+    }
+
+    // Feed hold, let the machine decelerate, then optionally flush Grbl before the job stops
+    pub fn abort(&self) {
+        if self.stopping.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        warn!("Shutting down...");
+
+        self.send(Realtime::FeedHold);
+
+        // Give the machine time to decelerate before the planner is flushed
+        thread::sleep(self.decel);
+
+        if self.soft_reset {
+            self.send(Realtime::SoftReset);
+        } else {
+            warn!("Leaving Grbl in feed hold, queued commands were not flushed");
+        }
+
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Blocks until the job has finished and returns its result
+    pub fn wait(&self) -> Result<(), String> {
+        self.done
+            .recv()
+            .unwrap_or_else(|_| Err("Job thread exited unexpectedly".to_string()))
+    }
+
+    // Realtime commands are written directly so they aren't queued behind streamed G-code
+    fn send(&self, command: Realtime) {
+        let mut serial = match self.serial.lock() {
+            Ok(serial) => serial,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if let Err(error) = serial.write_all(&[command as u8]) {
+            error!("Failed to send {:?}: {}", command, error);
+        }
+    }
+}
+
+pub fn start(
+    config: CncConfig,
+    selection: Selection,
+    controller: Controller,
+    serial: Box<dyn SerialPort>,
+    mut signal: StartSignal,
+) -> JobHandle {
+    let (done_tx, done_rx) = channel::bounded(1);
+    let finishing = Arc::new(AtomicBool::new(false));
+
+    let handle = JobHandle {
+        running: controller.running.clone(),
+        paused: controller.paused.clone(),
+        stopping: controller.stopping.clone(),
+        skip_wait: controller.skip_wait.clone(),
+        finishing: finishing.clone(),
+        repeat: config.job.repeat,
+        serial: Mutex::new(serial),
+        decel: Duration::from_millis(config.shutdown.decel_ms),
+        soft_reset: config.shutdown.soft_reset,
+        done: done_rx,
+    };
+
+    thread::spawn(move || {
+        let result = run(&config, &selection, &controller, &mut signal, &finishing);
+        let _ = done_tx.send(result);
+    });
+
+    handle
+}
+
+fn run(
+    config: &CncConfig,
+    selection: &Selection,
+    controller: &Controller,
+    signal: &mut StartSignal,
+    finishing: &AtomicBool,
+) -> Result<(), String> {
+    // `--from` only applies to the first sequence, later sequences run every selected step
+    let mut from = selection.start(&config.steps);
+    let mut count = config.counter.start;
+    // Alarm recoveries per code since the last completed sequence
+    let mut recoveries: HashMap<u8, u32> = HashMap::new();
+    let snapshot = Snapshot::take(config, controller);
+    let mut sequences = 0;
+    let mut sequence_report: Option<SequenceReport> = None;
+
+    let result = 'job: {
+        'sequence: while controller.running.load(Ordering::Relaxed) {
+            let mut vars = TemplateVars::new(config, count);
+            let report = sequence_report.insert(SequenceReport::new(config, sequences + 1, &vars));
+
+            let start = std::mem::take(&mut from);
+            let mut first = true;
+
+            for (i, step) in config.steps.iter().enumerate() {
+                if i < start || !selection.includes(step) {
+                    continue;
+                }
+                json_log::set_step(Some(i + 1));
+
+                // A finish request while waiting to start a sequence ends the job straight away
+                let cancel = first.then_some(finishing);
+                if std::mem::take(&mut first) || step.should_wait() {
+                    match step.wait(controller, config, signal, cancel) {
+                        Ok(true) => {}
+                        Ok(false) => break 'job Ok(()),
+                        Err(error) => break 'job Err(format!("Step {} failed: {}", i + 1, error)),
+                    }
+                }
+
+                info!(
+                    "Executing step {} (timestamp: {}, part: {})",
+                    i + 1,
+                    vars.timestamp,
+                    vars.count
+                );
+
+                vars.step = step.label(i + 1);
+                status::step(i + 1, &vars.step, step.step.kind());
+                status::set(State::Running);
+                report.start_step();
+                let started = Instant::now();
+                let result = step.execute(controller, &mut vars, config, signal);
+
+                let kind = step.step.kind();
+                match result {
+                    Ok(()) => {
+                        info!("Step {} completed successfully", i + 1);
+                        report.step(i + 1, &vars.step, kind, "completed", started, None);
+                    }
+                    Err(e) if controller.stopping.load(Ordering::Relaxed) => {
+                        warn!("Step {} interrupted: {}", i + 1, e);
+                        report.step(
+                            i + 1,
+                            &vars.step,
+                            kind,
+                            "interrupted",
+                            started,
+                            Some(e.to_string()),
+                        );
+                        break 'job Ok(());
+                    }
+                    Err(e) => {
+                        report.step(
+                            i + 1,
+                            &vars.step,
+                            kind,
+                            "failed",
+                            started,
+                            Some(e.to_string()),
+                        );
+
+                        let alarm = controller.alarm.load(Ordering::Relaxed);
+                        if alarm == 0 {
+                            break 'job Err(format!("Step {} failed: {}", i + 1, e));
+                        }
+                        report.alarm(alarm);
+                        notify::event(NotifyEvent::Alarm, &format!("Step {} failed: {}", i + 1, e));
+
+                        error!("Step {} failed: {}", i + 1, e);
+                        status::set(State::Error);
+                        let attempts = recoveries.entry(alarm).or_default();
+                        if let Err(error) =
+                            alarm::recover(controller, config, signal, alarm, *attempts)
+                        {
+                            break 'job Err(format!("Step {} failed: {}", i + 1, error));
+                        }
+                        *attempts += 1;
+
+                        warn!("Abandoning sequence after alarm recovery");
+                        if let Some(report) = sequence_report.take() {
+                            report.finish("abandoned", Some(&e.to_string()));
+                        }
+                        count = vars.count;
+                        continue 'sequence;
+                    }
+                }
+            }
+
+            recoveries.clear();
+            count = vars.count;
+            if config.counter.per_sequence {
+                count += 1;
+            }
+
+            info!(
+                "Sequence complete (timestamp: {}, next part: {})",
+                vars.timestamp, count
+            );
+            if let Some(report) = sequence_report.take() {
+                report.finish("completed", None);
+            }
+            notify::event(
+                NotifyEvent::Sequence,
+                &format!("Sequence {} complete, part {}", sequences + 1, vars.count),
+            );
+
+            sequences += 1;
+            let done = match config.job.repeat {
+                Repeat::Once => true,
+                Repeat::Count(count) => sequences >= count,
+                Repeat::Forever => false,
+                Repeat::UntilSignal => finishing.load(Ordering::Relaxed),
+            };
+            if done {
+                info!("Job complete after {} sequence(s)", sequences);
+                break;
+            }
+        }
+
+        Ok(())
+    };
+
+    json_log::set_step(None);
+    steps::stop_background();
+
+    // The sequence the job ended in, unless it had already finished
+    if let Some(report) = sequence_report {
+        match &result {
+            Ok(()) => report.finish("interrupted", None),
+            Err(error) => report.finish("failed", Some(error)),
+        }
+    }
+
+    status::set(match result {
+        Ok(()) => State::Idle,
+        Err(_) => State::Error,
+    });
+
+    let vars = TemplateVars::new(config, count);
+    match &result {
+        Ok(()) => {
+            if let Some(hook) = &config.job.hooks.on_success {
+                steps::run_hook("on_success", hook, &vars, None);
+            }
+        }
+        Err(error) => {
+            if let Some(hook) = &config.job.hooks.on_failure {
+                steps::run_hook("on_failure", hook, &vars, Some(error));
+            }
+        }
+    }
+
+    match &result {
+        Ok(()) if controller.stopping.load(Ordering::Relaxed) => {
+            notify::event(NotifyEvent::Success, "Job stopped")
+        }
+        Ok(()) => notify::event(NotifyEvent::Success, "Job complete"),
+        Err(error) => notify::event(NotifyEvent::Failure, &format!("Job failed: {}", error)),
+    }
+
+    info!("Run summary:");
+    snapshot.log();
+    if let Ok(messages) = controller.messages.lock() {
+        let mut messages: Vec<_> = messages.iter().collect();
+        messages.sort();
+        for (text, count) in messages {
+            info!("Controller message '{}' received {} times", text, count);
+        }
+    }
+    let (passed, failed, not_assessed) = report::qc_totals();
+    if passed + failed + not_assessed > 0 {
+        info!(
+            "QC checkpoints: {} passed, {} failed, {} not assessed",
+            passed, failed, not_assessed
+        );
+    }
+    info!(
+        "Batch total: {} parts completed",
+        count - config.counter.start
+    );
+
+    notify::flush();
+
+    result
+}
+
+/// Blocks until the signal input triggers, returns false if the job is stopped first.
+/// The pendant jogs the machine while waiting
+pub fn wait_for_signal(
+    controller: &Controller,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+) -> Result<bool, String> {
+    wait_for_signal_or(controller, config, signal, None)
+}
+
+/// Like `wait_for_signal`, but also returns false once `cancel` is set
+pub fn wait_for_signal_or(
+    controller: &Controller,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+    cancel: Option<&AtomicBool>,
+) -> Result<bool, String> {
+    status::set(State::Waiting);
+    pendant::start();
+    signal.reset();
+    // A skip requested before the wait began was meant for something else
+    controller.skip_wait.store(false, Ordering::Relaxed);
+    loop {
+        if signal.poll(Duration::from_millis(100))?
+            || controller.skip_wait.swap(false, Ordering::Relaxed)
+        {
+            pendant::stop(controller)?;
+            status::set(State::Running);
+            return Ok(true);
+        }
+        pendant::jog(controller, config);
+        if controller.stopping.load(Ordering::Relaxed)
+            || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+        {
+            return Ok(false);
+        }
+    }
+}
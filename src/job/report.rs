@@ -0,0 +1,258 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::{DateTime, Local};
+use log::{info, warn};
+
+use crate::config::{CncConfig, TemplateVars, apply_template, expand_path};
+use crate::json::{optional_string, string};
+
+#[derive(Default)]
+struct StepCounts {
+    lines: Option<usize>,
+    probes: Option<(usize, usize)>,
+    // Line of each QC checkpoint and its verdict, `None` if the operator wasn't asked
+    qc: Vec<(usize, Option<bool>)>,
+}
+
+// Filled in by the running step, steps only run on the job thread
+static COUNTS: Mutex<StepCounts> = Mutex::new(StepCounts {
+    lines: None,
+    probes: None,
+    qc: Vec::new(),
+});
+
+/// QC verdicts over the whole run: passed, failed and not assessed
+static QC_TOTALS: Mutex<(usize, usize, usize)> = Mutex::new((0, 0, 0));
+
+/// Adds lines streamed by a G-code program to the running step's report
+pub fn record_lines(lines: usize) {
+    let mut counts = lock_counts();
+    *counts.lines.get_or_insert(0) += lines;
+}
+
+/// Adds probe results, and how many of them didn't make contact, to the running step's report
+pub fn record_probes(probes: usize, missed: usize) {
+    let mut counts = lock_counts();
+    let (total, total_missed) = counts.probes.get_or_insert((0, 0));
+    *total += probes;
+    *total_missed += missed;
+}
+
+/// Adds a QC checkpoint's verdict to the running step's report and the run's totals
+pub fn record_qc(line: usize, verdict: Option<bool>) {
+    lock_counts().qc.push((line, verdict));
+
+    let mut totals = match QC_TOTALS.lock() {
+        Ok(totals) => totals,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    match verdict {
+        Some(true) => totals.0 += 1,
+        Some(false) => totals.1 += 1,
+        None => totals.2 += 1,
+    }
+}
+
+/// QC verdicts recorded so far in the run, passed, failed and not assessed
+pub fn qc_totals() -> (usize, usize, usize) {
+    match QC_TOTALS.lock() {
+        Ok(totals) => *totals,
+        Err(poisoned) => *poisoned.into_inner(),
+    }
+}
+
+fn verdict_name(verdict: Option<bool>) -> &'static str {
+    match verdict {
+        Some(true) => "passed",
+        Some(false) => "failed",
+        None => "not_assessed",
+    }
+}
+
+fn lock_counts() -> std::sync::MutexGuard<'static, StepCounts> {
+    match COUNTS.lock() {
+        Ok(counts) => counts,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+struct StepReport {
+    number: usize,
+    label: String,
+    kind: &'static str,
+    status: &'static str,
+    duration_s: f64,
+    error: Option<String>,
+    counts: StepCounts,
+}
+
+/// What happened in one sequence of the job, written as JSON for tracking batches
+pub struct SequenceReport {
+    path: Option<String>,
+    job: Option<String>,
+    sequence: u32,
+    part: u32,
+    timestamp: String,
+    started_at: DateTime<Local>,
+    started: Instant,
+    steps: Vec<StepReport>,
+    alarms: Vec<u8>,
+}
+
+impl SequenceReport {
+    pub fn new(config: &CncConfig, sequence: u32, vars: &TemplateVars) -> Self {
+        Self {
+            path: config
+                .job
+                .report
+                .as_ref()
+                .map(|path| apply_template(&expand_path(path), vars)),
+            job: config.job.name.clone(),
+            sequence,
+            part: vars.count,
+            timestamp: vars.timestamp.clone(),
+            started_at: Local::now(),
+            started: Instant::now(),
+            steps: Vec::new(),
+            alarms: Vec::new(),
+        }
+    }
+
+    /// Clears the counts for the step about to run
+    pub fn start_step(&self) {
+        *lock_counts() = StepCounts::default();
+    }
+
+    /// Records the step that just ran, `status` is `completed`, `failed` or `interrupted`
+    pub fn step(
+        &mut self,
+        number: usize,
+        label: &str,
+        kind: &'static str,
+        status: &'static str,
+        started: Instant,
+        error: Option<String>,
+    ) {
+        self.steps.push(StepReport {
+            number,
+            label: label.to_string(),
+            kind,
+            status,
+            duration_s: started.elapsed().as_secs_f64(),
+            error,
+            counts: std::mem::take(&mut *lock_counts()),
+        });
+    }
+
+    pub fn alarm(&mut self, code: u8) {
+        self.alarms.push(code);
+    }
+
+    /// Writes the report if one is configured. A sequence stopped before any step ran has
+    /// nothing to report. Failing to write it only warns, the job itself is unaffected
+    pub fn finish(self, status: &str, error: Option<&str>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if self.steps.is_empty() && error.is_none() {
+            return;
+        }
+
+        match self.write(path, status, error) {
+            Ok(()) => info!("Sequence report written to '{}'", path),
+            Err(error) => warn!("Failed to write sequence report '{}': {}", path, error),
+        }
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        status: &str,
+        error: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let qc: Vec<String> = step
+                    .counts
+                    .qc
+                    .iter()
+                    .map(|(line, verdict)| {
+                        format!(
+                            "{{\"line\": {}, \"verdict\": {}}}",
+                            line,
+                            string(verdict_name(*verdict))
+                        )
+                    })
+                    .collect();
+                format!(
+                    "    {{\"number\": {}, \"name\": {}, \"type\": {}, \"status\": {}, \"duration_s\": {:.3}, \"error\": {}, \"lines_streamed\": {}, \"probes\": {}, \"qc\": [{}]}}",
+                    step.number,
+                    string(&step.label),
+                    string(step.kind),
+                    string(step.status),
+                    step.duration_s,
+                    optional_string(step.error.as_deref()),
+                    step.counts
+                        .lines
+                        .map_or("null".to_string(), |lines| lines.to_string()),
+                    step.counts.probes.map_or("null".to_string(), |(probes, missed)| {
+                        format!("{{\"total\": {}, \"missed\": {}}}", probes, missed)
+                    }),
+                    qc.join(", "),
+                )
+            })
+            .collect();
+        let lines: usize = self.steps.iter().filter_map(|step| step.counts.lines).sum();
+        let probes: usize = self
+            .steps
+            .iter()
+            .filter_map(|step| step.counts.probes)
+            .map(|(probes, _)| probes)
+            .sum();
+        let qc_failed = self
+            .steps
+            .iter()
+            .flat_map(|step| &step.counts.qc)
+            .filter(|(_, verdict)| *verdict == Some(false))
+            .count();
+        let alarms: Vec<String> = self.alarms.iter().map(|code| code.to_string()).collect();
+
+        let mut json = String::from("{\n");
+        json += &format!("  \"job\": {},\n", optional_string(self.job.as_deref()));
+        json += &format!("  \"sequence\": {},\n", self.sequence);
+        json += &format!("  \"part\": {},\n", self.part);
+        json += &format!("  \"timestamp\": {},\n", string(&self.timestamp));
+        json += &format!(
+            "  \"started\": {},\n",
+            string(&self.started_at.to_rfc3339())
+        );
+        json += &format!(
+            "  \"duration_s\": {:.3},\n",
+            self.started.elapsed().as_secs_f64()
+        );
+        json += &format!("  \"status\": {},\n", string(status));
+        json += &format!("  \"error\": {},\n", optional_string(error));
+        json += &format!("  \"alarms\": [{}],\n", alarms.join(", "));
+        json += &format!("  \"lines_streamed\": {},\n", lines);
+        json += &format!("  \"probes\": {},\n", probes);
+        json += &format!("  \"qc_failed\": {},\n", qc_failed);
+        if steps.is_empty() {
+            json += "  \"steps\": []\n";
+        } else {
+            json += &format!("  \"steps\": [\n{}\n  ]\n", steps.join(",\n"));
+        }
+        json += "}\n";
+
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
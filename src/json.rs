@@ -0,0 +1,24 @@
+// JSON is only ever written, one small document at a time, so it's formatted by hand
+
+/// Quotes and escapes `text` as a JSON string
+pub fn string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if c.is_control() => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// `text` as a JSON string, or `null`
+pub fn optional_string(text: Option<&str>) -> String {
+    text.map_or("null".to_string(), string)
+}
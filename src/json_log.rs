@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{Local, SecondsFormat};
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+
+use crate::json::string;
+
+// Number of the top-level step running, 0 between steps
+static STEP: AtomicUsize = AtomicUsize::new(0);
+
+/// Tags the following log records with the step running, or none between steps
+pub fn set_step(step: Option<usize>) {
+    STEP.store(step.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Writes a JSON object per log record, one per line, for log shippers like Promtail or Filebeat
+pub struct JsonLogger {
+    level: LevelFilter,
+    connection: String,
+    file: Mutex<File>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, connection: &str, file: File) -> Box<Self> {
+        Box::new(Self {
+            level,
+            connection: connection.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let step = match STEP.load(Ordering::Relaxed) {
+            0 => "null".to_string(),
+            step => step.to_string(),
+        };
+        let line = format!(
+            "{{\"timestamp\": {}, \"level\": {}, \"target\": {}, \"connection\": {}, \"step\": {}, \"message\": {}}}",
+            string(&Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)),
+            string(&record.level().to_string().to_lowercase()),
+            string(record.target()),
+            string(&self.connection),
+            step,
+            string(&record.args().to_string())
+        );
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // There's nowhere left to report a failure to write the log
+        let _ = writeln!(file, "{}", line);
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
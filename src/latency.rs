@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::config::CncConfig;
+use crate::controller::Controller;
+use crate::controller::serial::{buffered_stream, checked_stream};
+
+// Comments are acknowledged without doing anything, and pad lines out to a typical G-code length
+const THROUGHPUT_LINE: &str = "(cnc-ctrl throughput test, this line is ignored by Grbl)";
+const THROUGHPUT_LINES_PER_SAMPLE: usize = 10;
+
+pub fn measure(
+    config: &CncConfig,
+    controller: &Controller,
+    samples: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if samples == 0 {
+        return Err("Number of samples must be at least 1".into());
+    }
+
+    info!("Measuring round-trip latency with {} '$G' queries", samples);
+
+    controller.flush_responses();
+
+    let mut latencies = Vec::with_capacity(samples);
+    for _ in 0..samples {
+        let started = Instant::now();
+        checked_stream(controller, vec!["$G"], &config.grbl)?;
+        latencies.push(started.elapsed());
+    }
+    latencies.sort();
+
+    let percentile = |p: usize| latencies[((latencies.len() - 1) * p).div_ceil(100)];
+    info!(
+        "Latency: min {}, p50 {}, p90 {}, p99 {}, max {}",
+        millis(latencies[0]),
+        millis(percentile(50)),
+        millis(percentile(90)),
+        millis(percentile(99)),
+        millis(latencies[latencies.len() - 1])
+    );
+
+    let lines = samples * THROUGHPUT_LINES_PER_SAMPLE;
+    info!("Measuring throughput with {} streamed lines", lines);
+
+    let started = Instant::now();
+    buffered_stream(
+        controller,
+        std::iter::repeat_n(THROUGHPUT_LINE, lines),
+        &config.grbl,
+        (),
+    )?;
+    let elapsed = started.elapsed().as_secs_f64();
+    let bytes = lines * (THROUGHPUT_LINE.len() + 1);
+
+    info!(
+        "Throughput: {:.0} lines/s, {:.0} bytes/s ({:.0}% of {} baud)",
+        lines as f64 / elapsed,
+        bytes as f64 / elapsed,
+        // 10 bits per byte on the wire with 8N1 framing
+        bytes as f64 * 10.0 / elapsed / config.serial.baudrate as f64 * 100.0,
+        config.serial.baudrate
+    );
+
+    Ok(())
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2} ms", duration.as_secs_f64() * 1000.0)
+}
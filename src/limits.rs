@@ -0,0 +1,102 @@
+use std::fmt;
+
+use crate::config::{Axis, AxisLimits, MachineConfig};
+use crate::display;
+
+#[derive(Debug)]
+pub enum LimitError {
+    TravelError {
+        axis: Axis,
+        target: f64,
+        min: f64,
+        max: f64,
+    },
+    FeedError {
+        axis: Axis,
+        feed: f64,
+        max_feed: f64,
+    },
+}
+
+impl std::error::Error for LimitError {}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::TravelError {
+                axis,
+                target,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} target {} is outside of the machine's travel ({} to {})",
+                axis,
+                display::length(*target),
+                display::length(*min),
+                display::length(*max)
+            ),
+            LimitError::FeedError {
+                axis,
+                feed,
+                max_feed,
+            } => write!(
+                f,
+                "Feed {} exceeds the {} axis' maximum of {}",
+                display::feed(*feed),
+                axis,
+                display::feed(*max_feed)
+            ),
+        }
+    }
+}
+
+impl MachineConfig {
+    pub fn axis(&self, axis: Axis) -> Option<&AxisLimits> {
+        match axis {
+            Axis::X => self.x.as_ref(),
+            Axis::Y => self.y.as_ref(),
+            Axis::Z => self.z.as_ref(),
+        }
+    }
+
+    // Targets are in machine coordinates and mm, axes without limits are not checked
+    pub fn check(&self, targets: &[(Axis, f64)], feed: f64) -> Result<(), LimitError> {
+        for &(axis, target) in targets {
+            let Some(limits) = self.axis(axis) else {
+                continue;
+            };
+
+            let min = self.units.to_mm(limits.min);
+            let max = self.units.to_mm(limits.max);
+            if target < min || target > max {
+                return Err(LimitError::TravelError {
+                    axis,
+                    target,
+                    min,
+                    max,
+                });
+            }
+
+            if let Some(max_feed) = limits.max_feed.map(|feed| self.units.to_mm(feed))
+                && feed > max_feed
+            {
+                return Err(LimitError::FeedError {
+                    axis,
+                    feed,
+                    max_feed,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn component(position: (f32, f32, f32), axis: Axis) -> f64 {
+    match axis {
+        Axis::X => position.0 as f64,
+        Axis::Y => position.1 as f64,
+        Axis::Z => position.2 as f64,
+    }
+}
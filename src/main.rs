@@ -1,144 +1,615 @@
+mod alarm;
+mod api;
+mod button;
+mod cli;
+mod compatibility;
 mod config;
 mod controller;
+mod display;
+mod flash;
+mod gpio;
+mod init;
+mod interlock;
+mod job;
+mod json;
+mod json_log;
+mod latency;
+mod limits;
+mod maintenance;
+mod manual;
+mod mqtt;
+mod notify;
+mod pendant;
+mod plan;
+mod ports;
+mod prompt;
+mod report;
+mod requires;
+mod selection;
+mod snapshot;
+mod start_signal;
+mod status;
 mod steps;
+mod traffic;
+mod tui;
+mod validate;
 
 use std::fs::{self, File};
-use std::io::Write;
-use std::sync::atomic::Ordering;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use chrono::Local;
 use log::{LevelFilter, error, info, warn};
-use rppal::gpio::{Gpio, InputPin, Trigger};
+use serialport::SerialPort;
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
 use simplelog::*;
 
-use config::{CncConfig, apply_template, expand_path};
+use button::Button;
+use cli::{Command, RunJob};
+use config::{
+    CncConfig, Edge, Level, LogFormat, LogLevel, TemplateVars, apply_template, expand_path,
+};
 use controller::Controller;
+use controller::command::Realtime;
+use gpio::Watch;
+use json_log::JsonLogger;
+use selection::Selection;
+use start_signal::StartSignal;
+use traffic::Tap;
+use tui::TuiLogger;
+
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(5);
+const BANNER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// How much of the discarded output to include when no banner is recognised
+const BANNER_DUMP_BYTES: usize = 512;
+const DEFAULT_LATENCY_SAMPLES: usize = 100;
 
 struct GpioInputs {
-    signal: InputPin,
+    signal: StartSignal,
+    button: Option<Button>,
+    pause: Option<Box<dyn Watch>>,
+    door: Option<Box<dyn Watch>>,
 }
 
 fn setup_gpio(config: &CncConfig) -> Result<GpioInputs, Box<dyn std::error::Error>> {
-    let gpio = Gpio::new()?;
+    let gpio = gpio::open()?;
+
+    // Telling presses apart needs both edges
+    let input = &config.inputs.signal;
+    let signal = gpio.watch(
+        input.pin,
+        input.pull,
+        match input.presses {
+            Some(_) => Edge::Both,
+            None => input.trigger_edge(),
+        },
+        Duration::from_millis(input.debounce_ms),
+    )?;
+    let (signal, button) = match &input.presses {
+        Some(presses) => {
+            let (button, signal) = Button::new(signal, input.active, presses);
+            (signal, Some(button))
+        }
+        None => (StartSignal::Gpio(signal), None),
+    };
+
+    let pause = match &config.inputs.pause {
+        Some(input) => Some(gpio.watch(
+            input.pin,
+            input.pull,
+            input.trigger_edge(),
+            Duration::from_millis(input.debounce_ms),
+        )?),
+        None => None,
+    };
+
+    // Both edges, to tell the door opening from closing
+    let door = match &config.inputs.door {
+        Some(input) => Some(gpio.watch(
+            input.pin,
+            input.pull,
+            Edge::Both,
+            Duration::from_millis(input.debounce_ms),
+        )?),
+        None => None,
+    };
+
+    Ok(GpioInputs {
+        signal,
+        button,
+        pause,
+        door,
+    })
+}
+
+// Calls `on_edge` for every edge of `input` on its own thread, until the input fails
+fn watch_input(
+    name: &'static str,
+    mut input: Box<dyn Watch>,
+    mut on_edge: impl FnMut(Edge) + Send + 'static,
+) {
+    thread::spawn(move || {
+        loop {
+            match input.poll(None) {
+                Ok(Some(edge)) => on_edge(edge),
+                Ok(None) => {}
+                Err(error) => {
+                    error!("{} input stopped: {}", name, error);
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// Without GPIO, e.g. on the bench, the start signal comes from the configured fallback instead
+fn setup_inputs(config: &CncConfig) -> Result<GpioInputs, Box<dyn std::error::Error>> {
+    let error = match setup_gpio(config) {
+        Ok(inputs) => return Ok(inputs),
+        Err(error) => error,
+    };
+
+    let fallback = config.inputs.fallback.as_ref().ok_or(error.to_string())?;
+    let signal = StartSignal::from(fallback);
+
+    warn!(
+        "GPIO unavailable ({}), using {} as the start signal",
+        error, signal
+    );
+    if config.inputs.pause.is_some() {
+        warn!("Pause input disabled, send SIGUSR1 to pause and SIGUSR2 to resume instead");
+    }
+    if config.inputs.door.is_some() {
+        warn!("Door input disabled, opening the door won't stop the machine");
+    }
+
+    Ok(GpioInputs {
+        signal,
+        button: None,
+        pause: None,
+        door: None,
+    })
+}
+
+fn open_serial(config: &CncConfig) -> Result<Box<dyn SerialPort>, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let max_wait = Duration::from_millis(config.serial.connect.max_wait_ms);
+    let mut backoff = Duration::from_millis(config.serial.connect.backoff_ms);
+
+    if config.serial.connect.wait_for_device {
+        while !std::path::Path::new(&config.serial.port).exists() {
+            if started.elapsed() >= max_wait {
+                return Err(
+                    format!("Device '{}' did not appear in time", config.serial.port).into(),
+                );
+            }
+
+            info!("Waiting for device '{}'...", config.serial.port);
+            thread::sleep(backoff);
+        }
+    }
+
+    let serial = loop {
+        match serialport::new(&config.serial.port, config.serial.baudrate)
+            .timeout(Duration::from_millis(config.serial.timeout_ms))
+            .open()
+        {
+            Ok(serial) => break serial,
+            Err(error) if started.elapsed() + backoff < max_wait => {
+                warn!(
+                    "Failed to open '{}': {}, retrying in {} ms",
+                    config.serial.port,
+                    error,
+                    backoff.as_millis()
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+            }
+            Err(error) => return Err(error.into()),
+        }
+    };
+
+    match &config.serial.dump_traffic {
+        Some(path) => {
+            let vars = TemplateVars::new(config, config.counter.start);
+            let dump = create_log_file(path, &vars)?;
+            Ok(Box::new(Tap::new(serial, dump)))
+        }
+        None => Ok(serial),
+    }
+}
+
+// Discard anything a serial bridge prints while booting, until Grbl's banner or a status report
+fn wait_for_banner(
+    serial: &mut Box<dyn SerialPort>,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let timeout = Duration::from_millis(config.serial.connect.banner_timeout_ms);
+    let mut last_poll: Option<Instant> = None;
+    let mut discarded: Vec<u8> = Vec::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut byte = [0; 1];
+
+    info!("Waiting for Grbl banner...");
+
+    while started.elapsed() < timeout {
+        // Grbl behind a bridge may have booted long ago, so ask for a status report too
+        if last_poll.is_none_or(|poll| poll.elapsed() >= BANNER_POLL_INTERVAL) {
+            serial.write_all(b"?")?;
+            last_poll = Some(Instant::now());
+        }
+
+        match serial.read(&mut byte) {
+            Ok(0) => continue,
+            Ok(_) if byte[0] == b'\n' => {
+                let text = String::from_utf8_lossy(&line);
+                let text = text.trim();
+
+                if text.starts_with("Grbl") || (text.starts_with('<') && text.ends_with('>')) {
+                    if !discarded.is_empty() {
+                        info!("Discarded {} bytes before Grbl responded", discarded.len());
+                    }
+                    info!("Grbl responded: {}", text);
+                    return Ok(());
+                }
+
+                discarded.append(&mut line);
+                discarded.push(byte[0]);
+            }
+            Ok(_) => line.push(byte[0]),
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
 
-    let signal = gpio.get(config.inputs.signal.pin)?.into_input_pullup();
+    discarded.append(&mut line);
+    let dump = &discarded[discarded.len().saturating_sub(BANNER_DUMP_BYTES)..];
+    warn!(
+        "Received {} bytes without a Grbl banner or status report, last {}: {:?}",
+        discarded.len(),
+        dump.len(),
+        String::from_utf8_lossy(dump)
+    );
 
-    Ok(GpioInputs { signal })
+    Err(format!(
+        "No Grbl banner or status report within {} ms",
+        timeout.as_millis()
+    )
+    .into())
 }
 
-fn setup_logging(config: &CncConfig) -> Result<(), Box<dyn std::error::Error>> {
+fn create_log_file(path: &str, vars: &TemplateVars) -> Result<File, Box<dyn std::error::Error>> {
+    let expanded_path = expand_path(path);
+    let templated_path = apply_template(&expanded_path, vars);
+
+    if let Some(parent) = std::path::Path::new(&templated_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let log_file = File::create(&templated_path)
+        .map_err(|e| format!("Failed to create log file '{}': {}", templated_path, e))?;
+
+    Ok(log_file)
+}
+
+// For commands that don't start a log file
+fn setup_terminal_logging() -> Result<(), String> {
+    TermLogger::init(
+        LevelFilter::Info,
+        Config::default(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    )
+    .map_err(|error| format!("Failed to setup logging: {}", error))
+}
+
+fn setup_logging(config: &CncConfig, tui: bool) -> Result<(), Box<dyn std::error::Error>> {
     let log_level = if config.logs.verbose {
         LevelFilter::Debug
     } else {
         LevelFilter::Info
     };
 
-    if config.logs.save {
-        let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
-
-        let expanded_path = expand_path(&config.logs.path);
-        let templated_path = apply_template(&expanded_path, &timestamp);
+    let vars = TemplateVars::new(config, config.counter.start);
 
-        if let Some(parent) = std::path::Path::new(&templated_path).parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        let log_file = File::create(&templated_path)
-            .map_err(|e| format!("Failed to create log file '{}': {}", templated_path, e))?;
-
-        CombinedLogger::init(vec![
-            TermLogger::new(
-                log_level,
-                Config::default(),
-                TerminalMode::Mixed,
-                ColorChoice::Auto,
-            ),
-            WriteLogger::new(log_level, Config::default(), log_file),
-        ])?;
+    // The terminal UI shows the log in a pane of its own
+    let mut loggers: Vec<Box<dyn SharedLogger>> = if tui {
+        vec![TuiLogger::new(log_level)]
     } else {
-        TermLogger::init(
+        vec![TermLogger::new(
             log_level,
             Config::default(),
             TerminalMode::Mixed,
             ColorChoice::Auto,
-        )?;
+        )]
+    };
+
+    if config.logs.save {
+        let log_file = create_log_file(&config.logs.path, &vars)?;
+        loggers.push(match config.logs.format {
+            LogFormat::Text => WriteLogger::new(log_level, Config::default(), log_file),
+            LogFormat::Json => JsonLogger::new(log_level, &config.serial.port, log_file),
+        });
+    }
+
+    // Concise log for operators, without the wire traffic and state dumps logged when verbose
+    if let Some(operator) = &config.logs.operator {
+        let log_file = create_log_file(&operator.path, &vars)?;
+        loggers.push(WriteLogger::new(
+            operator.level.into(),
+            Config::default(),
+            log_file,
+        ));
     }
 
+    CombinedLogger::init(loggers)?;
+
     Ok(())
 }
 
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+        }
+    }
+}
+
 fn main() -> Result<(), String> {
-    let config =
-        CncConfig::load().map_err(|error| format!("Failed to load configuration: {}", error))?;
+    let (overrides, config_path, command) = cli::parse();
+    if let Some(path) = config_path {
+        CncConfig::set_path(&path);
+    }
+
+    // There's no config to load yet
+    if let Command::Init(options) = &command {
+        setup_terminal_logging()?;
+
+        let config_path = CncConfig::get_config_path()
+            .map_err(|error| format!("Failed to find config directory: {}", error))?;
+        let directory = Path::new(&config_path)
+            .parent()
+            .ok_or("Failed to find config directory")?;
+
+        return init::init(directory, options)
+            .map_err(|error| format!("Failed to create config: {}", error));
+    }
+
+    // Nor is one needed to find the port to configure
+    if let Command::Ports { probe, baudrate } = command {
+        setup_terminal_logging()?;
+
+        return ports::list(probe, baudrate)
+            .map_err(|error| format!("Failed to list serial ports: {}", error));
+    }
+
+    // A maintenance job is run like the config's job, in its place
+    let (job, command) = match command {
+        Command::Maintenance { name: None } => {
+            setup_terminal_logging()?;
+            maintenance::list();
+            return Ok(());
+        }
+        Command::Maintenance { name: Some(name) } => {
+            let job = maintenance::job(&name)
+                .map_err(|error| format!("Failed to start maintenance: {}", error))?;
+            (Some(job), Command::Run(RunJob::default()))
+        }
+        command => (None, command),
+    };
+
+    let config = CncConfig::load(&overrides, job.as_deref())
+        .map_err(|error| format!("Failed to load configuration: {}", error))?;
+    display::init(&config.display);
+    gpio::init(&config.gpio);
+
+    match &command {
+        // Don't start a new log for the report, so the last run's logs are the newest
+        Command::Report { output } => {
+            setup_terminal_logging()?;
+
+            return report::report(&config, output.as_deref())
+                .map_err(|error| format!("Failed to create bug report: {}", error));
+        }
+        // Checking a job only reads files, so it doesn't start a new log either
+        Command::Check { file: None } => {
+            setup_terminal_logging()?;
+
+            return plan::check(&config).map_err(|error| format!("Invalid job: {}", error));
+        }
+        // Nor do a status query and a file check, which don't move the machine
+        Command::Status | Command::Check { file: Some(_) } => setup_terminal_logging()?,
+        Command::Run(run) => setup_logging(&config, run.args.tui)
+            .map_err(|error| format!("Failed to setup logging: {}", error))?,
+        _ => setup_logging(&config, false)
+            .map_err(|error| format!("Failed to setup logging: {}", error))?,
+    }
+
+    let config = CncConfig::load(&overrides, job.as_deref())
+        .map_err(|error| format!("Failed to load configuration: {}", error))?;
+
+    if let Command::Flash { firmware } = &command {
+        return flash::flash(&config, firmware)
+            .map_err(|error| format!("Failed to flash firmware: {}", error));
+    }
+
+    // One-off commands don't run the job, so only its connection settings need to be valid
+    let selection = match &command {
+        Command::Run(run) => {
+            config
+                .validate()
+                .map_err(|error| format!("Invalid configuration: {}", error))?;
 
-    setup_logging(&config).map_err(|error| format!("Failed to setup logging: {}", error))?;
+            let run = &run.args;
+            let selection = Selection::new(run.only.clone(), run.skip.clone(), run.from.clone());
+            selection
+                .check(&config.steps)
+                .map_err(|error| format!("Invalid step selection: {}", error))?;
 
-    let config =
-        CncConfig::load().map_err(|error| format!("Failed to load configuration: {}", error))?;
+            requires::wait_for(&config.requires)
+                .map_err(|error| format!("Startup requirement not met: {}", error))?;
 
-    let serial = serialport::new(&config.serial.port, config.serial.baudrate)
-        .timeout(Duration::from_millis(config.serial.timeout_ms))
-        .open()
+            Some(selection)
+        }
+        _ => None,
+    };
+
+    let mut serial = open_serial(&config)
         .map_err(|error| format!("Failed to open serial connection: {}", error))?;
-    let mut serial_clone = serial
+    if config.serial.connect.wait_for_banner {
+        wait_for_banner(&mut serial, &config)
+            .map_err(|error| format!("Failed to connect to Grbl: {}", error))?;
+    }
+    let job_serial = serial
         .try_clone()
         .map_err(|error| format!("Failed to clone serial connection: {}", error))?;
 
     let mut controller = Controller::new();
-    let controller_running = controller.running.clone();
-    controller.start(serial, config.logs.verbose);
+    controller.start(
+        serial,
+        config.logs.verbose,
+        Duration::from_millis(config.logs.repeat_window_ms),
+    );
 
-    ctrlc::set_handler(move || {
-        warn!("Shutting down...");
+    let (Command::Run(run), Some(selection)) = (&command, selection) else {
+        let result = match &command {
+            Command::Latency { samples } => latency::measure(
+                &config,
+                &controller,
+                samples.unwrap_or(DEFAULT_LATENCY_SAMPLES),
+            )
+            .map_err(|error| format!("Failed to measure latency: {}", error)),
+            Command::Status => manual::status(&controller)
+                .map_err(|error| format!("Failed to read status: {}", error)),
+            Command::Check { file: Some(file) } => manual::check(file, &controller, &config)
+                .map_err(|error| format!("Failed to check '{}': {}", file, error)),
+            Command::Console => manual::console(&controller, &config)
+                .map_err(|error| format!("Console failed: {}", error)),
+            Command::Monitor { interval_ms } => {
+                let stop = Arc::new(AtomicBool::new(false));
+                let interrupt = stop.clone();
+                ctrlc::set_handler(move || interrupt.store(true, Ordering::Relaxed))
+                    .map_err(|error| error.into())
+                    .and_then(|_| {
+                        manual::monitor(&controller, Duration::from_millis(*interval_ms), &stop)
+                    })
+                    .map_err(|error| format!("Failed to monitor: {}", error))
+            }
+            Command::Jog(args) => manual::stop_on_interrupt(job_serial, Realtime::JogCancel)
+                .map_err(|error| error.into())
+                .and_then(|_| manual::jog(args, &controller, &config))
+                .map_err(|error| format!("Failed to jog: {}", error)),
+            Command::Home => manual::stop_on_interrupt(job_serial, Realtime::SoftReset)
+                .map_err(|error| error.into())
+                .and_then(|_| manual::home(&controller, &config))
+                .map_err(|error| format!("Failed to home: {}", error)),
+            _ => Ok(()),
+        };
+        controller.stop();
 
-        controller_running.store(false, Ordering::Relaxed);
-        thread::sleep(Duration::from_secs(2));
+        return result;
+    };
+    let tui = run.args.tui;
 
-        if let Err(error) = serial_clone.write_all(&[0x18]) {
-            error!("Failed to soft reset Grbl: {}", error);
-        }
-    })
-    .map_err(|error| format!("Failed to set up exit handler: {}", error))?;
-
-    let mut gpio_inputs =
-        setup_gpio(&config).map_err(|error| format!("Failed to setup GPIO pins: {}", error))?;
-
-    gpio_inputs
-        .signal
-        .set_interrupt(
-            Trigger::RisingEdge,
-            Some(Duration::from_millis(config.inputs.signal.debounce_ms)),
-        )
-        .map_err(|error| format!("Failed to set signal interrupt: {}", error))?;
-
-    while controller.running.load(Ordering::Relaxed) {
-        let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
-
-        for (i, step) in config.steps.iter().enumerate() {
-            if i == 0 || step.should_wait() {
-                info!("Waiting for start signal...");
-                gpio_inputs
-                    .signal
-                    .poll_interrupt(true, None)
-                    .map_err(|error| format!("Failed to poll signal interrupt: {}", error))?;
-            }
+    let GpioInputs {
+        signal,
+        button,
+        pause,
+        door,
+    } = setup_inputs(&config).map_err(|error| format!("Failed to setup GPIO pins: {}", error))?;
 
-            info!("Executing step {} (timestamp: {})", i + 1, timestamp);
+    if let Some(status) = &config.status {
+        status::init(status);
+    }
+    if let Some(pendant) = &config.pendant {
+        pendant::init(pendant);
+    }
+    if let Some(mqtt) = &config.mqtt {
+        mqtt::init(mqtt, &controller);
+    }
+    if let Some(notify) = &config.notify {
+        notify::init(notify, config.job.name.as_deref());
+    }
+    let api = config
+        .api
+        .as_ref()
+        .map(|api| api::bind(api, &controller))
+        .transpose()?;
 
-            let result = step.execute(&controller, &timestamp, &config);
+    let door_trigger = config
+        .inputs
+        .door
+        .as_ref()
+        .map(|input| (input.active, input.resume_on_close));
+
+    let tui_monitor = tui.then(|| (controller.monitor(), config.job.name.clone()));
+
+    let job = Arc::new(job::start(
+        config, selection, controller, job_serial, signal,
+    ));
+
+    if let Some(api) = api {
+        api.serve(job.clone());
+    }
 
-            match result {
-                Ok(()) => info!("Step {} completed successfully", i + 1),
-                Err(e) => {
-                    return Err(format!("Step {} failed: {}", i + 1, e));
+    let exit_job = job.clone();
+    ctrlc::set_handler(move || exit_job.abort())
+        .map_err(|error| format!("Failed to set up exit handler: {}", error))?;
+
+    // For systemd units and scripts: SIGTERM stops like Ctrl-C, SIGUSR1 holds, SIGUSR2 resumes
+    // and SIGHUP finishes a job repeating until_signal
+    let mut signals = Signals::new([SIGUSR1, SIGUSR2, SIGHUP, SIGTERM])
+        .map_err(|error| format!("Failed to set up signal handler: {}", error))?;
+    let signal_job = job.clone();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGUSR1 => signal_job.pause(),
+                SIGUSR2 => signal_job.resume(),
+                SIGHUP => signal_job.finish(),
+                _ => {
+                    info!("Received SIGTERM");
+                    signal_job.abort();
                 }
             }
         }
+    });
 
-        info!("Sequence complete (timestamp: {})", timestamp);
+    if let Some(button) = button {
+        button.watch(job.clone());
     }
 
-    Ok(())
+    if let Some(pause) = pause {
+        let gpio_job = job.clone();
+        watch_input("Pause", pause, move |_| gpio_job.toggle_pause());
+    }
+
+    if let (Some(door), Some((active, resume_on_close))) = (door, door_trigger) {
+        let gpio_job = job.clone();
+        watch_input("Door", door, move |edge| {
+            let open = matches!(
+                (edge, active),
+                (Edge::Rising, Level::High) | (Edge::Falling, Level::Low)
+            );
+            gpio_job.door(open, resume_on_close);
+        });
+    }
+
+    let tui = tui_monitor
+        .map(|(monitor, name)| tui::start(job.clone(), monitor, name))
+        .transpose()
+        .map_err(|error| format!("Failed to start terminal UI: {}", error))?;
+
+    let result = job.wait();
+    // Restores the terminal before the error, if any, is printed
+    drop(tui);
+    result
 }
@@ -0,0 +1,148 @@
+use std::fs;
+
+use log::info;
+
+// Probes a 3 x 3 grid over 200 x 200 mm from the front left point, where the operator leaves
+// the probe a few mm above the spoilboard. The rows snake so each move is to a neighbouring point
+const TRAM_PROGRAM: &str = "\
+; Spoilboard tram check written by cnc-ctrl
+G21 G91
+G38.2 Z-15 F100
+G0 Z5
+G0 X100
+G38.2 Z-15 F100
+G0 Z5
+G0 X100
+G38.2 Z-15 F100
+G0 Z5
+G0 Y100
+G38.2 Z-15 F100
+G0 Z5
+G0 X-100
+G38.2 Z-15 F100
+G0 Z5
+G0 X-100
+G38.2 Z-15 F100
+G0 Z5
+G0 Y100
+G38.2 Z-15 F100
+G0 Z5
+G0 X100
+G38.2 Z-15 F100
+G0 Z5
+G0 X100
+G38.2 Z-15 F100
+G0 Z10
+G90
+";
+
+const TRAM_JOB: &str = r#"
+job:
+  name: tram
+  repeat: once
+  requirements:
+    probe: true
+steps:
+  - type: gcode
+    name: probe
+    path: "{program}"
+    check: false
+    probe: {}
+"#;
+
+const BACKLASH_JOB: &str = r#"
+job:
+  name: backlash
+  repeat: once
+steps:
+  - type: backlash
+    name: backlash
+    axes: [x, y]
+    distance: 5
+    feed: 500
+"#;
+
+const CALIBRATE_JOB: &str = r#"
+job:
+  name: calibrate
+  repeat: once
+steps:
+  - type: calibrate
+    name: calibrate
+    axis: x
+    distance: 100
+    feed: 500
+"#;
+
+// Name, description and job of each maintenance job
+const JOBS: [(&str, &str, &str); 3] = [
+    (
+        "tram",
+        "Probe a 3 x 3 grid over 200 x 200 mm and log the spoilboard's tilt",
+        TRAM_JOB,
+    ),
+    (
+        "backlash",
+        "Measure the X and Y backlash with a dial indicator",
+        BACKLASH_JOB,
+    ),
+    (
+        "calibrate",
+        "Correct the X steps/mm from a measured 100 mm move",
+        CALIBRATE_JOB,
+    ),
+];
+
+/// Logs the maintenance jobs that can be run
+pub fn list() {
+    info!("Maintenance jobs, run with `cnc-ctrl maintenance <name>`:");
+    for (name, description, _) in JOBS {
+        info!("  {:<10} {}", name, description);
+    }
+}
+
+/// The job (YAML) of the maintenance job `name`, with any program it streams written to a
+/// temporary file
+pub fn job(name: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let Some((_, _, job)) = JOBS.iter().find(|(job, _, _)| *job == name) else {
+        let names: Vec<&str> = JOBS.iter().map(|(name, _, _)| *name).collect();
+        return Err(format!(
+            "No maintenance job named '{}' (jobs: {})",
+            name,
+            names.join(", ")
+        )
+        .into());
+    };
+
+    if !job.contains("{program}") {
+        return Ok(job.to_string());
+    }
+    let program = std::env::temp_dir().join(format!("cnc-ctrl-{}.nc", name));
+    fs::write(&program, TRAM_PROGRAM)
+        .map_err(|error| format!("Failed to write '{}': {}", program.display(), error))?;
+
+    Ok(job.replace("{program}", &program.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Config, File, FileFormat};
+
+    use super::*;
+    use crate::config::{JobConfig, StepConfig};
+
+    #[test]
+    fn jobs_are_valid() {
+        for (name, _, _) in JOBS {
+            let settings = Config::builder()
+                .add_source(File::from_str(&job(name).unwrap(), FileFormat::Yaml))
+                .build()
+                .unwrap();
+            settings.get::<JobConfig>("job").unwrap();
+            let steps: Vec<StepConfig> = settings.get("steps").unwrap();
+            assert_eq!(steps.len(), 1, "{}", name);
+        }
+
+        assert!(job("nothing").is_err());
+    }
+}
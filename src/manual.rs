@@ -0,0 +1,257 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::RecvTimeoutError;
+use log::{info, warn};
+use serialport::SerialPort;
+
+use crate::cli::JogArgs;
+use crate::config::{CncConfig, HomeStepConfig, JogStepConfig, Units};
+use crate::controller::Controller;
+use crate::controller::command::Realtime;
+use crate::controller::message::{Push, Report, Response, error_description};
+use crate::controller::serial::buffered_stream;
+use crate::display;
+use crate::steps::{check_program_in_grbl, execute_home_step, execute_jog_step};
+
+// How long the monitor waits for a report before checking for responses and the next poll
+const MONITOR_WAIT: Duration = Duration::from_millis(100);
+
+/// Prints the machine's state, position, feed and overrides
+pub fn status(controller: &Controller) -> Result<(), Box<dyn std::error::Error>> {
+    let report = controller
+        .monitor()
+        .report()
+        .ok_or("No status report received, check the connection")?;
+
+    let position = |position: Option<(f32, f32, f32)>| match position {
+        Some((x, y, z)) => display::position((x as f64, y as f64, z as f64)),
+        None => "-".to_string(),
+    };
+
+    println!("State:     {}", report.state());
+    println!("Machine:   {}", position(report.machine_position()));
+    println!("Work:      {}", position(report.work_position()));
+    if let Some(feed) = report.feed {
+        println!("Feed:      {}", display::feed(feed as f64));
+    }
+    if let Some(spindle) = report.spindle {
+        println!("Spindle:   {} rpm", spindle);
+    }
+    if let Some((feed, rapid, spindle)) = report.ov {
+        println!(
+            "Overrides: feed {}%, rapid {}%, spindle {}%",
+            feed, rapid, spindle
+        );
+    }
+
+    Ok(())
+}
+
+/// Polls a status report every `interval` and logs each one that differs from the last, along
+/// with the controller's responses, until `stop` is set. Status requests (`?`) are all it sends,
+/// so it can watch a machine without getting in the way
+pub fn monitor(
+    controller: &Controller,
+    interval: Duration,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some((_, pushes)), Some((_, responses))) =
+        (&controller.prio_serial_channel, &controller.serial_channel)
+    else {
+        return Err("Controller not started".into());
+    };
+
+    info!(
+        "Monitoring, polling the status every {} ms, Ctrl-C to exit",
+        interval.as_millis()
+    );
+
+    let mut last = String::new();
+    let mut next_poll = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        if Instant::now() >= next_poll {
+            controller.realtime(Realtime::StatusReport)?;
+            next_poll = Instant::now() + interval;
+        }
+
+        // Responses to another sender sharing the connection, e.g. through a serial splitter
+        while let Ok(response) = responses.try_recv() {
+            info!("Grbl: {}", response);
+        }
+
+        // Alarms and messages are logged by the controller as they arrive
+        match pushes.recv_timeout(MONITOR_WAIT) {
+            Ok(Push::Report(_)) => {
+                // The last report keeps the work offset, which Grbl only sends now and then
+                let report = match controller.last_report.lock() {
+                    Ok(report) => report.clone(),
+                    Err(poisoned) => poisoned.into_inner().clone(),
+                };
+                if let Some(report) = report {
+                    let summary = summarize(&report);
+                    if summary != last {
+                        info!("{}", summary);
+                        last = summary;
+                    }
+                }
+            }
+            Ok(Push::Alarm(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err("Connection to the controller closed".into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// One line with the parts of a report worth watching, leaving out buffer counts that change
+// with every report
+fn summarize(report: &Report) -> String {
+    let position = |position: Option<(f32, f32, f32)>| match position {
+        Some((x, y, z)) => display::position((x as f64, y as f64, z as f64)),
+        None => "-".to_string(),
+    };
+
+    let mut summary = format!(
+        "{} | MPos {} | WPos {}",
+        report.state(),
+        position(report.machine_position()),
+        position(report.work_position())
+    );
+    if let Some(feed) = report.feed {
+        summary += &format!(" | Feed {}", display::feed(feed as f64));
+    }
+    if let Some(spindle) = report.spindle {
+        summary += &format!(" | Spindle {} rpm", spindle);
+    }
+    if let Some((feed, rapid, spindle)) = report.ov {
+        summary += &format!(" | Ov {}%/{}%/{}%", feed, rapid, spindle);
+    }
+    summary
+}
+
+/// Streams a G-code file in Grbl's check mode and prints each line it rejects as
+/// `file:line: error:N description: text`, failing if there are any
+pub fn check(
+    path: &str,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Checking '{}' in Grbl's check mode", path);
+
+    let rejected = check_program_in_grbl(path, None, controller, config)?;
+    for rejected in &rejected {
+        let description = match rejected.response {
+            Response::Error(code) => error_description(code),
+            _ => "Unexpected response",
+        };
+        println!(
+            "{}:{}: {} {}: {}",
+            path, rejected.line, rejected.response, description, rejected.text
+        );
+    }
+
+    match rejected.len() {
+        0 => {
+            info!("No errors found");
+            Ok(())
+        }
+        1 => Err("1 line rejected".into()),
+        count => Err(format!("{} lines rejected", count).into()),
+    }
+}
+
+/// Jogs the machine as a `jog` step would, checked against the machine limits
+pub fn jog(
+    args: &JogArgs,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let step = JogStepConfig {
+        x: args.x,
+        y: args.y,
+        z: args.z,
+        feed: args.feed,
+        units: if args.inch { Units::Inch } else { Units::Mm },
+        relative: !args.absolute,
+        wait_for_signal: false,
+    };
+
+    execute_jog_step(&step, controller, config)
+}
+
+pub fn home(controller: &Controller, config: &CncConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // Without positions to verify, the tolerance isn't used
+    let step = HomeStepConfig {
+        x: None,
+        y: None,
+        z: None,
+        tolerance: 0.0,
+        units: Units::Mm,
+        wait_for_signal: false,
+    };
+
+    execute_home_step(&step, controller, config)
+}
+
+/// Sends each line typed on the terminal and prints the controller's responses, until the
+/// input ends
+pub fn console(
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!(
+        "Type G-code or Grbl commands, '?' for the status, '!' to hold, '~' to resume, Ctrl-D to exit"
+    );
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => {}
+            "?" => match controller.monitor().report() {
+                Some(report) => println!("{}", report.raw),
+                None => warn!("No status report received"),
+            },
+            "!" => controller.realtime(Realtime::FeedHold)?,
+            "~" => controller.realtime(Realtime::CycleStart)?,
+            line => {
+                // `error:N` responses are printed rather than ending the console
+                let responses = buffered_stream(controller, [line], &config.grbl, ())?;
+                for (_, response) in &responses {
+                    println!("{}", response);
+                }
+                if !responses
+                    .iter()
+                    .any(|(_, response)| matches!(response, Response::Error(_)))
+                {
+                    println!("ok");
+                }
+            }
+        }
+    }
+}
+
+/// Stops the machine with `command` on Ctrl-C and exits, for commands that move it without a
+/// job to stop
+pub fn stop_on_interrupt(
+    mut serial: Box<dyn SerialPort>,
+    command: Realtime,
+) -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(move || {
+        warn!("Interrupted, sending {:?}", command);
+        let _ = serial.write_all(&[command as u8]);
+        std::process::exit(130);
+    })
+}
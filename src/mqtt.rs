@@ -0,0 +1,294 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::RecvTimeoutError;
+use log::{info, warn};
+
+use crate::config::MqttConfig;
+use crate::controller::Controller;
+use crate::status::{self, Event, State};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+// Pings well within the keep alive, in case nothing else has been sent
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+// How long a read waits for anything from the broker when draining it
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Starts publishing to the broker on a thread of its own. The broker being unreachable only
+/// warns, publishing resumes once it can be reached
+pub fn init(config: &MqttConfig, controller: &Controller) {
+    let events = status::subscribe();
+
+    let mut publisher = Publisher {
+        config: config.clone(),
+        stream: None,
+        last_sent: Instant::now(),
+        retry_at: Instant::now(),
+        backoff: Duration::from_secs(1),
+        state: Some(status::state()),
+    };
+    let monitor = controller.monitor();
+    let interval = (config.interval_ms > 0).then(|| Duration::from_millis(config.interval_ms));
+
+    info!("Publishing status to MQTT broker {}", config.address);
+
+    thread::spawn(move || {
+        publisher.publish_state();
+        let mut next_status = Instant::now();
+
+        loop {
+            let timeout = match interval {
+                Some(_) => next_status.saturating_duration_since(Instant::now()),
+                None => PING_INTERVAL,
+            };
+            match events.recv_timeout(timeout.min(PING_INTERVAL)) {
+                Ok(Event::State(state)) => {
+                    publisher.state = Some(state);
+                    publisher.publish_state();
+                }
+                Ok(Event::Step(step)) => publisher.publish("step", &step.json()),
+                Ok(Event::Probe(probe)) => publisher.publish("probe", &probe.json()),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if let Some(interval) = interval
+                && Instant::now() >= next_status
+            {
+                next_status = Instant::now() + interval;
+
+                let status = status::json(monitor.report().as_ref(), monitor.paused());
+                publisher.publish("status", &status);
+            }
+
+            publisher.keep_alive();
+        }
+    });
+}
+
+struct Publisher {
+    config: MqttConfig,
+    stream: Option<TcpStream>,
+    last_sent: Instant,
+    retry_at: Instant,
+    backoff: Duration,
+    state: Option<State>,
+}
+
+impl Publisher {
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.config.topic, name)
+    }
+
+    fn publish_state(&mut self) {
+        if let Some(state) = self.state {
+            self.publish("state", state.name());
+        }
+    }
+
+    fn publish(&mut self, name: &str, payload: &str) {
+        let topic = self.topic(name);
+        let packet = publish_packet(&topic, payload.as_bytes(), self.config.retain);
+        self.write(&packet);
+    }
+
+    // Pings if nothing was sent for a while, and drops anything the broker sent, e.g. ping responses
+    fn keep_alive(&mut self) {
+        if let Some(stream) = &mut self.stream {
+            let mut discard = [0; 64];
+            loop {
+                match stream.read(&mut discard) {
+                    Ok(0) => {
+                        warn!("MQTT broker closed the connection");
+                        self.stream = None;
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(error)
+                        if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    {
+                        break;
+                    }
+                    Err(error) => {
+                        warn!("Lost connection to MQTT broker: {}", error);
+                        self.stream = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if self.stream.is_some() && self.last_sent.elapsed() >= PING_INTERVAL {
+            self.write(&[0xc0, 0x00]);
+        }
+    }
+
+    fn write(&mut self, packet: &[u8]) {
+        if self.stream.is_none() && !self.connect() {
+            return;
+        }
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        match stream.write_all(packet) {
+            Ok(()) => self.last_sent = Instant::now(),
+            Err(error) => {
+                warn!("Lost connection to MQTT broker: {}", error);
+                self.stream = None;
+            }
+        }
+    }
+
+    // Backs off between attempts, so an unreachable broker doesn't hold up each publish
+    fn connect(&mut self) -> bool {
+        if Instant::now() < self.retry_at {
+            return false;
+        }
+
+        match self.try_connect() {
+            Ok(stream) => {
+                info!("Connected to MQTT broker {}", self.config.address);
+                self.stream = Some(stream);
+                self.backoff = Duration::from_secs(1);
+                self.last_sent = Instant::now();
+
+                // Replaces the `offline` will left by a previous session
+                let online = publish_packet(&self.topic("available"), b"online", true);
+                self.write(&online);
+                true
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to connect to MQTT broker {}, retrying in {} s: {}",
+                    self.config.address,
+                    self.backoff.as_secs(),
+                    error
+                );
+                self.retry_at = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                false
+            }
+        }
+    }
+
+    fn try_connect(&self) -> Result<TcpStream, Box<dyn std::error::Error>> {
+        let address = self
+            .config
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or(format!("Failed to resolve '{}'", self.config.address))?;
+
+        let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+        stream.write_all(&connect_packet(&self.config, &self.topic("available")))?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 {
+            return Err(format!("Unexpected response from broker: {:02x?}", connack).into());
+        }
+        if connack[3] != 0 {
+            return Err(format!("Broker refused connection (code {})", connack[3]).into());
+        }
+
+        // Reads only drain what the broker sends from here on, so they barely wait. Writes keep
+        // blocking, with the timeout, so a full send buffer doesn't cut a packet short
+        stream.set_read_timeout(Some(DRAIN_TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+// MQTT 3.1.1 CONNECT with a clean session and a retained `offline` will on `will_topic`
+fn connect_packet(config: &MqttConfig, will_topic: &str) -> Vec<u8> {
+    let mut flags = 0x02 | 0x04 | 0x20;
+    let mut payload = encode_string(config.client_id.as_bytes());
+    payload.extend(encode_string(will_topic.as_bytes()));
+    payload.extend(encode_string(b"offline"));
+    if let Some(username) = &config.username {
+        flags |= 0x80;
+        payload.extend(encode_string(username.as_bytes()));
+    }
+    if let Some(password) = &config.password {
+        flags |= 0x40;
+        payload.extend(encode_string(password.as_bytes()));
+    }
+
+    let mut body = encode_string(b"MQTT");
+    body.push(0x04);
+    body.push(flags);
+    body.extend((KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    body.extend(payload);
+
+    packet(0x10, body)
+}
+
+// QoS 0, so there is no packet id or acknowledgement
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = encode_string(topic.as_bytes());
+    body.extend_from_slice(payload);
+
+    packet(if retain { 0x31 } else { 0x30 }, body)
+}
+
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![header];
+    // Remaining length, 7 bits per byte with the top bit set on all but the last
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend(body);
+    packet
+}
+
+fn encode_string(text: &[u8]) -> Vec<u8> {
+    let mut encoded = (text.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(text);
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length() {
+        for (length, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (16383, vec![0xff, 0x7f]),
+            (16384, vec![0x80, 0x80, 0x01]),
+            (2097151, vec![0xff, 0xff, 0x7f]),
+            (2097152, vec![0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = packet(0x30, vec![0; length]);
+            assert_eq!(packet[0], 0x30);
+            assert_eq!(packet[1..1 + encoded.len()], encoded, "length {}", length);
+            assert_eq!(packet.len(), 1 + encoded.len() + length);
+        }
+    }
+
+    #[test]
+    fn publish() {
+        assert_eq!(
+            publish_packet("a/b", b"on", false),
+            [0x30, 7, 0, 3, b'a', b'/', b'b', b'o', b'n']
+        );
+        assert_eq!(publish_packet("t", b"", true), [0x31, 3, 0, 1, b't']);
+    }
+}
@@ -0,0 +1,174 @@
+mod smtp;
+
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel::{self, Sender};
+use log::{info, warn};
+use ureq::Agent;
+
+use crate::config::{NotifyChannel, NotifyConfig, NotifyEvent};
+use crate::json::string;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+// How long the end of the job waits for messages still being sent
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Message {
+    title: String,
+    body: String,
+    // Failures and alarms, shown with a higher priority where the channel has one
+    urgent: bool,
+}
+
+enum Request {
+    Send(Message),
+    Flush(Sender<()>),
+}
+
+struct Notifier {
+    events: Vec<NotifyEvent>,
+    title: String,
+    requests: Sender<Request>,
+}
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+/// Starts sending notifications on a thread of their own, so a slow channel doesn't hold up
+/// the job
+pub fn init(config: &NotifyConfig, job: Option<&str>) {
+    let (tx, rx) = channel::unbounded();
+    let notifier = Notifier {
+        events: config.events.clone(),
+        title: match job {
+            Some(job) => format!("cnc-ctrl: {}", job),
+            None => "cnc-ctrl".to_string(),
+        },
+        requests: tx,
+    };
+    if NOTIFIER.set(notifier).is_err() {
+        return;
+    }
+
+    let channels = config.channels.clone();
+    thread::spawn(move || {
+        for request in rx {
+            match request {
+                Request::Send(message) => {
+                    for channel in &channels {
+                        if let Err(error) = send(channel, &message) {
+                            warn!(
+                                "Failed to send notification to {}: {}",
+                                channel_name(channel),
+                                error
+                            );
+                        }
+                    }
+                }
+                Request::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+}
+
+/// Notifies every channel of `event`, if notifications are set up and the event is selected
+pub fn event(event: NotifyEvent, body: &str) {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    if !notifier.events.contains(&event) {
+        return;
+    }
+
+    info!("Sending notification: {}", body);
+    let _ = notifier.requests.send(Request::Send(Message {
+        title: notifier.title.clone(),
+        body: body.to_string(),
+        urgent: matches!(event, NotifyEvent::Failure | NotifyEvent::Alarm),
+    }));
+}
+
+/// Waits for the notifications already queued to be sent, before cnc-ctrl exits
+pub fn flush() {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+
+    let (tx, rx) = channel::bounded(1);
+    if notifier.requests.send(Request::Flush(tx)).is_ok() && rx.recv_timeout(FLUSH_TIMEOUT).is_err()
+    {
+        warn!("Gave up waiting for notifications to be sent");
+    }
+}
+
+fn channel_name(channel: &NotifyChannel) -> String {
+    match channel {
+        NotifyChannel::Ntfy { topic, .. } => format!("ntfy topic '{}'", topic),
+        NotifyChannel::Telegram { chat_id, .. } => format!("Telegram chat {}", chat_id),
+        NotifyChannel::Email { to, .. } => format!("email to {}", to.join(", ")),
+    }
+}
+
+fn send(channel: &NotifyChannel, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    match channel {
+        NotifyChannel::Ntfy {
+            server,
+            topic,
+            token,
+        } => {
+            let mut request = agent
+                .post(format!("{}/{}", server.trim_end_matches('/'), topic))
+                .header("Title", &message.title);
+            if message.urgent {
+                request = request.header("Priority", "high").header("Tags", "warning");
+            }
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            request.send(&message.body)?;
+        }
+        NotifyChannel::Telegram { bot_token, chat_id } => {
+            let body = format!(
+                "{{\"chat_id\": {}, \"text\": {}}}",
+                string(chat_id),
+                string(&format!("{}\n{}", message.title, message.body))
+            );
+            agent
+                .post(format!(
+                    "https://api.telegram.org/bot{}/sendMessage",
+                    bot_token
+                ))
+                .header("Content-Type", "application/json")
+                .send(body)?;
+        }
+        NotifyChannel::Email {
+            server,
+            security,
+            username,
+            password,
+            from,
+            to,
+        } => {
+            let login = username.as_deref().zip(password.as_deref());
+            smtp::send(
+                server,
+                *security,
+                login,
+                from,
+                to,
+                &message.title,
+                &message.body,
+            )?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::prelude::*;
+use chrono::Local;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::config::SmtpSecurity;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+// Bytes of text per encoded word, keeping each within the 75 characters RFC 2047 allows
+const ENCODED_WORD_BYTES: usize = 45;
+
+enum Connection {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Sends a plain text email through `server`, logging in with `login` if given
+pub fn send(
+    server: &str,
+    security: SmtpSecurity,
+    login: Option<(&str, &str)>,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    body: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A line break in a header would start another header
+    for value in [subject, from]
+        .into_iter()
+        .chain(to.iter().map(String::as_str))
+    {
+        if value.chars().any(char::is_control) {
+            return Err(format!("'{}' can't be used in a header", value.escape_debug()).into());
+        }
+    }
+
+    let host = host(server);
+    let address = server
+        .to_socket_addrs()?
+        .next()
+        .ok_or(format!("Failed to resolve '{}'", server))?;
+
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut connection = match security {
+        SmtpSecurity::Tls => tls(stream, &host)?,
+        _ => Connection::Plain(stream),
+    };
+    reply(&mut connection, 220)?;
+    command(&mut connection, "EHLO cnc-ctrl", 250)?;
+
+    if security == SmtpSecurity::Starttls {
+        command(&mut connection, "STARTTLS", 220)?;
+        let Connection::Plain(stream) = connection else {
+            return Err("Connection is already encrypted".into());
+        };
+        connection = tls(stream, &host)?;
+        command(&mut connection, "EHLO cnc-ctrl", 250)?;
+    }
+
+    if let Some((username, password)) = login {
+        let credentials = BASE64_STANDARD.encode(format!("\0{}\0{}", username, password));
+        command(&mut connection, &format!("AUTH PLAIN {}", credentials), 235)?;
+    }
+
+    command(&mut connection, &format!("MAIL FROM:<{}>", from), 250)?;
+    for recipient in to {
+        command(&mut connection, &format!("RCPT TO:<{}>", recipient), 250)?;
+    }
+    command(&mut connection, "DATA", 354)?;
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        encode_header(subject),
+        Local::now().to_rfc2822()
+    );
+    // Lines starting with a dot are escaped, a dot on its own ends the message
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message += line;
+        message += "\r\n";
+    }
+    message += ".";
+    command(&mut connection, &message, 250)?;
+
+    let _ = command(&mut connection, "QUIT", 221);
+    Ok(())
+}
+
+// Headers are ASCII, anything else is sent as RFC 2047 encoded words, split between characters
+fn encode_header(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for character in text.chars() {
+        if word.len() + character.len_utf8() > ENCODED_WORD_BYTES {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(character);
+    }
+    words.push(word);
+
+    words
+        .iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", BASE64_STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+// The certificate is checked against the host without the port, or the brackets of an IPv6
+// address such as `[::1]:587`
+fn host(server: &str) -> String {
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string()
+}
+
+fn tls(stream: TcpStream, host: &str) -> Result<Connection, Box<dyn std::error::Error>> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+    let name = ServerName::try_from(host.to_string())?;
+    let client = ClientConnection::new(Arc::new(config), name)?;
+    Ok(Connection::Tls(Box::new(StreamOwned::new(client, stream))))
+}
+
+fn command(
+    connection: &mut Connection,
+    line: &str,
+    expected: u16,
+) -> Result<(), Box<dyn std::error::Error>> {
+    connection.write_all(format!("{}\r\n", line).as_bytes())?;
+    connection.flush()?;
+    reply(connection, expected)
+}
+
+// Reads a reply, which may span several `250-` lines, and checks its code
+fn reply(connection: &mut Connection, expected: u16) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while !line.ends_with(b"\r\n") {
+            if connection.read(&mut byte)? == 0 {
+                return Err("Server closed the connection".into());
+            }
+            line.push(byte[0]);
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let code: u16 = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("Unexpected reply '{}'", line.trim()))?;
+        if code != expected {
+            return Err(format!("Server replied '{}'", line.trim()).into());
+        }
+        // The last line of a reply has a space after the code
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_named_without_the_port() {
+        assert_eq!(host("smtp.example.com:465"), "smtp.example.com");
+        assert_eq!(host("127.0.0.1:25"), "127.0.0.1");
+        assert_eq!(host("[::1]:587"), "::1");
+        assert!(ServerName::try_from(host("[::1]:587")).is_ok());
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, info, warn};
+
+use crate::config::{Axis, CncConfig, Edge, Level, PendantConfig};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Status};
+use crate::controller::serial::wait_for_report;
+use crate::display;
+use crate::gpio::{self, Input, Watch};
+use crate::steps::check_move;
+
+// Encoder edges closer together than this are contact bounce
+const ENCODER_DEBOUNCE: Duration = Duration::from_millis(1);
+// Count change for each transition from the encoder's previous state (A << 1 | B) to its next
+const TRANSITIONS: [i64; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+struct Pendant {
+    axes: Vec<(Axis, Box<dyn Input>)>,
+    steps: Vec<(f64, Box<dyn Input>)>,
+    active: Level,
+    feed: f64,
+    counts_per_detent: i64,
+    reverse: bool,
+    counts: Arc<AtomicI64>,
+    // Counts already turned into jogs
+    used: i64,
+    // Direction of the last jog, queued jogs are cancelled when it changes
+    direction: i64,
+    jogged: bool,
+}
+
+// Only the job thread jogs, the encoder is counted on threads of its own
+static PENDANT: Mutex<Option<Pendant>> = Mutex::new(None);
+
+/// Sets up the pendant's inputs. Without GPIO the pendant is disabled rather than stopping the job
+pub fn init(config: &PendantConfig) {
+    match setup(config) {
+        Ok(pendant) => {
+            info!(
+                "Pendant enabled, jogging {} while waiting for the start signal",
+                pendant
+                    .axes
+                    .iter()
+                    .map(|(axis, _)| axis.to_string())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            );
+            *lock_pendant() = Some(pendant);
+        }
+        Err(error) => warn!("Pendant disabled: {}", error),
+    }
+}
+
+fn setup(config: &PendantConfig) -> Result<Pendant, Box<dyn std::error::Error>> {
+    let gpio = gpio::open()?;
+
+    let input = |pin| gpio.input(pin, config.pull);
+    let axes = config
+        .axes
+        .iter()
+        .map(|selector| Ok((selector.axis, input(selector.pin)?)))
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+    let steps = config
+        .steps
+        .iter()
+        .map(|selector| Ok((config.units.to_mm(selector.distance), input(selector.pin)?)))
+        .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+    let encoder = &config.encoder;
+    let counts = Arc::new(AtomicI64::new(0));
+    // Most encoders rest with both channels high
+    let state = Arc::new(Mutex::new(0b11));
+    for (pin, bit) in [(encoder.a, 0b10), (encoder.b, 0b01)] {
+        let channel = gpio.watch(pin, encoder.pull, Edge::Both, ENCODER_DEBOUNCE)?;
+        count(channel, bit, state.clone(), counts.clone());
+    }
+
+    Ok(Pendant {
+        axes,
+        steps,
+        active: config.active,
+        feed: config.units.to_mm(config.feed),
+        counts_per_detent: encoder.counts_per_detent.max(1).into(),
+        reverse: encoder.reverse,
+        counts,
+        used: 0,
+        direction: 0,
+        jogged: false,
+    })
+}
+
+// Decodes edges on one encoder channel into counts, with the other channel's level from `state`
+fn count(mut channel: Box<dyn Watch>, bit: u8, state: Arc<Mutex<u8>>, counts: Arc<AtomicI64>) {
+    thread::spawn(move || {
+        loop {
+            let edge = match channel.poll(None) {
+                Ok(Some(edge)) => edge,
+                Ok(None) => continue,
+                Err(error) => {
+                    error!("Pendant encoder stopped: {}", error);
+                    return;
+                }
+            };
+
+            let mut state = match state.lock() {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let next = match edge {
+                Edge::Falling => *state & !bit,
+                _ => *state | bit,
+            };
+            counts.fetch_add(
+                TRANSITIONS[usize::from(*state << 2 | next)],
+                Ordering::Relaxed,
+            );
+            *state = next;
+        }
+    });
+}
+
+/// Jogs the selected axis by the selected step for each detent turned since the last call.
+/// Turns while no axis or step is selected are dropped
+pub fn jog(controller: &Controller, config: &CncConfig) {
+    let mut pendant = lock_pendant();
+    let Some(pendant) = pendant.as_mut() else {
+        return;
+    };
+
+    let counts = pendant.counts.load(Ordering::Relaxed);
+    let detents = (counts - pendant.used) / pendant.counts_per_detent;
+    if detents == 0 {
+        return;
+    }
+    pendant.used += detents * pendant.counts_per_detent;
+
+    let (Some(axis), Some(step)) = (pendant.selected_axis(), pendant.selected_step()) else {
+        return;
+    };
+    let detents = if pendant.reverse { -detents } else { detents };
+    let distance = detents as f64 * step;
+
+    // Queued jogs in the old direction would otherwise run before the new one
+    if pendant.jogged
+        && detents.signum() != pendant.direction
+        && let Err(error) = controller.cancel_jog()
+    {
+        warn!("Failed to cancel pendant jog: {}", error);
+    }
+
+    let moves = [(axis, distance)];
+    if let Err(error) = check_move(controller, config, &moves, pendant.feed, true) {
+        warn!(
+            "Refusing to jog {} {}: {}",
+            axis,
+            display::length(distance),
+            error
+        );
+        return;
+    }
+    match controller.jog(&moves, pendant.feed, true, &config.grbl) {
+        Ok(()) => {
+            pendant.direction = detents.signum();
+            pendant.jogged = true;
+        }
+        Err(error) => warn!(
+            "Failed to jog {} {}: {}",
+            axis,
+            display::length(distance),
+            error
+        ),
+    }
+}
+
+/// Starts jogging from the current encoder position, so turns while the job ran are ignored
+pub fn start() {
+    if let Some(pendant) = lock_pendant().as_mut() {
+        pendant.used = pendant.counts.load(Ordering::Relaxed);
+    }
+}
+
+/// Cancels any jog still running, and waits for the machine to stop before the job continues
+pub fn stop(controller: &Controller) -> Result<(), String> {
+    let mut pendant = lock_pendant();
+    let Some(pendant) = pendant.as_mut() else {
+        return Ok(());
+    };
+
+    if !std::mem::take(&mut pendant.jogged) {
+        return Ok(());
+    }
+
+    controller
+        .cancel_jog()
+        .map_err(|error| format!("Failed to cancel pendant jog: {}", error))?;
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )
+    .map_err(|error| format!("Failed to wait for pendant jog to stop: {}", error))?;
+
+    Ok(())
+}
+
+impl Pendant {
+    fn selected_axis(&self) -> Option<Axis> {
+        self.axes
+            .iter()
+            .find(|(_, input)| input.read().is_ok_and(|level| level == self.active))
+            .map(|(axis, _)| *axis)
+    }
+
+    fn selected_step(&self) -> Option<f64> {
+        self.steps
+            .iter()
+            .find(|(_, input)| input.read().is_ok_and(|level| level == self.active))
+            .map(|(distance, _)| *distance)
+    }
+}
+
+fn lock_pendant() -> std::sync::MutexGuard<'static, Option<Pendant>> {
+    match PENDANT.lock() {
+        Ok(pendant) => pendant,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
@@ -0,0 +1,108 @@
+use log::{error, info};
+
+use crate::config::{
+    BashCommand, CncConfig, Step, StepConfig, TemplateVars, apply_template, expand_home,
+    expand_path,
+};
+use crate::steps::check_program;
+
+/// Checks the job without a machine: the config, every G-code program after templating,
+/// and the directories written to. Logs the steps that would run, the serial port is never opened.
+pub fn check(config: &CncConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut problems = Vec::new();
+
+    if let Err(error) = config.validate() {
+        problems.push(error.to_string());
+    }
+
+    // Templates are rendered as they would be for the first sequence
+    let mut vars = TemplateVars::new(config, config.counter.start);
+
+    info!(
+        "Plan for job '{}', repeating {:?}:",
+        config.job.name.as_deref().unwrap_or("unnamed"),
+        config.job.repeat
+    );
+    plan_steps(&config.steps, "", "steps", &mut vars, &mut problems);
+
+    if problems.is_empty() {
+        info!("Job is valid, no machine was connected");
+        Ok(())
+    } else {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        Err(format!("{} problem(s) found", problems.len()).into())
+    }
+}
+
+fn plan_steps(
+    steps: &[StepConfig],
+    prefix: &str,
+    key: &str,
+    vars: &mut TemplateVars,
+    problems: &mut Vec<String>,
+) {
+    for (i, step) in steps.iter().enumerate() {
+        let number = format!("{}{}", prefix, i + 1);
+        let key = format!("{}[{}]", key, i);
+        vars.step = step.label(i + 1);
+
+        let template = |text: &str| apply_template(&expand_path(text), vars);
+        let detail = match &step.step {
+            Step::Gcode(gcode) => {
+                let path = template(&gcode.path);
+                match check_program(&path, gcode.post) {
+                    Ok(Some(lines)) => format!("'{}', {} lines", path, lines),
+                    Ok(None) => format!("'{}', read as it is streamed", path),
+                    Err(error) => {
+                        problems.push(format!("{}.path: {}", key, error));
+                        format!("'{}'", path)
+                    }
+                }
+            }
+            Step::Bash(bash) => match &bash.command {
+                BashCommand::Shell(command) => apply_template(&expand_home(command), vars),
+                BashCommand::Argv(argv) => argv
+                    .iter()
+                    .map(|arg| template(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            },
+            Step::Http(http) => format!("{:?} {}", http.method, apply_template(&http.url, vars)),
+            Step::Settings(settings) => format!("'{}'", template(&settings.path)),
+            Step::Repeat(repeat) => format!("{} times", repeat.count),
+            _ => String::new(),
+        };
+
+        let wait = if step.should_wait() {
+            ", waits for signal"
+        } else {
+            ""
+        };
+        if detail.is_empty() {
+            info!("  {}. {} ({}{})", number, vars.step, step.step.kind(), wait);
+        } else {
+            info!(
+                "  {}. {} ({}{}): {}",
+                number,
+                vars.step,
+                step.step.kind(),
+                wait,
+                detail
+            );
+        }
+
+        if let Step::Repeat(repeat) = &step.step {
+            let outer_step = vars.step.clone();
+            plan_steps(
+                &repeat.steps,
+                &format!("{}.", number),
+                &format!("{}.steps", key),
+                vars,
+                problems,
+            );
+            vars.step = outer_step;
+        }
+    }
+}
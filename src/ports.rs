@@ -0,0 +1,85 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use log::info;
+use serialport::SerialPortType;
+
+// How long a probed port has to answer, Arduino-based boards take a second or two to boot
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Prints the serial ports with their USB vendor and product where known. With `probe`, each is
+/// opened at `baudrate` to see whether Grbl answers
+pub fn list(probe: bool, baudrate: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        info!("No serial ports found");
+        return Ok(());
+    }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    for port in ports {
+        let mut details = match &port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                let mut details = format!("USB {:04x}:{:04x}", usb.vid, usb.pid);
+                for value in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                    details += &format!(" {}", value);
+                }
+                if let Some(serial_number) = &usb.serial_number {
+                    details += &format!(" (serial {})", serial_number);
+                }
+                details
+            }
+            SerialPortType::PciPort => "PCI".to_string(),
+            SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+            SerialPortType::Unknown => "Unknown type".to_string(),
+        };
+
+        if probe {
+            details += &match identify(&port.port_name, baudrate) {
+                Ok(Some(answer)) => format!(", Grbl: {}", answer),
+                Ok(None) => ", no answer from Grbl".to_string(),
+                Err(error) => format!(", failed to open: {}", error),
+            };
+        }
+
+        println!("{}  {}", port.port_name, details);
+    }
+
+    Ok(())
+}
+
+// Grbl's banner or a status report, polled for in case the board didn't reset when opened
+fn identify(port: &str, baudrate: u32) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut serial = serialport::new(port, baudrate)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+
+    let started = Instant::now();
+    let mut last_poll: Option<Instant> = None;
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+
+    while started.elapsed() < PROBE_TIMEOUT {
+        if last_poll.is_none_or(|poll| poll.elapsed() >= PROBE_POLL_INTERVAL) {
+            serial.write_all(b"?")?;
+            last_poll = Some(Instant::now());
+        }
+
+        match serial.read(&mut byte) {
+            Ok(0) => continue,
+            Ok(_) if byte[0] == b'\n' => {
+                let text = String::from_utf8_lossy(&line).trim().to_string();
+                if text.starts_with("Grbl") || (text.starts_with('<') && text.ends_with('>')) {
+                    return Ok(Some(text));
+                }
+                line.clear();
+            }
+            Ok(_) => line.push(byte[0]),
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,30 @@
+use std::io::{self, Write};
+
+use log::info;
+
+use crate::tui;
+
+pub fn ask(question: &str) -> io::Result<String> {
+    let answer = if tui::active() {
+        tui::read_line(question)?
+    } else {
+        print!("{} ", question);
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        answer
+    };
+
+    let answer = answer.trim().to_string();
+    // Keep a record of operator decisions in the logs
+    info!("Prompt '{}' answered '{}'", question, answer);
+
+    Ok(answer)
+}
+
+pub fn confirm(question: &str) -> io::Result<bool> {
+    let answer = ask(&format!("{} [y/N]", question))?;
+
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
@@ -0,0 +1,118 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::config::{Pull, Requirement, RequirementCheck, expand_path};
+use crate::gpio;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+// MQTT 3.1.1 CONNECT with a clean session, 60 s keep alive and the client id "cnc-ctrl"
+const MQTT_CONNECT: [u8; 22] = [
+    0x10, 0x14, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x3c, 0x00, 0x08, b'c', b'n',
+    b'c', b'-', b'c', b't', b'r', b'l',
+];
+const MQTT_DISCONNECT: [u8; 2] = [0xe0, 0x00];
+
+impl RequirementCheck {
+    fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            RequirementCheck::Tcp { address } => {
+                connect(address)?;
+            }
+            RequirementCheck::File { path } => {
+                let path = expand_path(path);
+                if !Path::new(&path).exists() {
+                    return Err(format!("'{}' does not exist", path).into());
+                }
+            }
+            RequirementCheck::Mqtt { address } => {
+                let mut stream = connect(address)?;
+                stream.write_all(&MQTT_CONNECT)?;
+
+                let mut connack = [0; 4];
+                stream.read_exact(&mut connack)?;
+                if connack[0] != 0x20 {
+                    return Err(format!("Unexpected response from broker: {:02x?}", connack).into());
+                }
+                if connack[3] != 0 {
+                    return Err(format!("Broker refused connection (code {})", connack[3]).into());
+                }
+
+                stream.write_all(&MQTT_DISCONNECT)?;
+            }
+            RequirementCheck::Gpio { pin, level } => {
+                let actual = gpio::open()?.input(*pin, Pull::None)?.read()?;
+
+                if actual != *level {
+                    return Err(format!("GPIO {} is {:?}", pin, actual).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RequirementCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequirementCheck::Tcp { address } => write!(f, "TCP port {}", address),
+            RequirementCheck::File { path } => write!(f, "file '{}'", path),
+            RequirementCheck::Mqtt { address } => write!(f, "MQTT broker {}", address),
+            RequirementCheck::Gpio { pin, level } => write!(f, "GPIO {} {:?}", pin, level),
+        }
+    }
+}
+
+fn connect(address: &str) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or(format!("Failed to resolve '{}'", address))?;
+
+    let stream = TcpStream::connect_timeout(&address, CHECK_TIMEOUT)?;
+    stream.set_read_timeout(Some(CHECK_TIMEOUT))?;
+    stream.set_write_timeout(Some(CHECK_TIMEOUT))?;
+
+    Ok(stream)
+}
+
+// Check each requirement in order, retrying with backoff until it passes or its max wait elapses
+pub fn wait_for(requirements: &[Requirement]) -> Result<(), Box<dyn std::error::Error>> {
+    for requirement in requirements {
+        let started = Instant::now();
+        let max_wait = Duration::from_millis(requirement.max_wait_ms);
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            match requirement.check.check() {
+                Ok(()) => {
+                    info!("Requirement met: {}", requirement.check);
+                    break;
+                }
+                Err(error) if started.elapsed() + backoff < max_wait => {
+                    warn!(
+                        "Requirement not met: {}: {}, retrying in {} ms",
+                        requirement.check,
+                        error,
+                        backoff.as_millis()
+                    );
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(error) => {
+                    return Err(format!("{}: {}", requirement.check, error).into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
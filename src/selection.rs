@@ -0,0 +1,53 @@
+use crate::config::StepConfig;
+
+/// Top-level steps picked by name on the command line with `--only`, `--skip` and `--from`
+#[derive(Default)]
+pub struct Selection {
+    only: Vec<String>,
+    skip: Vec<String>,
+    from: Option<String>,
+}
+
+impl Selection {
+    pub fn new(only: Vec<String>, skip: Vec<String>, from: Option<String>) -> Self {
+        let trim = |names: Vec<String>| names.iter().map(|name| name.trim().to_string()).collect();
+        Selection {
+            only: trim(only),
+            skip: trim(skip),
+            from,
+        }
+    }
+
+    /// Fails if a name doesn't match any step, or if no steps are left to run
+    pub fn check(&self, steps: &[StepConfig]) -> Result<(), String> {
+        for name in self.only.iter().chain(&self.skip).chain(&self.from) {
+            if !steps.iter().any(|step| step.name.as_ref() == Some(name)) {
+                return Err(format!("No step is named '{}'", name));
+            }
+        }
+
+        if !steps.iter().any(|step| self.includes(step)) {
+            return Err("No steps are selected".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Index of the step named with `--from`, 0 without one
+    pub fn start(&self, steps: &[StepConfig]) -> usize {
+        self.from
+            .as_ref()
+            .and_then(|from| {
+                steps
+                    .iter()
+                    .position(|step| step.name.as_ref() == Some(from))
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn includes(&self, step: &StepConfig) -> bool {
+        let named = |names: &[String]| step.name.as_ref().is_some_and(|name| names.contains(name));
+
+        (self.only.is_empty() || named(&self.only)) && !named(&self.skip)
+    }
+}
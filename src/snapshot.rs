@@ -0,0 +1,116 @@
+use std::fs;
+
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::config::{CncConfig, Step, StepConfig};
+use crate::controller::Controller;
+use crate::controller::message::Response;
+use crate::controller::serial::checked_stream;
+use crate::report::redact_override;
+
+/// Context a run depends on, logged in the run summary so results can be reproduced
+pub struct Snapshot {
+    version: String,
+    firmware: String,
+    config_hash: String,
+    machine_hash: Option<String>,
+    profile: Option<String>,
+    preprocessing: Vec<String>,
+    overrides: Vec<String>,
+}
+
+impl Snapshot {
+    pub fn take(config: &CncConfig, controller: &Controller) -> Self {
+        let firmware = match checked_stream(controller, vec!["$I"], &config.grbl) {
+            Ok(responses) => responses
+                .into_iter()
+                .filter_map(|(_, response)| match response {
+                    Response::Build(build) => Some(build),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            Err(error) => {
+                warn!("Failed to read firmware build info: {}", error);
+                "unknown".to_string()
+            }
+        };
+
+        // Included files are part of the config, so they are hashed with it
+        let config_hash = CncConfig::files()
+            .and_then(|files| {
+                files
+                    .iter()
+                    .map(|file| Ok(fs::read(file)?))
+                    .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()
+            })
+            .map(|contents| hash(contents.concat()))
+            .unwrap_or_else(|_| "unknown".to_string());
+
+        let mut preprocessing = Vec::new();
+        describe_steps(&config.steps, "", &mut preprocessing);
+
+        Self {
+            version: format!(
+                "{} ({})",
+                env!("CARGO_PKG_VERSION"),
+                env!("CNC_CTRL_GIT_HASH")
+            ),
+            firmware,
+            config_hash,
+            machine_hash: config
+                .machine
+                .as_ref()
+                .map(|machine| hash(format!("{:?}", machine))),
+            profile: config.profile.clone(),
+            preprocessing,
+            overrides: config
+                .overrides
+                .iter()
+                .map(|(key, value)| redact_override(key, value))
+                .collect(),
+        }
+    }
+
+    pub fn log(&self) {
+        info!("cnc-ctrl: {}", self.version);
+        info!("Firmware: {}", self.firmware);
+        info!("Config: sha256 {}", self.config_hash);
+        if let Some(profile) = &self.profile {
+            info!("Machine: {}", profile);
+        }
+        if let Some(machine_hash) = &self.machine_hash {
+            info!("Machine limits: sha256 {}", machine_hash);
+        }
+        for step in &self.preprocessing {
+            info!("{}", step);
+        }
+        for config_override in &self.overrides {
+            info!("Override: {}", config_override);
+        }
+    }
+}
+
+fn hash(data: impl AsRef<[u8]>) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Options that change the G-code actually streamed, per gcode step
+fn describe_steps(steps: &[StepConfig], prefix: &str, descriptions: &mut Vec<String>) {
+    for (i, step) in steps.iter().enumerate() {
+        let number = format!("{}{}", prefix, i + 1);
+
+        match &step.step {
+            Step::Gcode(step) => descriptions.push(format!(
+                "Step {} G-code '{}': dry_run={}, dry_run_raise_z={:?}, start_line={:?}, overrides={:?}, post={:?}",
+                number, step.path, step.dry_run, step.dry_run_raise_z, step.start_line, step.overrides, step.post
+            )),
+            Step::Repeat(step) => describe_steps(&step.steps, &format!("{}.", number), descriptions),
+            _ => {}
+        }
+    }
+}
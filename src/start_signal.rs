@@ -0,0 +1,102 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{SignalFallback, expand_path};
+use crate::gpio::Watch;
+use crate::tui;
+use crossbeam::channel::{self, Receiver, RecvTimeoutError};
+
+/// The input that starts a sequence, the signal button or its fallback when GPIO is unavailable
+pub enum StartSignal {
+    Gpio(Box<dyn Watch>),
+    // Short presses of a signal button that also takes long and double presses
+    Presses(u8, Receiver<()>),
+    // A pending read is kept across polls, so a wait that is given up on doesn't leave a
+    // second reader competing for the next line
+    Console(Option<Receiver<io::Result<String>>>),
+    File(String),
+}
+
+impl From<&SignalFallback> for StartSignal {
+    fn from(fallback: &SignalFallback) -> Self {
+        match fallback {
+            SignalFallback::Console => StartSignal::Console(None),
+            SignalFallback::File(path) => StartSignal::File(expand_path(path)),
+        }
+    }
+}
+
+impl fmt::Display for StartSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartSignal::Gpio(input) => write!(f, "GPIO {}", input.pin()),
+            StartSignal::Presses(pin, _) => write!(f, "short press on GPIO {}", pin),
+            StartSignal::Console(_) => write!(f, "Enter on the console"),
+            StartSignal::File(path) => write!(f, "creating '{}'", path),
+        }
+    }
+}
+
+impl StartSignal {
+    /// Forgets presses from before the wait, which were meant for something else
+    pub fn reset(&mut self) {
+        if let StartSignal::Presses(_, presses) = self {
+            while presses.try_recv().is_ok() {}
+        }
+    }
+
+    /// Waits up to `timeout` for the signal, returns whether it triggered
+    pub fn poll(&mut self, timeout: Duration) -> Result<bool, String> {
+        match self {
+            StartSignal::Gpio(input) => input
+                .poll(Some(timeout))
+                .map(|edge| edge.is_some())
+                .map_err(|error| format!("Failed to poll signal input: {}", error)),
+            StartSignal::Presses(_, presses) => match presses.recv_timeout(timeout) {
+                Ok(()) => Ok(true),
+                Err(RecvTimeoutError::Timeout) => Ok(false),
+                Err(RecvTimeoutError::Disconnected) => Err("Signal button stopped".to_string()),
+            },
+            StartSignal::Console(pending) => {
+                let lines = pending.get_or_insert_with(|| {
+                    let (tx, rx) = channel::bounded(1);
+                    thread::spawn(move || {
+                        // The terminal UI has the console's input while it's up
+                        if tui::active() {
+                            let _ = tx.send(tui::read_line("Press Enter to start"));
+                            return;
+                        }
+                        let mut line = String::new();
+                        let _ = tx.send(io::stdin().read_line(&mut line).map(|_| line));
+                    });
+                    rx
+                });
+
+                let result = match lines.recv_timeout(timeout) {
+                    Ok(Ok(_)) => Ok(true),
+                    Ok(Err(error)) => Err(format!("Failed to read the console: {}", error)),
+                    Err(RecvTimeoutError::Timeout) => return Ok(false),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        Err("Console reader stopped".to_string())
+                    }
+                };
+                *pending = None;
+                result
+            }
+            StartSignal::File(path) => {
+                if Path::new(path).exists() {
+                    // Consume the trigger so it only starts one sequence
+                    fs::remove_file(&*path)
+                        .map_err(|error| format!("Failed to remove '{}': {}", path, error))?;
+                    return Ok(true);
+                }
+                thread::sleep(timeout);
+                Ok(false)
+            }
+        }
+    }
+}
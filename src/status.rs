@@ -0,0 +1,304 @@
+use std::sync::Mutex;
+
+use crossbeam::channel::{self, Receiver, Sender};
+
+use crate::config::{Level, StatusConfig};
+use crate::controller::message::Report;
+use crate::gpio::{self, Output};
+use crate::json::{optional_string, string};
+use log::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Idle,
+    Waiting,
+    Running,
+    Error,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Waiting => "waiting",
+            State::Running => "running",
+            State::Error => "error",
+        }
+    }
+}
+
+/// The step running, or the last one to run
+#[derive(Clone)]
+pub struct Step {
+    pub number: usize,
+    pub name: String,
+    pub kind: &'static str,
+}
+
+impl Step {
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"number\": {}, \"name\": {}, \"type\": {}}}",
+            self.number,
+            string(&self.name),
+            string(self.kind)
+        )
+    }
+}
+
+/// A probe result, as it arrives while a G-code program streams
+#[derive(Clone)]
+pub struct Probe {
+    pub line: i32,
+    pub position: (f64, f64, f64),
+    pub contact: bool,
+    /// Why the probe validator flagged the point, if it did
+    pub outlier: Option<String>,
+    /// Left out of the probe file by the `discard` outlier policy
+    pub discarded: bool,
+}
+
+impl Probe {
+    pub fn json(&self) -> String {
+        let (x, y, z) = self.position;
+        format!(
+            "{{\"line\": {}, \"position\": {{\"x\": {}, \"y\": {}, \"z\": {}}}, \"contact\": {}, \"outlier\": {}, \"discarded\": {}}}",
+            self.line,
+            x,
+            y,
+            z,
+            self.contact,
+            optional_string(self.outlier.as_deref()),
+            self.discarded
+        )
+    }
+}
+
+/// What the job is doing, for MQTT, the HTTP API and the terminal UI. Kept whether or not the
+/// outputs are set up
+#[derive(Clone)]
+pub struct Job {
+    pub state: State,
+    pub step: Option<Step>,
+    /// Percent of the running G-code program streamed
+    pub progress: Option<f64>,
+    /// Last line of the running G-code program acknowledged
+    pub line: Option<i32>,
+}
+
+/// A change to what the job is doing, sent to each subscriber
+#[derive(Clone)]
+pub enum Event {
+    State(State),
+    Step(Step),
+    Probe(Probe),
+}
+
+static SUBSCRIBERS: Mutex<Vec<Sender<Event>>> = Mutex::new(Vec::new());
+
+static JOB: Mutex<Job> = Mutex::new(Job {
+    state: State::Idle,
+    step: None,
+    progress: None,
+    line: None,
+});
+
+struct StatusOutputs {
+    config: StatusConfig,
+    pins: Vec<(u8, Box<dyn Output>)>,
+    state: Option<State>,
+}
+
+// Pins are never dropped, so they keep showing the last state after cnc-ctrl exits
+static STATUS: Mutex<Option<StatusOutputs>> = Mutex::new(None);
+
+/// Sets up the status outputs and shows `Idle`. Without GPIO, e.g. on the bench,
+/// the outputs are disabled rather than stopping the job
+pub fn init(config: &StatusConfig) {
+    let mut numbers: Vec<u8> = [config.idle, config.waiting, config.running, config.error]
+        .into_iter()
+        .flatten()
+        .collect();
+    numbers.sort();
+    numbers.dedup();
+
+    let pins = match gpio::open().and_then(|gpio| {
+        numbers
+            .iter()
+            .map(|&number| Ok((number, gpio.output(number)?)))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()
+    }) {
+        Ok(pins) => pins,
+        Err(error) => {
+            warn!("Status outputs disabled, GPIO unavailable: {}", error);
+            return;
+        }
+    };
+
+    *lock_status() = Some(StatusOutputs {
+        config: config.clone(),
+        pins,
+        state: None,
+    });
+    set(State::Idle);
+}
+
+/// Drives the pin for `state` high and every other status pin low, and tells the subscribers
+pub fn set(state: State) {
+    if std::mem::replace(&mut lock_job().state, state) != state {
+        publish(Event::State(state));
+    }
+
+    let mut status = lock_status();
+    let Some(status) = status.as_mut() else {
+        return;
+    };
+    if status.state == Some(state) {
+        return;
+    }
+
+    let active = match state {
+        State::Idle => status.config.idle,
+        State::Waiting => status.config.waiting,
+        State::Running => status.config.running,
+        State::Error => status.config.error,
+    };
+    for (number, pin) in &mut status.pins {
+        let level = if Some(*number) == active {
+            Level::High
+        } else {
+            Level::Low
+        };
+        if let Err(error) = pin.set(level) {
+            warn!("Failed to set status output GPIO {}: {}", number, error);
+        }
+    }
+
+    debug!("Status: {:?}", state);
+    status.state = Some(state);
+}
+
+fn lock_status() -> std::sync::MutexGuard<'static, Option<StatusOutputs>> {
+    match STATUS.lock() {
+        Ok(status) => status,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Records the step about to run and tells the subscribers
+pub fn step(number: usize, name: &str, kind: &'static str) {
+    let step = Step {
+        number,
+        name: name.to_string(),
+        kind,
+    };
+    {
+        let mut job = lock_job();
+        job.step = Some(step.clone());
+        job.progress = None;
+        job.line = None;
+    }
+    publish(Event::Step(step));
+}
+
+/// Tells the subscribers about a probe result
+pub fn probe(probe: Probe) {
+    publish(Event::Probe(probe));
+}
+
+/// Receives every change of state and step, and every probe result, from here on, e.g. to publish them
+pub fn subscribe() -> Receiver<Event> {
+    let (tx, rx) = channel::unbounded();
+    lock_subscribers().push(tx);
+    rx
+}
+
+pub fn state() -> State {
+    lock_job().state
+}
+
+pub fn job() -> Job {
+    lock_job().clone()
+}
+
+// Subscribers that went away are dropped
+fn publish(event: Event) {
+    lock_subscribers().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+/// Sets the progress through the G-code program streaming
+pub fn set_progress(percent: Option<f64>) {
+    lock_job().progress = percent;
+}
+
+/// Sets the line of the G-code program last acknowledged
+pub fn set_line(line: i32) {
+    lock_job().line = Some(line);
+}
+
+/// The job's state with the machine's status from `report`, as published over MQTT and served
+/// by the HTTP API
+pub fn json(report: Option<&Report>, paused: bool) -> String {
+    object(&fields(report, paused))
+}
+
+/// The fields of the status, each with its value as JSON, in the order `json` writes them
+pub fn fields(report: Option<&Report>, paused: bool) -> Vec<(&'static str, String)> {
+    let Job {
+        state,
+        step,
+        progress,
+        ..
+    } = job();
+
+    let position = |position: Option<(f32, f32, f32)>| match position {
+        Some((x, y, z)) => format!("{{\"x\": {}, \"y\": {}, \"z\": {}}}", x, y, z),
+        None => "null".to_string(),
+    };
+    let machine = report.and_then(Report::machine_position);
+    let work = report.and_then(Report::work_position);
+    let machine_state = report.map(Report::state);
+    let step = step.map_or("null".to_string(), |step| step.json());
+
+    vec![
+        ("state", string(state.name())),
+        ("paused", paused.to_string()),
+        ("machine_state", optional_string(machine_state)),
+        ("step", step),
+        (
+            "progress",
+            progress.map_or("null".to_string(), |progress| format!("{:.1}", progress)),
+        ),
+        ("position", position(machine)),
+        ("work_position", position(work)),
+        (
+            "feed",
+            report
+                .and_then(|report| report.feed)
+                .map_or("null".to_string(), |feed| feed.to_string()),
+        ),
+    ]
+}
+
+/// A JSON object of `fields`, as returned by `fields`
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", string(name), value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn lock_job() -> std::sync::MutexGuard<'static, Job> {
+    match JOB.lock() {
+        Ok(job) => job,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn lock_subscribers() -> std::sync::MutexGuard<'static, Vec<Sender<Event>>> {
+    match SUBSCRIBERS.lock() {
+        Ok(subscribers) => subscribers,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
@@ -1,34 +1,253 @@
+mod backlash;
 mod bash;
+mod calibrate;
+mod count;
 mod gcode;
+mod gpio;
+mod home;
+mod http;
+mod jog;
+mod repeat;
+mod set_offset;
+mod settings;
+mod toolchange;
+mod wait;
 
-use super::config::{CncConfig, Step};
+use std::sync::atomic::AtomicBool;
+
+use log::info;
+
+use super::config::{Axis, CncConfig, Step, StepConfig, TemplateVars};
 use super::controller::Controller;
+use super::controller::message::{Report, Status};
+use super::controller::serial::{checked_stream, wait_for_report};
+use super::job::wait_for_signal_or;
+use super::limits::component;
+use super::start_signal::StartSignal;
 
-use bash::execute_bash_step;
+pub use bash::{run_hook, stop_background};
+pub use gcode::{check_program, check_program_in_grbl, line_matches};
+pub use home::execute_home_step;
+pub use jog::execute_jog_step;
+
+use backlash::execute_backlash_step;
+use bash::{execute_bash_step, execute_join_step};
+use calibrate::execute_calibrate_step;
+use count::execute_count_step;
 use gcode::execute_gcode_step;
+use gpio::execute_gpio_step;
+use http::execute_http_step;
+use repeat::execute_repeat_step;
+use set_offset::execute_set_offset_step;
+use settings::execute_settings_step;
+use toolchange::execute_toolchange_step;
+use wait::execute_wait_step;
+
+impl StepConfig {
+    /// The step's name, or its number if it has none
+    pub fn label(&self, number: usize) -> String {
+        self.name.clone().unwrap_or_else(|| number.to_string())
+    }
+
+    pub fn should_wait(&self) -> bool {
+        self.step.should_wait()
+    }
+
+    /// Waits for the start signal between the step's `before_wait` and `after_wait` G-code,
+    /// returns false if the job is stopped or `cancel` is set first
+    pub fn wait(
+        &self,
+        controller: &Controller,
+        config: &CncConfig,
+        signal: &mut StartSignal,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        stream_and_wait(controller, config, &self.before_wait)
+            .map_err(|error| format!("Failed to run before_wait G-code: {}", error))?;
+
+        info!("Waiting for start signal ({})...", signal);
+        if !wait_for_signal_or(controller, config, signal, cancel)? {
+            return Ok(false);
+        }
+
+        stream_and_wait(controller, config, &self.after_wait)
+            .map_err(|error| format!("Failed to run after_wait G-code: {}", error))?;
+
+        Ok(true)
+    }
+
+    pub fn execute(
+        &self,
+        controller: &Controller,
+        vars: &mut TemplateVars,
+        config: &CncConfig,
+        signal: &mut StartSignal,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let result = self.step.execute(controller, vars, config, signal);
+
+        match &result {
+            Ok(()) => {
+                if let Some(hook) = &self.hooks.on_success {
+                    run_hook("on_success", hook, vars, None);
+                }
+            }
+            Err(error) => {
+                if let Some(hook) = &self.hooks.on_failure {
+                    run_hook("on_failure", hook, vars, Some(&error.to_string()));
+                }
+            }
+        }
+
+        result
+    }
+}
 
 impl Step {
+    /// The step's type, as written in the config
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Step::Gcode(_) => "gcode",
+            Step::Bash(_) => "bash",
+            Step::Calibrate(_) => "calibrate",
+            Step::Backlash(_) => "backlash",
+            Step::Count(_) => "count",
+            Step::Jog(_) => "jog",
+            Step::Home(_) => "home",
+            Step::Toolchange(_) => "toolchange",
+            Step::Gpio(_) => "gpio",
+            Step::Wait(_) => "wait",
+            Step::Http(_) => "http",
+            Step::SetOffset(_) => "set_offset",
+            Step::Settings(_) => "settings",
+            Step::Repeat(_) => "repeat",
+            Step::Join(_) => "join",
+        }
+    }
+
     pub fn should_wait(&self) -> bool {
         match self {
             Step::Gcode(step) => step.wait_for_signal,
             Step::Bash(step) => step.wait_for_signal,
+            Step::Calibrate(step) => step.wait_for_signal,
+            Step::Backlash(step) => step.wait_for_signal,
+            Step::Count(_) => false,
+            Step::Jog(step) => step.wait_for_signal,
+            Step::Home(step) => step.wait_for_signal,
+            Step::Toolchange(step) => step.wait_for_signal,
+            Step::Gpio(step) => step.wait_for_signal,
+            Step::Wait(step) => step.wait_for_signal,
+            Step::Http(step) => step.wait_for_signal,
+            Step::SetOffset(step) => step.wait_for_signal,
+            Step::Settings(step) => step.wait_for_signal,
+            Step::Repeat(step) => step.wait_for_signal,
+            Step::Join(step) => step.wait_for_signal,
         }
     }
 
     pub fn execute(
         &self,
         controller: &Controller,
-        timestamp: &str,
+        vars: &mut TemplateVars,
         config: &CncConfig,
+        signal: &mut StartSignal,
     ) -> Result<(), Box<dyn std::error::Error>> {
         match self {
-            Step::Gcode(step) => execute_gcode_step(
-                step,
-                controller,
-                timestamp,
-                config.grbl.rx_buffer_size_bytes,
-            ),
-            Step::Bash(step) => execute_bash_step(step, timestamp),
+            Step::Gcode(step) => execute_gcode_step(step, controller, vars, config),
+            Step::Bash(step) => execute_bash_step(step, vars),
+            Step::Calibrate(step) => execute_calibrate_step(step, controller, config),
+            Step::Backlash(step) => execute_backlash_step(step, controller, config),
+            Step::Count(step) => execute_count_step(step, vars),
+            Step::Jog(step) => execute_jog_step(step, controller, config),
+            Step::Home(step) => execute_home_step(step, controller, config),
+            Step::Toolchange(step) => execute_toolchange_step(step, controller, config, signal),
+            Step::Gpio(step) => execute_gpio_step(step),
+            Step::Wait(step) => execute_wait_step(step, controller),
+            Step::Http(step) => execute_http_step(step, vars),
+            Step::SetOffset(step) => execute_set_offset_step(step, controller, config),
+            Step::Settings(step) => execute_settings_step(step, controller, vars, config),
+            Step::Repeat(step) => execute_repeat_step(step, controller, vars, config, signal),
+            Step::Join(step) => execute_join_step(step),
         }
     }
 }
+
+// Streams a block of G-code and waits for its moves to finish
+fn stream_and_wait(
+    controller: &Controller,
+    config: &CncConfig,
+    lines: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    checked_stream(
+        controller,
+        lines.iter().map(String::as_str).collect(),
+        &config.grbl,
+    )?;
+
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )?
+    .ok_or("Stopped while waiting for the machine")?;
+
+    Ok(())
+}
+
+fn relative_move(
+    controller: &Controller,
+    config: &CncConfig,
+    axis: Axis,
+    distance: f64,
+    feed: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    check_move(controller, config, &[(axis, distance)], feed, true)
+        .map_err(|error| format!("Refusing to move {} axis: {}", axis, error))?;
+
+    let motion = format!("G21 G91 G1 {}{} F{}", axis, distance, feed);
+
+    checked_stream(controller, vec![&motion, "G90"], &config.grbl)
+        .map_err(|error| format!("Failed to move {} axis: {}", axis, error))?;
+
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )?;
+
+    Ok(())
+}
+
+// Validate a generated move against the machine's travel and feed limits before it is sent
+pub fn check_move(
+    controller: &Controller,
+    config: &CncConfig,
+    moves: &[(Axis, f64)],
+    feed: f64,
+    relative: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(machine) = &config.machine else {
+        return Ok(());
+    };
+
+    // Absolute targets are in work coordinates, so the work offset is needed to convert them
+    let report = wait_for_report(
+        controller,
+        Some(|report: &Report| report.mpos.is_some() && (relative || report.wco.is_some())),
+    )?
+    .ok_or("Failed to read machine position")?;
+
+    let targets: Vec<(Axis, f64)> = moves
+        .iter()
+        .map(|&(axis, value)| match (relative, report.mpos, report.wco) {
+            (true, Some(mpos), _) => (axis, component(mpos, axis) + value),
+            (false, _, Some(wco)) => (axis, component(wco, axis) + value),
+            _ => (axis, value),
+        })
+        .collect();
+
+    machine.check(&targets, feed)?;
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use log::info;
+
+use crate::config::{BacklashStepConfig, CncConfig};
+use crate::controller::Controller;
+use crate::display;
+use crate::prompt::ask;
+
+use super::relative_move;
+
+pub fn execute_backlash_step(
+    step: &BacklashStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut results = Vec::new();
+    let distance = step.units.to_mm(step.distance);
+    let feed = step.units.to_mm(step.feed);
+
+    for &axis in &step.axes {
+        info!("Measuring {} axis backlash", axis);
+
+        // Approach the measurement position from the negative side to take up any slack
+        relative_move(controller, config, axis, -distance, feed)?;
+        relative_move(controller, config, axis, distance, feed)?;
+
+        ask(&format!(
+            "Zero the dial indicator against the {} axis, then press Enter:",
+            axis
+        ))?;
+
+        // Return to the same nominal position from the positive side
+        relative_move(controller, config, axis, distance, feed)?;
+        relative_move(controller, config, axis, -distance, feed)?;
+
+        let reading: f64 = ask(&format!(
+            "Dial indicator reading on {} ({}):",
+            axis, step.units
+        ))?
+        .parse()
+        .map_err(|error| format!("Invalid dial indicator reading: {}", error))?;
+
+        let backlash = step.units.to_mm(reading.abs());
+        info!("{} axis backlash: {}", axis, display::length(backlash));
+        results.push((axis, backlash));
+    }
+
+    info!(
+        "Backlash report: {}",
+        results
+            .iter()
+            .map(|(axis, backlash)| format!("{}={}", axis, display::length(*backlash)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    Ok(())
+}
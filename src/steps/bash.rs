@@ -1,36 +1,287 @@
-use std::process::Command;
+use std::fs::{self, File};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use log::info;
+use log::{info, warn};
 
-use crate::config::{BashStepConfig, apply_template, expand_path};
+use crate::config::{
+    BashCommand, BashStepConfig, JoinStepConfig, TemplateVars, apply_template, expand_home,
+    expand_path,
+};
+
+const DEFAULT_SHELL: &str = "sh";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+// Commands started with `background`, until a join step or the end of the job
+static BACKGROUND: Mutex<Vec<(String, Child)>> = Mutex::new(Vec::new());
 
 pub fn execute_bash_step(
     step: &BashStepConfig,
-    timestamp: &str,
+    vars: &mut TemplateVars,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let expanded_command = expand_path(&step.command);
-    let templated_command = apply_template(&expanded_command, timestamp);
+    if step.capture.is_some() && step.background {
+        return Err("Output of a background command can't be captured".into());
+    }
+    if step.capture.is_some() && step.stdout.is_some() {
+        return Err("Output of a command redirected with stdout can't be captured".into());
+    }
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(&templated_command)
-        .output()
+    let (command, description) = build_command(step, vars)?;
+
+    if step.background {
+        return spawn_background(command, description);
+    }
+
+    let output = run(command, &description)?;
+
+    if let Some(name) = &step.capture {
+        info!("Captured {{%{}}} = '{}'", name, output);
+        vars.captures.insert(name.clone(), output);
+    }
+
+    Ok(())
+}
+
+// Returns the step's process ready to run, along with a description of it for the log
+fn build_command(
+    step: &BashStepConfig,
+    vars: &TemplateVars,
+) -> Result<(Command, String), Box<dyn std::error::Error>> {
+    let template = |text: &str| apply_template(&expand_path(text), vars);
+    // Commands and their environment are passed on with `$` as written, e.g. for a list starting
+    // with `sh -c` or an `env` value of `$PATH:...`, for the shell or program to expand
+    let template_command = |text: &str| apply_template(&expand_home(text), vars);
+
+    let (mut command, description) = match &step.command {
+        BashCommand::Shell(command) => {
+            let templated_command = template_command(command);
+            let shell = step.shell.as_deref().unwrap_or(DEFAULT_SHELL);
+            (shell_command(shell, &templated_command), templated_command)
+        }
+        // Each argument is templated on its own, so values with spaces or quotes stay one argument
+        BashCommand::Argv(argv) => {
+            if step.shell.is_some() {
+                return Err("A shell can't be used with a command given as a list".into());
+            }
+
+            let argv: Vec<String> = argv.iter().map(|arg| template_command(arg)).collect();
+            let Some((program, args)) = argv.split_first() else {
+                return Err("Command list is empty".into());
+            };
+
+            let mut command = Command::new(program);
+            command.args(args);
+            (command, argv.join(" "))
+        }
+    };
+
+    for (name, value) in &step.env {
+        command.env(name, template_command(value));
+    }
+    if let Some(cwd) = &step.cwd {
+        command.current_dir(template(cwd));
+    }
+    if let Some(path) = &step.stdout {
+        command.stdout(create_output(&template(path))?);
+    }
+    if let Some(path) = &step.stderr {
+        command.stderr(create_output(&template(path))?);
+    }
+
+    Ok((command, description))
+}
+
+fn shell_command(shell: &str, command: &str) -> Command {
+    let mut shell = Command::new(shell);
+    shell.arg("-c").arg(command);
+    shell
+}
+
+fn create_output(path: &str) -> Result<File, Box<dyn std::error::Error>> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = File::create(path)
+        .map_err(|error| format!("Failed to create output file '{}': {}", path, error))?;
+
+    Ok(file)
+}
+
+fn spawn_background(
+    mut command: Command,
+    description: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A process group of its own lets the command's children be stopped along with it
+    let child = command
+        .stdin(Stdio::null())
+        .process_group(0)
+        .spawn()
         .map_err(|error| {
             format!(
-                "Failed to execute command '{}': {}",
-                templated_command, error
+                "Failed to start background command '{}': {}",
+                description, error
             )
         })?;
 
+    info!(
+        "Started background command '{}' (pid {})",
+        description,
+        child.id()
+    );
+    lock_background().push((description, child));
+
+    Ok(())
+}
+
+pub fn execute_join_step(step: &JoinStepConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let mut background = std::mem::take(&mut *lock_background());
+    if background.is_empty() {
+        info!("No background commands to join");
+        return Ok(());
+    }
+
+    if step.kill {
+        for (command, child) in &mut background {
+            terminate(command, child);
+        }
+        return Ok(());
+    }
+
+    info!("Waiting for {} background command(s)", background.len());
+
+    let deadline = step
+        .timeout_ms
+        .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
+    let mut failed = Vec::new();
+
+    while !background.is_empty() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            for (command, child) in &mut background {
+                warn!("Background command '{}' timed out", command);
+                terminate(command, child);
+            }
+            return Err(format!("{} background command(s) timed out", background.len()).into());
+        }
+
+        let mut running = Vec::new();
+        for (command, mut child) in background {
+            match child.try_wait()? {
+                Some(status) if status.success() => {
+                    info!("Background command '{}' completed", command)
+                }
+                Some(status) => failed.push(format!("'{}' exited with {}", command, status)),
+                None => running.push((command, child)),
+            }
+        }
+        background = running;
+
+        if !background.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(format!("Background commands failed: {}", failed.join(", ")).into());
+    }
+
+    Ok(())
+}
+
+/// Stops any background commands still running when the job ends
+pub fn stop_background() {
+    let mut background = std::mem::take(&mut *lock_background());
+    for (command, child) in &mut background {
+        terminate(command, child);
+    }
+}
+
+fn lock_background() -> std::sync::MutexGuard<'static, Vec<(String, Child)>> {
+    match BACKGROUND.lock() {
+        Ok(background) => background,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// SIGTERM the command's process group so scripts get a chance to clean up, then kill it
+fn terminate(command: &str, child: &mut Child) {
+    if let Ok(Some(_)) = child.try_wait() {
+        return;
+    }
+
+    info!("Stopping background command '{}'", command);
+    let group = format!("-{}", child.id());
+    if let Err(error) = Command::new("kill").args(["-TERM", "--", &group]).status() {
+        warn!("Failed to signal '{}': {}", command, error);
+    }
+
+    let deadline = Instant::now() + TERMINATE_GRACE;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    warn!("Background command '{}' didn't stop, killing it", command);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Runs an `on_success` or `on_failure` hook, a failing hook is only logged
+pub fn run_hook(name: &str, command: &str, vars: &TemplateVars, error: Option<&str>) {
+    info!("Running {} hook", name);
+
+    let expanded_command = expand_home(command);
+    let templated_command = apply_template(&expanded_command, vars);
+
+    // The error is passed in the environment so its text can't be interpreted by the shell
+    let mut hook = shell_command(DEFAULT_SHELL, &templated_command);
+    if let Some(error) = error {
+        hook.env("CNC_CTRL_ERROR", error);
+    }
+
+    if let Err(error) = run(hook, &templated_command) {
+        warn!("{} hook failed: {}", name, error);
+    }
+}
+
+// Returns the command's trimmed stdout
+pub fn run_command(
+    command: &str,
+    vars: &TemplateVars,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let expanded_command = expand_home(command);
+    let templated_command = apply_template(&expanded_command, vars);
+
+    run(
+        shell_command(DEFAULT_SHELL, &templated_command),
+        &templated_command,
+    )
+}
+
+fn run(mut command: Command, description: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = command
+        .output()
+        .map_err(|error| format!("Failed to execute command '{}': {}", description, error))?;
+
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
+        // Nothing is captured when stderr is redirected to a file
+        if stderr.trim().is_empty() {
+            return Err(format!("Command failed: exited with {}", output.status).into());
+        }
         return Err(format!("Command failed: {}", stderr).into());
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    if !stdout.trim().is_empty() {
-        info!("Command output: {}", stdout.trim());
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !stdout.is_empty() {
+        info!("Command output: {}", stdout);
     }
 
-    Ok(())
+    Ok(stdout)
 }
@@ -0,0 +1,85 @@
+use log::{info, warn};
+
+use crate::config::{Axis, CalibrateStepConfig, CncConfig};
+use crate::controller::Controller;
+use crate::controller::message::Response;
+use crate::controller::serial::checked_stream;
+use crate::display;
+use crate::prompt::{ask, confirm};
+
+use super::relative_move;
+
+pub fn execute_calibrate_step(
+    step: &CalibrateStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let setting = match step.axis {
+        Axis::X => 100,
+        Axis::Y => 101,
+        Axis::Z => 102,
+    };
+
+    let current = checked_stream(controller, vec!["$$"], &config.grbl)
+        .map_err(|error| format!("Failed to read settings: {}", error))?
+        .into_iter()
+        .find_map(|(_, response)| match response {
+            Response::Setting { id, value } if id == setting => value.parse::<f64>().ok(),
+            _ => None,
+        })
+        .ok_or(format!("Failed to read ${}", setting))?;
+
+    info!(
+        "Calibrating {} axis (currently ${}={:.3}), moving {}",
+        step.axis,
+        setting,
+        current,
+        display::length(step.units.to_mm(step.distance))
+    );
+
+    relative_move(
+        controller,
+        config,
+        step.axis,
+        step.units.to_mm(step.distance),
+        step.units.to_mm(step.feed),
+    )?;
+
+    let measured: f64 = ask(&format!(
+        "Measured distance travelled on {} ({}):",
+        step.axis, step.units
+    ))?
+    .parse()
+    .map_err(|error| format!("Invalid measured distance: {}", error))?;
+
+    if measured <= 0.0 {
+        return Err(format!("Measured distance must be positive, got {}", measured).into());
+    }
+
+    let corrected = current * step.distance / measured;
+
+    info!(
+        "Commanded {}, measured {}: ${} {:.3} -> {:.3} steps/mm",
+        display::length(step.units.to_mm(step.distance)),
+        display::length(step.units.to_mm(measured)),
+        setting,
+        current,
+        corrected
+    );
+
+    if !confirm(&format!("Write ${}={:.3}?", setting, corrected))? {
+        warn!("Calibration for {} axis not applied", step.axis);
+        return Ok(());
+    }
+
+    let write = format!("${}={:.3}", setting, corrected);
+    checked_stream(controller, vec![&write], &config.grbl)
+        .map_err(|error| format!("Failed to write ${}: {}", setting, error))?;
+
+    info!(
+        "Updated {} axis steps/mm: ${} before {:.3}, after {:.3}",
+        step.axis, setting, current, corrected
+    );
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use log::info;
+
+use crate::config::{CountStepConfig, TemplateVars};
+
+pub fn execute_count_step(
+    step: &CountStepConfig,
+    vars: &mut TemplateVars,
+) -> Result<(), Box<dyn std::error::Error>> {
+    vars.count += step.amount;
+
+    info!("Part counter advanced to {}", vars.count);
+
+    Ok(())
+}
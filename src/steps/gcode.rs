@@ -1,86 +1,491 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+mod dry_run;
+mod home_moves;
+mod post;
+mod probe;
+mod program;
+mod progress;
+mod reports;
+mod responses;
+mod resume;
+mod retry;
+mod syntax;
+mod words;
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
 use log::{error, info, warn};
 
-use crate::config::{GcodeStepConfig, ProbeConfig, apply_template, expand_path};
-use crate::controller::command::Command;
+use crate::alarm;
+use crate::config::{
+    Axis, CncConfig, ErrorPolicy, GcodeStepConfig, HomeMovesPolicy, OutlierPolicy, PostPreset,
+    ProbeConfig, QcConfig, TemplateVars, apply_template, expand_path,
+};
+use crate::controller::command::Realtime;
 use crate::controller::message::{Report, Response, Status};
-use crate::controller::serial::{buffered_stream, wait_for_report};
+use crate::controller::serial::{
+    StreamObserver, WithResponses, buffered_stream, checked_stream, wait_for_report,
+};
 use crate::controller::{Controller, ControllerError};
+use crate::display;
+use crate::interlock;
+use crate::job;
+use crate::prompt::confirm;
+use crate::status;
+use crate::tui;
+
+use super::bash::run_command;
+
+use home_moves::HomeMoveParser;
+use probe::{ProbeValidator, Summary, write_points};
+use program::Program;
+use progress::Progress;
+use reports::{Recorded, ReportLog};
+use responses::{Logged, ResponseLog};
+use resume::{ModalState, read_checkpoint, write_checkpoint};
+use retry::{read_offsets, restore_offsets};
+use syntax::SyntaxChecker;
+
+pub use words::line_matches;
+
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+// Invalid lines listed when checking a program, the rest are only counted
+const MAX_LISTED_INVALID_LINES: usize = 10;
 
 pub fn execute_gcode_step(
     step: &GcodeStepConfig,
     controller: &Controller,
-    timestamp: &str,
-    rx_buffer_size: usize,
+    vars: &TemplateVars,
+    config: &CncConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let expanded_path = expand_path(&step.path);
-    let templated_path = apply_template(&expanded_path, timestamp);
+    let templated_path = apply_template(&expanded_path, vars);
 
-    let file = File::open(&templated_path)
-        .map_err(|error| format!("Failed to open G-code file '{}': {}", templated_path, error))?;
-    let reader = BufReader::new(file);
+    if templated_path == "-" {
+        info!("Reading G-code from stdin");
+    }
 
-    let gcode_lines: Vec<String> = reader
-        .lines()
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|error| format!("Failed to read G-code file: {}", error))?;
+    if step.dry_run {
+        match step.dry_run_raise_z {
+            Some(raise_z) => warn!(
+                "Dry run: spindle, laser and coolant disabled, Z raised by {}",
+                display::length(raise_z)
+            ),
+            None => warn!("Dry run: spindle, laser and coolant disabled"),
+        }
+    }
 
-    let gcode: Vec<&str> = gcode_lines.iter().map(|s| s.as_str()).collect();
+    if let Some(post) = step.post {
+        info!("Adapting G-code from {}: {}", post, post.describe());
 
-    let output_writer = if let Some(ProbeConfig {
-        save_path: Some(save_path),
-    }) = &step.probe
+        if post.needs_laser_mode() {
+            check_laser_mode(controller, config, post)?;
+        }
+    }
+
+    let mut program = Program::open(
+        &templated_path,
+        step.dry_run.then_some(step.dry_run_raise_z),
+        step.post,
+    )?;
+
+    if step.home_moves != HomeMovesPolicy::Allow {
+        intercept_home_moves(step, controller, config, &mut program)?;
+    }
+
+    if step.dry_run && !config.interlocks.is_empty() {
+        info!("Dry run: interlocks are not switched on");
+        return run_with_retry(step, controller, vars, config, &program);
+    }
+
+    let interlocks = interlock::engage(controller, config)?;
+    let result = run_with_retry(step, controller, vars, config, &program);
+    interlocks.release(controller, config, result.is_ok());
+
+    result
+}
+
+/// Reads the program at `path` as it would be streamed and checks every line parses,
+/// returning the number of lines. Stdin and live sources can't be read in advance, so give `None`
+pub fn check_program(
+    path: &str,
+    post: Option<PostPreset>,
+) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    if path == "-" {
+        return Ok(None);
+    }
+
+    let program = Program::open(path, None, post)?;
+    if program.is_live() {
+        return Ok(None);
+    }
+
+    let checker = SyntaxChecker::new();
+    let mut count = 0;
+    let mut invalid = Vec::new();
+    for (index, line) in program.lines()?.enumerate() {
+        count += 1;
+        if !checker.is_valid(&line) {
+            invalid.push(format!("line {}: '{}'", index + 1, line.trim()));
+        }
+    }
+    program.finish()?;
+
+    if invalid.is_empty() {
+        return Ok(Some(count));
+    }
+
+    let mut message = format!("Invalid G-code in '{}': ", path);
+    message.push_str(&invalid[..invalid.len().min(MAX_LISTED_INVALID_LINES)].join(", "));
+    if invalid.len() > MAX_LISTED_INVALID_LINES {
+        message.push_str(&format!(
+            " and {} more",
+            invalid.len() - MAX_LISTED_INVALID_LINES
+        ));
+    }
+    Err(message.into())
+}
+
+/// A line Grbl rejected in check mode
+pub struct Rejected {
+    pub line: usize,
+    pub text: String,
+    pub response: Response,
+}
+
+/// Checks the program at `path` in Grbl's check mode, returning the lines Grbl rejects
+pub fn check_program_in_grbl(
+    path: &str,
+    post: Option<PostPreset>,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<Vec<Rejected>, Box<dyn std::error::Error>> {
+    let program = Program::open(path, None, post)?;
+    if program.is_live() {
+        return Err("A live G-code source can only be read once, so it can't be checked".into());
+    }
+
+    let errors = stream_check_mode(controller, program.lines()?, config, ())?;
+    program.finish()?;
+
+    // The program is read again for the text of the rejected lines
+    let mut lines = program.lines()?.enumerate();
+    let mut rejected = Vec::new();
+    for (line, response) in errors {
+        let text = lines
+            .find(|(index, _)| index + 1 == line as usize)
+            .map(|(_, text)| text.trim().to_string())
+            .unwrap_or_default();
+        rejected.push(Rejected {
+            line: line as usize,
+            text,
+            response,
+        });
+    }
+    program.finish()?;
+
+    Ok(rejected)
+}
+
+// Streams the lines in check mode ($C), which Grbl parses without moving, and returns the errors
+fn stream_check_mode<S: AsRef<str>, O: StreamObserver>(
+    controller: &Controller,
+    lines: impl IntoIterator<Item = S>,
+    config: &CncConfig,
+    observer: O,
+) -> Result<Vec<(i32, Response)>, Box<dyn std::error::Error>> {
+    // Waiting for the toggle's `ok` keeps it from being taken as the first line's
+    checked_stream(controller, vec!["$C"], &config.grbl)
+        .map_err(|error| format!("Failed to enable check mode: {}", error))?;
+
+    let result = buffered_stream(controller, lines, &config.grbl, observer)
+        .map_err(|error| format!("Failed to stream G-code in check mode: {}", error));
+
+    checked_stream(controller, vec!["$C"], &config.grbl)
+        .map_err(|error| format!("Failed to disable check mode: {}", error))?;
+
+    Ok(result?
+        .into_iter()
+        .filter(|(_, response)| matches!(response, Response::Error(_)))
+        .collect())
+}
+
+// Stale G28/G30 positions crash machines, so block, check or rewrite these moves before streaming
+fn intercept_home_moves(
+    step: &GcodeStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+    program: &mut Program,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A live source can't be scanned in advance, so assume it uses both
+    let used: Vec<(usize, &str, String)> = if program.is_live() {
+        if step.home_moves == HomeMovesPolicy::Block {
+            return Err("G28/G30 can't be blocked in a live G-code source".into());
+        }
+        vec![(0, "G28", String::new()), (0, "G30", String::new())]
+    } else {
+        let parser = HomeMoveParser::new();
+        let used = program
+            .lines()?
+            .enumerate()
+            .filter_map(|(index, line)| {
+                parser
+                    .find(&line)
+                    .map(|(command, axes)| (index + 1, command, axes))
+            })
+            .collect();
+        program.finish()?;
+        used
+    };
+
+    if used.is_empty() {
+        return Ok(());
+    }
+
+    let lines = used
+        .iter()
+        .filter(|(line, _, _)| *line > 0)
+        .map(|(line, command, _)| format!("{} ({})", line, command))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if step.home_moves == HomeMovesPolicy::Block {
+        return Err(format!("Program uses G28/G30 on line {}", lines).into());
+    }
+
+    let offsets = read_offsets(controller, config)?;
+    let stored = |command: &str| {
+        offsets
+            .iter()
+            .find(|(name, _)| name == command)
+            .map(|(_, coords)| *coords)
+            .ok_or(format!("Failed to read the stored {} position", command))
+    };
+    let (g28, g30) = (stored("G28")?, stored("G30")?);
+
+    for command in ["G28", "G30"] {
+        if !used.iter().any(|(_, used, _)| *used == command) {
+            continue;
+        }
+
+        let (x, y, z) = if command == "G28" { g28 } else { g30 };
+        info!(
+            "Stored {} position: {} (machine)",
+            command,
+            display::position((x, y, z))
+        );
+
+        match &config.machine {
+            Some(machine) => machine
+                .check(&[(Axis::X, x), (Axis::Y, y), (Axis::Z, z)], 0.0)
+                .map_err(|error| format!("Stored {} position is not sane: {}", command, error))?,
+            None => warn!(
+                "No machine limits configured, the stored {} position can't be checked",
+                command
+            ),
+        }
+    }
+
+    if step.home_moves == HomeMovesPolicy::Rewrite {
+        for (line, command, axes) in &used {
+            if *line > 0 && (axes.is_empty() || (axes.contains('Z') && axes != "Z")) {
+                warn!(
+                    "Line {}: {} only retracts Z, move X and Y separately",
+                    line, command
+                );
+            }
+        }
+
+        info!("Rewriting G28/G30 into G53 moves to the stored positions");
+        program.rewrite_home_moves(g28, g30);
+    }
+
+    Ok(())
+}
+
+fn check_laser_mode(
+    controller: &Controller,
+    config: &CncConfig,
+    post: PostPreset,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let laser_mode = checked_stream(controller, vec!["$$"], &config.grbl)
+        .map_err(|error| format!("Failed to read settings: {}", error))?
+        .into_iter()
+        .find_map(|(_, response)| match response {
+            Response::Setting { id: 32, value } => Some(value == "1"),
+            _ => None,
+        })
+        .ok_or("Failed to read $32")?;
+
+    if !laser_mode {
+        warn!(
+            "Laser mode is off ($32=0), {} output will pause at every power change",
+            post
+        );
+    }
+
+    Ok(())
+}
+
+fn run_with_retry(
+    step: &GcodeStepConfig,
+    controller: &Controller,
+    vars: &TemplateVars,
+    config: &CncConfig,
+    program: &Program,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(retry) = &step.retry else {
+        return run_program(step, controller, vars, config, program, false);
+    };
+
+    if program.is_live() {
+        return Err("A live G-code source can't be retried, it can only be read once".into());
+    }
+
+    let offsets = read_offsets(controller, config)?;
+    let mut attempt = 0;
+
+    loop {
+        let error = match run_program(step, controller, vars, config, program, attempt > 0) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+
+        let code = controller.alarm.load(Ordering::Relaxed);
+        if attempt >= retry.attempts
+            || !alarm::is_retryable(code)
+            || controller.stopping.load(Ordering::Relaxed)
+        {
+            return Err(error);
+        }
+
+        attempt += 1;
+        warn!(
+            "{}, restarting program (attempt {} of {})",
+            error, attempt, retry.attempts
+        );
+
+        let commands: &[&str] = if retry.home { &["$X", "$H"] } else { &["$X"] };
+        alarm::clear(controller, config, code, commands)?;
+        restore_offsets(controller, config, &offsets)?;
+    }
+}
+
+// Machine Z (mm) to retract to before resuming, the top of the machine's travel by default
+fn resume_safe_z(step: &GcodeStepConfig, config: &CncConfig) -> Option<f64> {
+    step.resume_moves.safe_z.or_else(|| {
+        let machine = config.machine.as_ref()?;
+        Some(machine.units.to_mm(machine.z.as_ref()?.max))
+    })
+}
+
+// Stream the program once, a restart streams it from the start without checking it again
+fn run_program(
+    step: &GcodeStepConfig,
+    controller: &Controller,
+    vars: &TemplateVars,
+    config: &CncConfig,
+    program: &Program,
+    restart: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let progress_interval = Duration::from_secs(config.logs.progress_interval_s);
+
+    let checkpoint_path = step
+        .checkpoint
+        .as_ref()
+        .map(|path| apply_template(&expand_path(path), vars));
+
+    let mut start_line = step.start_line.unwrap_or(1);
+    if step.resume
+        && !restart
+        && let Some(path) = &checkpoint_path
+        && let Some(line) = read_checkpoint(path)?
     {
-        let expanded_output = expand_path(&save_path);
-        let templated_output = apply_template(&expanded_output, timestamp);
+        info!("Found checkpoint at line {} in '{}'", line, path);
+        start_line = line + 1;
+    }
+
+    let live = program.is_live();
+    if live && start_line != 1 {
+        return Err("A live G-code source can't be started from a later line".into());
+    }
 
-        if let Some(parent) = std::path::Path::new(&templated_output).parent() {
-            std::fs::create_dir_all(parent)?;
+    // Only the size of the program is kept, its lines are read again for each pass
+    let mut total_lines = 0;
+    let mut total_bytes = 0;
+    if live {
+        // Live sources end whenever their generator does
+        total_lines = usize::MAX;
+    } else {
+        for line in program.lines()? {
+            total_lines += 1;
+            if total_lines >= start_line {
+                total_bytes += line.trim().len() + 1;
+            }
         }
+        program.finish()?;
+    }
 
-        let file = File::create(&templated_output).map_err(|error| {
-            format!(
-                "Failed to create output file '{}': {}",
-                templated_output, error
-            )
-        })?;
+    if start_line < 1 || start_line > total_lines {
+        return Err(format!(
+            "Start line {} is outside of the program ({} lines)",
+            start_line, total_lines
+        )
+        .into());
+    }
 
-        Some(BufWriter::new(file))
+    let output_writer = if let Some(ProbeConfig {
+        save_path: Some(save_path),
+        ..
+    }) = &step.probe
+    {
+        Some(create_output(save_path, vars)?)
     } else {
         None
     };
 
-    if step.check {
+    let mut response_log = match &step.responses {
+        Some(responses) => Some(ResponseLog::new(create_output(
+            &responses.save_path,
+            vars,
+        )?)?),
+        None => None,
+    };
+
+    let mut report_log = match &step.reports {
+        Some(reports) => Some(ReportLog::new(
+            create_output(&reports.save_path, vars)?,
+            Duration::from_millis(reports.interval_ms),
+        )?),
+        None => None,
+    };
+
+    if step.check && live {
+        info!("Skipping G-code check, a live source can only be read once");
+    } else if step.check && !restart {
         info!("Checking G-code");
 
-        if let Some((serial_tx, _)) = controller.serial_channel.clone() {
-            serial_tx
-                .send(Command::Gcode("$C".to_string()))
-                .map_err(|error| format!("Failed to enable check mode: {}", error))?;
+        if let Some(log) = &mut response_log {
+            log.set_mode("check");
         }
 
+        let observer = Logged {
+            observer: (),
+            log: response_log.as_mut(),
+            line_offset: 0,
+            first_line: 1,
+        };
         let errors: Vec<ControllerError> =
-            buffered_stream(controller, gcode.clone(), rx_buffer_size)
-                .map_err(|error| format!("Failed to stream G-code in check mode: {}", error))?
-                .iter()
-                .filter_map(|res| {
-                    if let Response::Error(_) = res.1 {
-                        Some(ControllerError::GcodeError(res.0, res.1.clone()))
-                    } else {
-                        None
-                    }
-                })
+            stream_check_mode(controller, program.lines()?, config, observer)?
+                .into_iter()
+                .map(|(line, response)| ControllerError::GcodeError(line, response))
                 .collect();
+        // A read error ends the lines early, and must fail the check rather than pass it
+        program.finish()?;
 
-        if let Some((serial_tx, _)) = controller.serial_channel.clone() {
-            serial_tx
-                .send(Command::Gcode("$C".to_string()))
-                .map_err(|error| format!("Failed to disable check mode: {}", error))?;
-        }
-
-        if errors.len() > 0 {
+        if !errors.is_empty() {
             error!(
                 "Checking complete! {} errors found:\n
                  {}\n",
@@ -92,33 +497,349 @@ pub fn execute_gcode_step(
             );
             warn!("Skipping streaming");
 
+            if let Some(log) = response_log {
+                log.finish()
+                    .map_err(|error| format!("Failed to write response log: {}", error))?;
+            }
+
             return Ok(());
         } else {
             info!("Checking complete! No errors found");
         }
     }
 
+    // Replay the modal state (units, feed, spindle, WCS...) of the skipped lines and move back to
+    // where they end before resuming
+    let preamble = if start_line > 1 {
+        let safe_z = resume_safe_z(step, config).ok_or(
+            "Resuming from a later line needs resume_moves.safe_z on the step, or machine.z limits",
+        )?;
+        let preamble = ModalState::scan(program.lines()?.take(start_line - 1)).preamble(
+            start_line,
+            safe_z,
+            step.resume_moves.spindle_delay_s,
+        )?;
+        program.finish()?;
+        info!(
+            "Resuming from line {}, restoring modal state: {}",
+            start_line,
+            preamble.join(" | ")
+        );
+        preamble
+    } else {
+        Vec::new()
+    };
+
+    let stream_lines = preamble.len() + total_lines - (start_line - 1);
+    let line_offset = start_line as i32 - 1 - preamble.len() as i32;
+
+    let mut progress = if live {
+        Progress::unbounded()
+    } else {
+        Progress::new(
+            stream_lines,
+            total_bytes + preamble.iter().map(|line| line.len() + 1).sum::<usize>(),
+        )
+    };
+
+    // Stream in segments that end at each QC checkpoint so the machine can be inspected there
+    let mut segment_ends: Vec<usize> = step
+        .qc
+        .iter()
+        .filter(|qc| qc.line >= start_line && qc.line < total_lines)
+        .map(|qc| (qc.line as i32 - line_offset) as usize)
+        .collect();
+    segment_ends.sort();
+    segment_ends.dedup();
+    segment_ends.push(stream_lines);
+
+    if let Some(overrides) = &step.overrides {
+        if let Some(feed) = overrides.feed {
+            info!("Setting feed override to {}%", feed);
+            controller.set_feed_override(feed)?;
+        }
+        if let Some(rapid) = overrides.rapid {
+            info!("Setting rapid override to {}%", rapid);
+            controller.set_rapid_override(rapid)?;
+        }
+        if let Some(spindle) = overrides.spindle {
+            info!("Setting spindle override to {}%", spindle);
+            controller.set_spindle_override(spindle)?;
+        }
+    }
+
+    // Read once more for the terminal UI to show, a live source can't be
+    if tui::active() && !live {
+        let lines: Vec<String> = program.lines()?.collect();
+        program.finish()?;
+        tui::show_program(lines, checkpoint_path.clone());
+    }
+
     info!("Streaming G-code");
 
-    let responses = buffered_stream(controller, gcode, rx_buffer_size)
-        .map_err(|error| format!("Failed to stream G-code: {}", error))?;
+    if let Some(log) = &mut response_log {
+        log.set_mode("stream");
+    }
 
-    if let Some(mut writer) = output_writer {
-        writeln!(writer, "x,y,z")?;
+    let mut last_line = None;
+    let mut last_checkpoint = Instant::now();
+    let mut probe_points = Vec::new();
+    let mut probe_count = 0;
+    let mut missed_lines = Vec::new();
+    let mut validator = step.probe.as_ref().map(ProbeValidator::new);
+    let mut verdicts = Vec::new();
+    let mut segment_start = 0;
+    let mut stream_gcode = preamble
+        .iter()
+        .cloned()
+        .chain(program.lines()?.skip(start_line - 1));
 
-        responses
-            .iter()
-            .try_for_each(|res| -> std::io::Result<()> {
-                if let Response::Probe { coords, .. } = res.1 {
-                    writeln!(writer, "{},{},{}", coords.0, coords.1, coords.2)?;
+    let result: Result<(), Box<dyn std::error::Error>> = 'stream: {
+        for &segment_end in &segment_ends {
+            let segment_offset = segment_start as i32;
+
+            let segment_result = buffered_stream(
+                controller,
+                stream_gcode.by_ref().take(segment_end - segment_start),
+                &config.grbl,
+                Recorded {
+                    log: report_log.as_mut(),
+                    line_offset: segment_offset + line_offset,
+                    first_line: start_line as i32,
+                    observer: Logged {
+                        log: response_log.as_mut(),
+                        line_offset: segment_offset + line_offset,
+                        first_line: start_line as i32,
+                        observer: WithResponses {
+                            on_ack: |acknowledged, sent, bytes, response: &Response| {
+                                let acknowledged = acknowledged + segment_offset;
+                                let sent = sent + segment_offset;
+
+                                progress.update(sent as usize, bytes);
+                                if !live {
+                                    status::set_progress(Some(progress.percent()));
+                                }
+                                if progress.should_report(progress_interval) {
+                                    info!("Progress: {}", progress);
+                                }
+
+                                let line = acknowledged + line_offset;
+
+                                if let Response::Error(_) = response {
+                                    handle_error(controller, step.on_error, line, response)?;
+                                }
+
+                                if line < start_line as i32 {
+                                    return Ok(());
+                                }
+
+                                last_line = Some(line);
+                                status::set_line(line);
+
+                                if let Some(path) = &checkpoint_path
+                                    && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL
+                                {
+                                    if let Err(error) = write_checkpoint(path, line) {
+                                        warn!("Failed to write checkpoint '{}': {}", path, error);
+                                    }
+                                    last_checkpoint = Instant::now();
+                                }
+
+                                Ok(())
+                            },
+                            // Probe results arrive before the probing line is acknowledged
+                            on_response: |received, response: &Response| {
+                                let Response::Probe {
+                                    coords, success, ..
+                                } = response
+                                else {
+                                    return Ok(());
+                                };
+                                let line = received + 1 + segment_offset + line_offset;
+                                probe_count += 1;
+                                if !success {
+                                    missed_lines.push(line);
+                                }
+
+                                let outlier = match &mut validator {
+                                    Some(validator) if !success => Some((
+                                        validator.policy(),
+                                        "probe didn't make contact".to_string(),
+                                    )),
+                                    Some(validator) => validator
+                                        .check(*coords)
+                                        .map(|reason| (validator.policy(), reason)),
+                                    None => None,
+                                };
+
+                                // Published as it arrives, so a leveling UI can show the surface
+                                // building up and abort early
+                                status::probe(status::Probe {
+                                    line,
+                                    position: *coords,
+                                    contact: *success,
+                                    outlier: outlier.as_ref().map(|(_, reason)| reason.clone()),
+                                    discarded: matches!(outlier, Some((OutlierPolicy::Discard, _))),
+                                });
+
+                                match outlier {
+                                    None => probe_points.push((*coords, *success)),
+                                    Some((OutlierPolicy::Warn, reason)) => {
+                                        warn!("Probe outlier at line {}: {}", line, reason);
+                                        probe_points.push((*coords, *success));
+                                    }
+                                    Some((OutlierPolicy::Discard, reason)) => {
+                                        warn!(
+                                            "Discarding probe point at line {}: {}",
+                                            line, reason
+                                        );
+                                    }
+                                    Some((OutlierPolicy::Abort, reason)) => {
+                                        error!("Probe outlier at line {}: {}", line, reason);
+                                        halt(controller)?;
+                                        return Err(ControllerError::ProbeError {
+                                            line,
+                                            message: format!("Probe outlier, {}", reason),
+                                        });
+                                    }
+                                }
+
+                                Ok(())
+                            },
+                        },
+                    },
+                },
+            );
+
+            if let Err(error) = segment_result {
+                break 'stream Err(error.into());
+            }
+
+            if let Err(error) = program.finish() {
+                break 'stream Err(error);
+            }
+
+            segment_start = segment_end;
+
+            if segment_end < stream_lines {
+                let line = segment_end as i32 + line_offset;
+                for qc in step.qc.iter().filter(|qc| qc.line as i32 == line) {
+                    let verdict = match inspect(controller, qc, vars) {
+                        Ok(verdict) => verdict,
+                        Err(error) => break 'stream Err(error),
+                    };
+                    verdicts.push((qc.line, verdict));
+                    job::record_qc(qc.line, verdict);
+
+                    if verdict == Some(false) {
+                        match confirm("Continue streaming after the failed QC checkpoint?") {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                break 'stream Err(format!(
+                                    "QC checkpoint on line {} failed",
+                                    qc.line
+                                )
+                                .into());
+                            }
+                            Err(error) => break 'stream Err(error.into()),
+                        }
+                    }
                 }
+            }
+        }
 
-                Ok(())
-            })?;
+        Ok(())
+    };
+
+    // A line marked in the terminal UI is where the next run resumes, however this one ended
+    let mark = tui::close_program();
+    if let Some(path) = &checkpoint_path {
+        match (mark, &result, last_line) {
+            (Some(line), _, _) => match write_checkpoint(path, line as i32 - 1) {
+                Ok(()) => info!("Marked line {} saved to '{}' to resume from", line, path),
+                Err(error) => warn!("Failed to write checkpoint '{}': {}", path, error),
+            },
+            (None, Ok(_), _) => {
+                if let Err(error) = fs::remove_file(path)
+                    && error.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!("Failed to remove checkpoint '{}': {}", path, error);
+                }
+            }
+            (None, Err(_), Some(line)) => match write_checkpoint(path, line) {
+                Ok(()) => info!("Last acknowledged line {} saved to '{}'", line, path),
+                Err(error) => warn!("Failed to write checkpoint '{}': {}", path, error),
+            },
+            (None, Err(_), None) => {}
+        }
+    }
+
+    if !verdicts.is_empty() {
+        info!(
+            "QC summary: {}",
+            verdicts
+                .iter()
+                .map(|(line, verdict)| match verdict {
+                    Some(true) => format!("line {} passed", line),
+                    Some(false) => format!("line {} failed", line),
+                    None => format!("line {} not assessed", line),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // Counted for the sequence report whether or not streaming finished
+    job::record_lines(last_line.map_or(0, |line| (line - start_line as i32 + 1) as usize));
+    if step.probe.is_some() || probe_count > 0 {
+        job::record_probes(probe_count, missed_lines.len());
+    }
+
+    // Flush the logs even if streaming failed, that's when it is most useful
+    let log_result = response_log.map(ResponseLog::finish).transpose();
+    let report_result = report_log.map(ReportLog::finish).transpose();
+
+    result.map_err(|error| format!("Failed to stream G-code: {}", error))?;
+    log_result.map_err(|error| format!("Failed to write response log: {}", error))?;
+    report_result.map_err(|error| format!("Failed to write report log: {}", error))?;
+
+    if let (Some(writer), Some(probe)) = (output_writer, &step.probe) {
+        let wco = read_wco(controller)
+            .map_err(|error| format!("Failed to read work coordinate offset: {}", error))?;
+        write_points(writer, probe, &probe_points, wco)
+            .map_err(|error| format!("Failed to write probe points: {}", error))?;
+    }
+
+    if let Some(probe) = &step.probe
+        && let Some(summary) = Summary::new(&probe_points)
+    {
+        summary.log();
+        if let Some(summary_path) = &probe.summary_path {
+            summary
+                .write(create_output(summary_path, vars)?)
+                .map_err(|error| format!("Failed to write probe summary: {}", error))?;
+        }
+    }
+
+    if step
+        .probe
+        .as_ref()
+        .is_some_and(|probe| probe.require_contact)
+        && !missed_lines.is_empty()
+    {
+        return Err(format!(
+            "Probe didn't make contact at line(s) {}",
+            missed_lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .into());
     }
 
     wait_for_report(
-        &controller,
+        controller,
         Some(|report: &Report| {
             matches!(
                 report,
@@ -134,3 +855,119 @@ pub fn execute_gcode_step(
 
     Ok(())
 }
+
+fn handle_error(
+    controller: &Controller,
+    policy: ErrorPolicy,
+    line: i32,
+    response: &Response,
+) -> Result<(), ControllerError> {
+    let error = ControllerError::GcodeError(line, response.clone());
+
+    match policy {
+        ErrorPolicy::Skip => {
+            warn!("{}, skipping", error);
+            Ok(())
+        }
+        ErrorPolicy::Abort => {
+            error!("{}, aborting", error);
+            halt(controller)?;
+            Err(error)
+        }
+        ErrorPolicy::Hold => {
+            warn!("{}, holding until resumed", error);
+            controller.realtime(Realtime::FeedHold)?;
+            controller.paused.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        ErrorPolicy::Ask => {
+            controller.realtime(Realtime::FeedHold)?;
+
+            let proceed = confirm(&format!("{}. Continue streaming?", error)).map_err(|error| {
+                ControllerError::SerialError(format!("Failed to read answer: {}", error))
+            })?;
+
+            if proceed {
+                info!("Continuing after error on line {}", line);
+                controller.realtime(Realtime::CycleStart)
+            } else {
+                error!("{}, aborting", error);
+                halt(controller)?;
+                Err(error)
+            }
+        }
+    }
+}
+
+fn inspect(
+    controller: &Controller,
+    qc: &QcConfig,
+    vars: &TemplateVars,
+) -> Result<Option<bool>, Box<dyn std::error::Error>> {
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )?;
+
+    info!("Paused at QC checkpoint on line {}", qc.line);
+
+    if let Some(snapshot) = &qc.snapshot {
+        run_command(snapshot, vars)
+            .map_err(|error| format!("Failed to take QC snapshot: {}", error))?;
+    }
+
+    if !qc.prompt {
+        return Ok(None);
+    }
+
+    let passed = confirm(&format!("QC checkpoint on line {}: did it pass?", qc.line))?;
+    if passed {
+        info!("QC checkpoint on line {} passed", qc.line);
+    } else {
+        warn!("QC checkpoint on line {} failed", qc.line);
+    }
+
+    Ok(Some(passed))
+}
+
+// Feed hold, wait for the machine to come to rest, then flush the planner with a soft reset
+fn halt(controller: &Controller) -> Result<(), ControllerError> {
+    controller.realtime(Realtime::FeedHold)?;
+
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Hold(0) | Status::Idle))),
+    )?;
+
+    controller.soft_reset()
+}
+
+// Grbl only includes the offset in some status reports, so this waits for one that does
+fn read_wco(controller: &Controller) -> Result<(f64, f64, f64), Box<dyn std::error::Error>> {
+    let report = wait_for_report(controller, Some(|report: &Report| report.wco.is_some()))?;
+    let (x, y, z) = report
+        .and_then(|report| report.wco)
+        .ok_or("controller stopped")?;
+
+    Ok((x.into(), y.into(), z.into()))
+}
+
+fn create_output(
+    path: &str,
+    vars: &TemplateVars,
+) -> Result<BufWriter<File>, Box<dyn std::error::Error>> {
+    let templated_output = apply_template(&expand_path(path), vars);
+
+    if let Some(parent) = std::path::Path::new(&templated_output).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let file = File::create(&templated_output).map_err(|error| {
+        format!(
+            "Failed to create output file '{}': {}",
+            templated_output, error
+        )
+    })?;
+
+    Ok(BufWriter::new(file))
+}
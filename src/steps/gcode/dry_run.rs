@@ -0,0 +1,55 @@
+use regex::Captures;
+
+use super::words::{WORDS, strip_comments};
+
+// Rewrites spindle/laser and coolant starts to stops and optionally lifts all absolute Z targets
+pub struct DryRun {
+    raise_z: Option<f64>,
+    absolute: bool,
+}
+
+impl DryRun {
+    pub fn new(raise_z: Option<f64>) -> Self {
+        Self {
+            raise_z,
+            absolute: true,
+        }
+    }
+
+    pub fn rewrite(&mut self, line: &str) -> String {
+        let code = strip_comments(line);
+
+        let mut offset_z = self.raise_z.is_some();
+        for captures in WORDS.captures_iter(&code) {
+            let Ok(number) = captures[2].parse::<f64>() else {
+                continue;
+            };
+
+            match (captures[1].to_ascii_uppercase().as_str(), number) {
+                ("G", 90.0) => self.absolute = true,
+                ("G", 91.0) => self.absolute = false,
+                // Machine coordinate and offset commands must not be shifted
+                ("G", 10.0 | 28.0 | 30.0 | 53.0 | 92.0) => offset_z = false,
+                _ => {}
+            }
+        }
+        offset_z &= self.absolute;
+
+        let raise_z = self.raise_z.unwrap_or_default();
+        WORDS
+            .replace_all(&code, |captures: &Captures| {
+                let letter = captures[1].to_ascii_uppercase();
+                let Ok(number) = captures[2].parse::<f64>() else {
+                    return captures[0].to_string();
+                };
+
+                match (letter.as_str(), number) {
+                    ("M", 3.0 | 4.0) => "M5".to_string(),
+                    ("M", 7.0 | 8.0) => "M9".to_string(),
+                    ("Z", z) if offset_z => format!("Z{:.4}", z + raise_z),
+                    _ => captures[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
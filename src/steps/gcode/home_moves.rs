@@ -0,0 +1,124 @@
+use super::words::{WORDS, strip_comments};
+
+type Position = (f64, f64, f64);
+
+// Finds G28/G30 moves, returning the command and the axes named on the line
+pub struct HomeMoveParser;
+
+impl HomeMoveParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn words(&self, line: &str) -> Vec<(String, String, f64)> {
+        let code = strip_comments(line);
+        WORDS
+            .captures_iter(&code)
+            .filter_map(|captures| {
+                let number = captures[2].parse::<f64>().ok()?;
+                Some((
+                    captures[1].to_ascii_uppercase(),
+                    captures[0].to_string(),
+                    number,
+                ))
+            })
+            .collect()
+    }
+
+    pub fn find(&self, line: &str) -> Option<(&'static str, String)> {
+        let words = self.words(line);
+
+        let command =
+            words
+                .iter()
+                .find_map(|(letter, _, number)| match (letter.as_str(), *number) {
+                    ("G", 28.0) => Some("G28"),
+                    ("G", 30.0) => Some("G30"),
+                    _ => None,
+                })?;
+        let axes = words
+            .iter()
+            .filter(|(letter, _, _)| matches!(letter.as_str(), "X" | "Y" | "Z"))
+            .map(|(letter, _, _)| letter.as_str())
+            .collect();
+
+        Some((command, axes))
+    }
+}
+
+// Rewrites G28/G30 into a G53 rapid straight to the stored position, skipping the intermediate point
+pub struct HomeMoves {
+    parser: HomeMoveParser,
+    g28: Position,
+    g30: Position,
+    inches: bool,
+    // Motion mode carried over from earlier lines, G53 only works in G0 or G1
+    linear: bool,
+}
+
+impl HomeMoves {
+    pub fn new(g28: Position, g30: Position) -> Self {
+        Self {
+            parser: HomeMoveParser::new(),
+            g28,
+            g30,
+            inches: false,
+            linear: true,
+        }
+    }
+
+    pub fn rewrite(&mut self, line: &str) -> String {
+        let words = self.parser.words(line);
+        let home_move = self.parser.find(line);
+
+        for (letter, _, number) in &words {
+            match (letter.as_str(), *number) {
+                ("G", 20.0) => self.inches = true,
+                ("G", 21.0) => self.inches = false,
+                ("G", 0.0 | 1.0) => self.linear = true,
+                ("G", 2.0 | 3.0 | 38.2 | 38.3 | 38.4 | 38.5 | 80.0) => self.linear = false,
+                _ => {}
+            }
+        }
+
+        let Some((command, axes)) = home_move else {
+            return line.to_string();
+        };
+
+        let (x, y, z) = if command == "G28" { self.g28 } else { self.g30 };
+        let scale = if self.inches { 1.0 / 25.4 } else { 1.0 };
+
+        // Moving Z together with X or Y is what crashes, so only retract Z then
+        let targets: Vec<(char, f64)> = if (axes.contains('Z') && axes != "Z") || axes.is_empty() {
+            vec![('Z', z)]
+        } else {
+            [('X', x), ('Y', y), ('Z', z)]
+                .into_iter()
+                .filter(|(axis, _)| axes.contains(*axis))
+                .collect()
+        };
+
+        // Keep the other words on the line, such as the G91 a post emits with G28
+        let mut rewritten: Vec<String> = words
+            .into_iter()
+            .filter(|(letter, _, number)| {
+                !matches!(
+                    (letter.as_str(), *number),
+                    ("X" | "Y" | "Z", _) | ("G", 28.0 | 30.0)
+                )
+            })
+            .map(|(_, word, _)| word)
+            .collect();
+
+        rewritten.push("G53".to_string());
+        if !self.linear {
+            rewritten.push("G0".to_string());
+            self.linear = true;
+        }
+        for (axis, position) in targets {
+            rewritten.push(format!("{}{:.4}", axis, position * scale));
+        }
+
+        rewritten.join(" ")
+    }
+}
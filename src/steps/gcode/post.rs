@@ -0,0 +1,86 @@
+use std::fmt;
+
+use regex::Captures;
+
+use crate::config::PostPreset;
+
+use super::words::{WORDS, strip_comments};
+
+impl fmt::Display for PostPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostPreset::Fusion360 => write!(f, "Fusion 360"),
+            PostPreset::CarbideCreate => write!(f, "Carbide Create"),
+            PostPreset::Lightburn => write!(f, "LightBurn"),
+            PostPreset::Flatcam => write!(f, "FlatCAM"),
+        }
+    }
+}
+
+impl PostPreset {
+    // Posts that emit M6 tool changes, which Grbl rejects as unsupported
+    fn strips_tool_changes(self) -> bool {
+        matches!(
+            self,
+            PostPreset::Fusion360 | PostPreset::CarbideCreate | PostPreset::Flatcam
+        )
+    }
+
+    // Posts that wrap the program in `%` tape markers
+    fn strips_percent(self) -> bool {
+        matches!(self, PostPreset::Fusion360 | PostPreset::Flatcam)
+    }
+
+    /// LightBurn relies on laser mode (`$32=1`) to stop at each `M4` power change
+    pub fn needs_laser_mode(self) -> bool {
+        matches!(self, PostPreset::Lightburn)
+    }
+
+    pub fn describe(self) -> String {
+        let mut changes = vec!["comments stripped"];
+        if self.strips_tool_changes() {
+            changes.push("M6 and T words removed");
+        }
+        if self.strips_percent() {
+            changes.push("% markers removed");
+        }
+        changes.join(", ")
+    }
+}
+
+// Rewrites the quirks of a CAM post-processor's output, keeping the line count the same
+pub struct Post {
+    preset: PostPreset,
+}
+
+impl Post {
+    pub fn new(preset: PostPreset) -> Self {
+        Self { preset }
+    }
+
+    pub fn rewrite(&self, line: &str) -> String {
+        // Long comments waste the RX buffer and can overflow Grbl's line buffer
+        let mut code = strip_comments(line).trim().to_string();
+
+        if self.preset.strips_percent() && code == "%" {
+            code.clear();
+        }
+
+        if self.preset.strips_tool_changes() {
+            code = WORDS
+                .replace_all(&code, |captures: &Captures| {
+                    match (
+                        captures[1].to_ascii_uppercase().as_str(),
+                        captures[2].parse::<f64>(),
+                    ) {
+                        ("M", Ok(6.0)) | ("T", _) => String::new(),
+                        _ => captures[0].to_string(),
+                    }
+                })
+                .trim()
+                .to_string();
+        }
+
+        code
+    }
+}
@@ -0,0 +1,458 @@
+use std::io::Write;
+
+use log::info;
+
+use crate::config::{OutlierPolicy, ProbeConfig, ProbeCoordinates, ProbeFormat};
+use crate::display;
+
+type Point = (f64, f64, f64);
+
+// Coordinates closer than this are the same row or column of a grid
+const GRID_TOLERANCE: f64 = 0.01;
+
+pub struct ProbeValidator<'a> {
+    config: &'a ProbeConfig,
+    points: Vec<Point>,
+}
+
+impl<'a> ProbeValidator<'a> {
+    pub fn new(config: &'a ProbeConfig) -> Self {
+        Self {
+            config,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> OutlierPolicy {
+        self.config.on_outlier
+    }
+
+    // Returns why a point is an outlier, points that pass become neighbours for later points
+    pub fn check(&mut self, point: Point) -> Option<String> {
+        let (x, y, z) = point;
+
+        if let Some(z_min) = self.config.z_min
+            && z < z_min
+        {
+            return Some(format!(
+                "Z {} is below the minimum of {}",
+                display::length(z),
+                display::length(z_min)
+            ));
+        }
+
+        if let Some(z_max) = self.config.z_max
+            && z > z_max
+        {
+            return Some(format!(
+                "Z {} is above the maximum of {}",
+                display::length(z),
+                display::length(z_max)
+            ));
+        }
+
+        if let Some(max_deviation) = self.config.max_deviation
+            && let Some(&(nx, ny, nz)) = self.points.iter().min_by(|a, b| {
+                let distance = |p: &&Point| (p.0 - x).powi(2) + (p.1 - y).powi(2);
+                distance(a).total_cmp(&distance(b))
+            })
+            && (z - nz).abs() > max_deviation
+        {
+            return Some(format!(
+                "Z {} deviates {} from the nearest point at {}",
+                display::length(z),
+                display::length((z - nz).abs()),
+                display::position((nx, ny, nz))
+            ));
+        }
+
+        self.points.push(point);
+
+        None
+    }
+}
+
+/// Writes the probed points in machine coordinates, each with whether the probe made contact.
+/// Work coordinates are the machine coordinates less `wco`, the work coordinate offset
+pub fn write_points(
+    mut writer: impl Write,
+    config: &ProbeConfig,
+    points: &[(Point, bool)],
+    wco: Point,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let work = |(x, y, z): Point| (x - wco.0, y - wco.1, z - wco.2);
+    let positioned: Vec<(Point, bool)> = match config.coordinates {
+        ProbeCoordinates::Machine => points.to_vec(),
+        ProbeCoordinates::Work => points
+            .iter()
+            .map(|&(point, success)| (work(point), success))
+            .collect(),
+    };
+
+    match config.format {
+        ProbeFormat::Csv => {
+            writeln!(writer, "x,y,z,work_x,work_y,work_z,success")?;
+            for &(point, success) in points {
+                let (x, y, z) = point;
+                let (wx, wy, wz) = work(point);
+                writeln!(writer, "{},{},{},{},{},{},{}", x, y, z, wx, wy, wz, success)?;
+            }
+        }
+        ProbeFormat::Json => {
+            let points: Vec<String> = points
+                .iter()
+                .map(|&(point, success)| {
+                    let (x, y, z) = point;
+                    let (wx, wy, wz) = work(point);
+                    format!(
+                        "    {{\"x\": {}, \"y\": {}, \"z\": {}, \"work\": {{\"x\": {}, \"y\": {}, \"z\": {}}}, \"success\": {}}}",
+                        x, y, z, wx, wy, wz, success
+                    )
+                })
+                .collect();
+            writeln!(
+                writer,
+                "{{\n  \"points\": [\n{}\n  ]\n}}",
+                points.join(",\n")
+            )?;
+        }
+        ProbeFormat::Grid => write_grid(&mut writer, &positioned)?,
+        // A point without contact is where the probe stopped, not on the surface
+        ProbeFormat::Ply => {
+            let contacts: Vec<&Point> = contacts(&positioned).collect();
+            writeln!(writer, "ply")?;
+            writeln!(writer, "format ascii 1.0")?;
+            writeln!(writer, "comment probe points from cnc-ctrl")?;
+            writeln!(writer, "element vertex {}", contacts.len())?;
+            for axis in ["x", "y", "z"] {
+                writeln!(writer, "property double {}", axis)?;
+            }
+            writeln!(writer, "end_header")?;
+            for (x, y, z) in contacts {
+                writeln!(writer, "{} {} {}", x, y, z)?;
+            }
+        }
+        ProbeFormat::Xyz => {
+            for (x, y, z) in contacts(&positioned) {
+                writeln!(writer, "{} {} {}", x, y, z)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn contacts(points: &[(Point, bool)]) -> impl Iterator<Item = &Point> {
+    points
+        .iter()
+        .filter(|(_, success)| *success)
+        .map(|(point, _)| point)
+}
+
+// Rows run along Y and columns along X, points that didn't make contact are null
+fn write_grid(
+    mut writer: impl Write,
+    points: &[(Point, bool)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let xs = axis_positions(points.iter().map(|((x, _, _), _)| *x))
+        .ok_or("probe points aren't evenly spaced along X")?;
+    let ys = axis_positions(points.iter().map(|((_, y, _), _)| *y))
+        .ok_or("probe points aren't evenly spaced along Y")?;
+
+    let mut z: Vec<Vec<Option<f64>>> = vec![vec![None; xs.count]; ys.count];
+    for &((x, y, value), success) in points {
+        z[ys.index(y)][xs.index(x)] = success.then_some(value);
+    }
+
+    let rows: Vec<String> = z
+        .iter()
+        .map(|row| {
+            let values: Vec<String> = row
+                .iter()
+                .map(|value| value.map_or("null".to_string(), |value| value.to_string()))
+                .collect();
+            format!("    [{}]", values.join(", "))
+        })
+        .collect();
+
+    writeln!(writer, "{{")?;
+    writeln!(
+        writer,
+        "  \"origin\": {{\"x\": {}, \"y\": {}}},",
+        xs.origin, ys.origin
+    )?;
+    writeln!(
+        writer,
+        "  \"spacing\": {{\"x\": {}, \"y\": {}}},",
+        xs.spacing, ys.spacing
+    )?;
+    writeln!(writer, "  \"rows\": {},", ys.count)?;
+    writeln!(writer, "  \"cols\": {},", xs.count)?;
+    writeln!(writer, "  \"z\": [\n{}\n  ]", rows.join(",\n"))?;
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+struct AxisPositions {
+    origin: f64,
+    spacing: f64,
+    count: usize,
+}
+
+impl AxisPositions {
+    fn index(&self, value: f64) -> usize {
+        if self.spacing == 0.0 {
+            return 0;
+        }
+        (((value - self.origin) / self.spacing).round() as usize).min(self.count - 1)
+    }
+}
+
+// The distinct positions along one axis, None unless they are evenly spaced
+fn axis_positions(values: impl Iterator<Item = f64>) -> Option<AxisPositions> {
+    let mut positions: Vec<f64> = Vec::new();
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    for value in values {
+        if positions
+            .last()
+            .is_none_or(|last| value - last > GRID_TOLERANCE)
+        {
+            positions.push(value);
+        }
+    }
+
+    let origin = *positions.first()?;
+    let spacing = match positions.len() {
+        1 => 0.0,
+        count => (positions[count - 1] - origin) / (count - 1) as f64,
+    };
+    let even = positions
+        .iter()
+        .enumerate()
+        .all(|(i, position)| (origin + i as f64 * spacing - position).abs() <= GRID_TOLERANCE);
+
+    even.then_some(AxisPositions {
+        origin,
+        spacing,
+        count: positions.len(),
+    })
+}
+
+/// Statistics of the contacts, for checking stock is flat and seated before cutting it
+pub struct Summary {
+    count: usize,
+    z_min: f64,
+    z_max: f64,
+    z_mean: f64,
+    plane: Option<Plane>,
+}
+
+// Best-fit plane z = x_slope * x + y_slope * y + c, and the span of the contacts it was fit to
+struct Plane {
+    x_slope: f64,
+    y_slope: f64,
+    x_span: f64,
+    y_span: f64,
+    // Peak-to-valley distance of the contacts from the plane
+    flatness: f64,
+}
+
+impl Summary {
+    /// Summarises the points that made contact, None if none did
+    pub fn new(points: &[(Point, bool)]) -> Option<Self> {
+        let contacts: Vec<&Point> = contacts(points).collect();
+        let count = contacts.len();
+        if count == 0 {
+            return None;
+        }
+
+        let z_min = contacts.iter().map(|p| p.2).fold(f64::INFINITY, f64::min);
+        let z_max = contacts
+            .iter()
+            .map(|p| p.2)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let z_mean = contacts.iter().map(|p| p.2).sum::<f64>() / count as f64;
+
+        Some(Self {
+            count,
+            z_min,
+            z_max,
+            z_mean,
+            plane: fit_plane(&contacts),
+        })
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Probe summary: {} contact(s), Z {} to {}, mean {}, peak-to-valley {}",
+            self.count,
+            display::length(self.z_min),
+            display::length(self.z_max),
+            display::length(self.z_mean),
+            display::length(self.z_max - self.z_min)
+        );
+
+        match &self.plane {
+            Some(plane) => info!(
+                "Probe summary: best-fit plane tilted {:.3}°, rising {} across X and {} across Y, {} peak-to-valley from the plane",
+                plane.degrees(),
+                display::length(plane.x_slope * plane.x_span),
+                display::length(plane.y_slope * plane.y_span),
+                display::length(plane.flatness)
+            ),
+            None => info!("Probe summary: too few points spread in X and Y to fit a plane"),
+        }
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+        let plane = match &self.plane {
+            Some(plane) => format!(
+                "{{\"x_slope\": {}, \"y_slope\": {}, \"degrees\": {}, \"flatness\": {}}}",
+                plane.x_slope,
+                plane.y_slope,
+                plane.degrees(),
+                plane.flatness
+            ),
+            None => "null".to_string(),
+        };
+
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"contacts\": {},", self.count)?;
+        writeln!(writer, "  \"z_min\": {},", self.z_min)?;
+        writeln!(writer, "  \"z_max\": {},", self.z_max)?;
+        writeln!(writer, "  \"z_mean\": {},", self.z_mean)?;
+        writeln!(writer, "  \"peak_to_valley\": {},", self.z_max - self.z_min)?;
+        writeln!(writer, "  \"plane\": {}", plane)?;
+        writeln!(writer, "}}")?;
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Plane {
+    fn degrees(&self) -> f64 {
+        self.x_slope.hypot(self.y_slope).atan().to_degrees()
+    }
+}
+
+// Least squares fit, None unless the points span an area rather than a line
+fn fit_plane(points: &[&Point]) -> Option<Plane> {
+    let count = points.len() as f64;
+    let (mx, my, mz) = points.iter().fold((0.0, 0.0, 0.0), |(x, y, z), p| {
+        (x + p.0 / count, y + p.1 / count, z + p.2 / count)
+    });
+
+    // Sums around the mean, which keeps the fit accurate far from the machine origin
+    let (mut sxx, mut syy, mut sxy, mut sxz, mut syz) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (x, y, z) in points.iter().map(|p| (p.0 - mx, p.1 - my, p.2 - mz)) {
+        sxx += x * x;
+        syy += y * y;
+        sxy += x * y;
+        sxz += x * z;
+        syz += y * z;
+    }
+
+    let determinant = sxx * syy - sxy * sxy;
+    if determinant.abs() <= f64::EPSILON * (sxx * syy).max(1.0) {
+        return None;
+    }
+    let x_slope = (sxz * syy - syz * sxy) / determinant;
+    let y_slope = (syz * sxx - sxz * sxy) / determinant;
+
+    let residuals: Vec<f64> = points
+        .iter()
+        .map(|p| p.2 - mz - x_slope * (p.0 - mx) - y_slope * (p.1 - my))
+        .collect();
+    let span = |values: &mut dyn Iterator<Item = f64>| {
+        let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+        max - min
+    };
+
+    Some(Plane {
+        x_slope,
+        y_slope,
+        x_span: span(&mut points.iter().map(|p| p.0)),
+        y_span: span(&mut points.iter().map(|p| p.1)),
+        flatness: span(&mut residuals.into_iter()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(points: &[Point]) -> Option<Plane> {
+        fit_plane(&points.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn fit_plane_tilted() {
+        // Far from the origin, so the fit has to cope with large coordinates
+        let points: Vec<Point> = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (0.0, 20.0),
+            (10.0, 20.0),
+            (5.0, 10.0),
+        ]
+        .iter()
+        .map(|&(x, y)| {
+            (
+                x + 1000.0,
+                y - 500.0,
+                0.02 * (x + 1000.0) - 0.01 * (y - 500.0) + 3.0,
+            )
+        })
+        .collect();
+        let plane = fit(&points).unwrap();
+        assert!((plane.x_slope - 0.02).abs() < 1e-9);
+        assert!((plane.y_slope + 0.01).abs() < 1e-9);
+        assert!((plane.x_span - 10.0).abs() < 1e-9);
+        assert!((plane.y_span - 20.0).abs() < 1e-9);
+        assert!(plane.flatness < 1e-9);
+    }
+
+    #[test]
+    fn fit_plane_flatness() {
+        let points = [
+            (0.0, 0.0, 0.0),
+            (10.0, 0.0, 0.0),
+            (0.0, 10.0, 0.0),
+            (10.0, 10.0, 0.0),
+            (5.0, 5.0, 0.1),
+        ];
+        let plane = fit(&points).unwrap();
+        assert!(plane.x_slope.abs() < 1e-9 && plane.y_slope.abs() < 1e-9);
+        assert!((plane.flatness - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fit_plane_degenerate() {
+        assert!(fit(&[(1.0, 1.0, 0.0)]).is_none());
+        assert!(fit(&[(0.0, 0.0, 0.0), (0.0, 0.0, 1.0)]).is_none());
+        // Collinear points span a line, which doesn't fix a plane
+        assert!(fit(&[(0.0, 0.0, 0.0), (1.0, 1.0, 0.1), (2.0, 2.0, 0.2)]).is_none());
+        assert!(fit(&[(0.0, 5.0, 0.0), (1.0, 5.0, 0.1), (2.0, 5.0, 0.2)]).is_none());
+    }
+
+    #[test]
+    fn axis_positions_even() {
+        let axis = axis_positions([20.0, 0.0, 10.0, 10.005, 0.0].into_iter()).unwrap();
+        assert_eq!((axis.origin, axis.spacing, axis.count), (0.0, 10.0, 3));
+
+        let single = axis_positions([4.0, 4.0].into_iter()).unwrap();
+        assert_eq!((single.origin, single.spacing, single.count), (4.0, 0.0, 1));
+    }
+
+    #[test]
+    fn axis_positions_uneven() {
+        assert!(axis_positions([0.0, 10.0, 25.0].into_iter()).is_none());
+        assert!(axis_positions(std::iter::empty()).is_none());
+    }
+}
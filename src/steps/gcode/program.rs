@@ -0,0 +1,149 @@
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+
+use flate2::read::MultiGzDecoder;
+
+use crate::config::PostPreset;
+
+use super::dry_run::DryRun;
+use super::home_moves::HomeMoves;
+use super::post::Post;
+
+type Position = (f64, f64, f64);
+
+enum Source {
+    File(String),
+    // Stdin can only be read once, so it is kept in memory
+    Buffered(Vec<String>),
+    // FIFOs and sockets fed by a generator are read once, as lines are streamed
+    Live(String),
+}
+
+pub struct Program {
+    source: Source,
+    dry_run: Option<Option<f64>>,
+    post: Option<PostPreset>,
+    home_moves: Option<(Position, Position)>,
+    read_error: Cell<Option<io::Error>>,
+    consumed: Cell<bool>,
+}
+
+impl Program {
+    pub fn open(
+        path: &str,
+        dry_run: Option<Option<f64>>,
+        post: Option<PostPreset>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let source = if path == "-" {
+            let lines = io::stdin()
+                .lock()
+                .lines()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|error| format!("Failed to read G-code from stdin: {}", error))?;
+            Source::Buffered(lines)
+        } else {
+            let file_type = fs::metadata(path)
+                .map_err(|error| format!("Failed to open G-code file '{}': {}", path, error))?
+                .file_type();
+
+            if file_type.is_fifo() || file_type.is_socket() {
+                Source::Live(path.to_string())
+            } else {
+                File::open(path)
+                    .map_err(|error| format!("Failed to open G-code file '{}': {}", path, error))?;
+                Source::File(path.to_string())
+            }
+        };
+
+        Ok(Self {
+            source,
+            dry_run,
+            post,
+            home_moves: None,
+            read_error: Cell::new(None),
+            consumed: Cell::new(false),
+        })
+    }
+
+    /// Rewrites G28/G30 into moves to these stored positions, in machine coordinates and mm
+    pub fn rewrite_home_moves(&mut self, g28: Position, g30: Position) {
+        self.home_moves = Some((g28, g30));
+    }
+
+    pub fn is_live(&self) -> bool {
+        matches!(self.source, Source::Live(_))
+    }
+
+    // Reads the program lazily from the start, a read error ends the lines and is kept for `finish`
+    pub fn lines(&self) -> Result<impl Iterator<Item = String> + '_, Box<dyn std::error::Error>> {
+        let lines: Box<dyn Iterator<Item = io::Result<String>> + '_> = match &self.source {
+            Source::File(path) => {
+                let file = File::open(path)
+                    .map_err(|error| format!("Failed to open G-code file '{}': {}", path, error))?;
+
+                // Compressed programs are decompressed as they are read
+                if path.ends_with(".gz") {
+                    Box::new(BufReader::new(MultiGzDecoder::new(file)).lines())
+                } else {
+                    Box::new(BufReader::new(file).lines())
+                }
+            }
+            Source::Buffered(lines) => Box::new(lines.iter().cloned().map(Ok)),
+            Source::Live(path) => {
+                if self.consumed.replace(true) {
+                    return Err(
+                        format!("Live G-code source '{}' can only be read once", path).into(),
+                    );
+                }
+
+                // Lines are only read as they are streamed, so a slow machine blocks the generator
+                if fs::metadata(path)?.file_type().is_socket() {
+                    let stream = UnixStream::connect(path).map_err(|error| {
+                        format!("Failed to connect to G-code socket '{}': {}", path, error)
+                    })?;
+                    Box::new(BufReader::new(stream).lines())
+                } else {
+                    let file = File::open(path).map_err(|error| {
+                        format!("Failed to open G-code FIFO '{}': {}", path, error)
+                    })?;
+                    Box::new(BufReader::new(file).lines())
+                }
+            }
+        };
+
+        let post = self.post.map(Post::new);
+        let mut home_moves = self.home_moves.map(|(g28, g30)| HomeMoves::new(g28, g30));
+        let mut dry_run = self.dry_run.map(DryRun::new);
+
+        Ok(lines.map_while(move |line| match line {
+            Ok(line) => {
+                let line = match &post {
+                    Some(post) => post.rewrite(&line),
+                    None => line,
+                };
+                let line = match &mut home_moves {
+                    Some(home_moves) => home_moves.rewrite(&line),
+                    None => line,
+                };
+                Some(match &mut dry_run {
+                    Some(dry_run) => dry_run.rewrite(&line),
+                    None => line,
+                })
+            }
+            Err(error) => {
+                self.read_error.set(Some(error));
+                None
+            }
+        }))
+    }
+
+    pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.read_error.take() {
+            Some(error) => Err(format!("Failed to read G-code: {}", error).into()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub struct Progress {
+    pub total_lines: usize,
+    pub total_bytes: usize,
+    pub sent_lines: usize,
+    pub acknowledged_lines: usize,
+    pub acknowledged_bytes: usize,
+
+    // False for live sources, whose size isn't known until they end
+    bounded: bool,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Progress {
+    pub fn new(total_lines: usize, total_bytes: usize) -> Self {
+        Self {
+            total_lines,
+            total_bytes,
+            sent_lines: 0,
+            acknowledged_lines: 0,
+            acknowledged_bytes: 0,
+            bounded: true,
+            started: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn unbounded() -> Self {
+        Self {
+            bounded: false,
+            ..Self::new(0, 0)
+        }
+    }
+
+    pub fn update(&mut self, sent_lines: usize, acknowledged_bytes: usize) {
+        self.sent_lines = sent_lines;
+        self.acknowledged_lines += 1;
+        self.acknowledged_bytes += acknowledged_bytes;
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 100.0;
+        }
+
+        self.acknowledged_bytes as f64 / self.total_bytes as f64 * 100.0
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if self.acknowledged_bytes == 0 || elapsed == 0.0 {
+            return None;
+        }
+
+        let throughput = self.acknowledged_bytes as f64 / elapsed;
+        let remaining = self.total_bytes.saturating_sub(self.acknowledged_bytes);
+
+        Some(Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+
+    pub fn should_report(&mut self, interval: Duration) -> bool {
+        if interval.is_zero() || self.last_report.elapsed() < interval {
+            return false;
+        }
+
+        self.last_report = Instant::now();
+        true
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.bounded {
+            return write!(
+                f,
+                "{} lines acknowledged ({} sent)",
+                self.acknowledged_lines, self.sent_lines
+            );
+        }
+
+        write!(
+            f,
+            "{}/{} lines acknowledged ({} sent), {:.1}% complete",
+            self.acknowledged_lines,
+            self.total_lines,
+            self.sent_lines,
+            self.percent()
+        )?;
+
+        match self.eta() {
+            Some(eta) => {
+                let seconds = eta.as_secs();
+                write!(
+                    f,
+                    ", ETA {:02}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                )
+            }
+            None => write!(f, ", ETA unknown"),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::controller::ControllerError;
+use crate::controller::message::{Push, Report, Response};
+use crate::controller::serial::StreamObserver;
+
+/// CSV log of the status reports polled while a program streams
+pub struct ReportLog {
+    writer: BufWriter<File>,
+    interval: Duration,
+    started: Instant,
+    // Reports only include the offset now and then, and it's needed to turn WPos into MPos
+    wco: Option<(f32, f32, f32)>,
+    // Last program line acknowledged. Grbl acknowledges lines as they are planned, so this runs
+    // ahead of the line being cut by up to the planner's length
+    line: Option<i32>,
+    error: Option<io::Error>,
+}
+
+impl ReportLog {
+    pub fn new(mut writer: BufWriter<File>, interval: Duration) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "time,elapsed_s,line,state,x,y,z,work_x,work_y,work_z,feed,spindle,feed_override,rapid_override,spindle_override,planner_free,rx_free"
+        )?;
+
+        Ok(Self {
+            writer,
+            interval,
+            started: Instant::now(),
+            wco: None,
+            line: None,
+            error: None,
+        })
+    }
+
+    /// Flushes the log, returning the first error hit while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()
+    }
+
+    fn write(&mut self, report: &Report) {
+        if report.wco.is_some() {
+            self.wco = report.wco;
+        }
+        if self.error.is_some() {
+            return;
+        }
+
+        let offset = |(x, y, z): (f32, f32, f32), sign: f32| {
+            self.wco
+                .map(|(wx, wy, wz)| (x + sign * wx, y + sign * wy, z + sign * wz))
+        };
+        let mpos = report
+            .mpos
+            .or_else(|| report.wpos.and_then(|wpos| offset(wpos, 1.0)));
+        let wpos = report
+            .wpos
+            .or_else(|| report.mpos.and_then(|mpos| offset(mpos, -1.0)));
+
+        let state = report
+            .raw
+            .trim_start_matches('<')
+            .split(['|', '>'])
+            .next()
+            .unwrap_or_default();
+        let position = |position: Option<(f32, f32, f32)>| match position {
+            Some((x, y, z)) => format!("{},{},{}", x, y, z),
+            None => ",,".to_string(),
+        };
+        let value = |value: Option<String>| value.unwrap_or_default();
+
+        if let Err(error) = writeln!(
+            self.writer,
+            "{},{:.3},{},{},{},{},{},{},{},{},{},{},{}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+            self.started.elapsed().as_secs_f64(),
+            value(self.line.map(|line| line.to_string())),
+            state,
+            position(mpos),
+            position(wpos),
+            value(report.feed.map(|feed| feed.to_string())),
+            value(report.spindle.map(|speed| speed.to_string())),
+            value(report.ov.map(|(feed, _, _)| feed.to_string())),
+            value(report.ov.map(|(_, rapid, _)| rapid.to_string())),
+            value(report.ov.map(|(_, _, spindle)| spindle.to_string())),
+            value(report.bf.map(|(planner, _)| planner.to_string())),
+            value(report.bf.map(|(_, rx)| rx.to_string())),
+        ) {
+            self.error = Some(error);
+        }
+    }
+}
+
+/// Polls status reports into a report log, if there is one, and passes the stream on
+pub struct Recorded<'a, O> {
+    pub observer: O,
+    pub log: Option<&'a mut ReportLog>,
+    /// Added to the stream's line count to get the program line number
+    pub line_offset: i32,
+    /// Lines numbered below this were added to the program, e.g. the modal state restored on resume
+    pub first_line: i32,
+}
+
+impl<O: StreamObserver> Recorded<'_, O> {
+    fn acknowledged(&mut self, acknowledged: i32) {
+        let number = acknowledged + self.line_offset;
+        if let Some(log) = &mut self.log
+            && number >= self.first_line
+        {
+            log.line = Some(number);
+        }
+    }
+}
+
+impl<O: StreamObserver> StreamObserver for Recorded<'_, O> {
+    fn on_line_sent(&mut self, sent: i32, line: &str) {
+        self.observer.on_line_sent(sent, line);
+    }
+
+    fn on_ack(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        self.acknowledged(acknowledged);
+        self.observer.on_ack(acknowledged, sent, bytes, response)
+    }
+
+    fn on_error(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        self.acknowledged(acknowledged);
+        self.observer.on_error(acknowledged, sent, bytes, response)
+    }
+
+    fn on_response(
+        &mut self,
+        acknowledged: i32,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        self.observer.on_response(acknowledged, response)
+    }
+
+    fn on_push(&mut self, push: &Push) {
+        if let (Some(log), Push::Report(report)) = (&mut self.log, push) {
+            log.write(report);
+        }
+        self.observer.on_push(push);
+    }
+
+    fn report_interval(&self) -> Option<Duration> {
+        match &self.log {
+            Some(log) => Some(log.interval),
+            None => self.observer.report_interval(),
+        }
+    }
+}
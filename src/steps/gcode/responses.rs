@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
+
+use crate::controller::ControllerError;
+use crate::controller::message::{Push, Response};
+use crate::controller::serial::StreamObserver;
+
+/// CSV log of every line streamed and each response Grbl sent for it
+pub struct ResponseLog {
+    writer: BufWriter<File>,
+    mode: &'static str,
+    // Lines sent but not yet acknowledged, by program line number
+    pending: VecDeque<(Option<i32>, String)>,
+    error: Option<io::Error>,
+}
+
+impl ResponseLog {
+    pub fn new(mut writer: BufWriter<File>) -> io::Result<Self> {
+        writeln!(writer, "mode,line,command,response")?;
+
+        Ok(Self {
+            writer,
+            mode: "stream",
+            pending: VecDeque::new(),
+            error: None,
+        })
+    }
+
+    /// Sets the mode column written for the following lines, `check` or `stream`
+    pub fn set_mode(&mut self, mode: &'static str) {
+        self.mode = mode;
+        self.pending.clear();
+    }
+
+    /// Flushes the log, returning the first error hit while writing it
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()
+    }
+
+    fn sent(&mut self, line: Option<i32>, command: &str) {
+        self.pending.push_back((line, command.to_string()));
+    }
+
+    fn write(&mut self, response: &Response, acknowledged: bool) {
+        let (line, command) = if acknowledged {
+            self.pending.pop_front().unwrap_or_default()
+        } else {
+            self.pending.front().cloned().unwrap_or_default()
+        };
+
+        if self.error.is_some() {
+            return;
+        }
+
+        let line = line.map(|line| line.to_string()).unwrap_or_default();
+        if let Err(error) = writeln!(
+            self.writer,
+            "{},{},{},{}",
+            self.mode,
+            line,
+            field(&command),
+            field(&response.to_string())
+        ) {
+            self.error = Some(error);
+        }
+    }
+}
+
+// Quote fields containing separators, since comments in G-code can contain anything
+fn field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Records the stream in a response log, if there is one, before passing it on
+pub struct Logged<'a, O> {
+    pub observer: O,
+    pub log: Option<&'a mut ResponseLog>,
+    /// Added to the stream's line count to get the program line number
+    pub line_offset: i32,
+    /// Lines numbered below this were added to the program, e.g. the modal state restored on resume
+    pub first_line: i32,
+}
+
+impl<O: StreamObserver> StreamObserver for Logged<'_, O> {
+    fn on_line_sent(&mut self, sent: i32, line: &str) {
+        if let Some(log) = &mut self.log {
+            let number = sent + self.line_offset;
+            log.sent((number >= self.first_line).then_some(number), line);
+        }
+        self.observer.on_line_sent(sent, line);
+    }
+
+    fn on_ack(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        if let Some(log) = &mut self.log {
+            log.write(response, true);
+        }
+        self.observer.on_ack(acknowledged, sent, bytes, response)
+    }
+
+    fn on_error(
+        &mut self,
+        acknowledged: i32,
+        sent: i32,
+        bytes: usize,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        if let Some(log) = &mut self.log {
+            log.write(response, true);
+        }
+        self.observer.on_error(acknowledged, sent, bytes, response)
+    }
+
+    fn on_response(
+        &mut self,
+        acknowledged: i32,
+        response: &Response,
+    ) -> Result<(), ControllerError> {
+        if let Some(log) = &mut self.log {
+            log.write(response, false);
+        }
+        self.observer.on_response(acknowledged, response)
+    }
+
+    fn on_push(&mut self, push: &Push) {
+        self.observer.on_push(push);
+    }
+
+    fn report_interval(&self) -> Option<Duration> {
+        self.observer.report_interval()
+    }
+}
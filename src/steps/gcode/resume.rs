@@ -0,0 +1,325 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+
+use log::warn;
+
+use super::words::{WORDS, strip_comments};
+
+// Axes whose position is tracked, in the order of `ModalState::position`
+const AXES: [&str; 3] = ["X", "Y", "Z"];
+
+#[derive(Default)]
+pub struct ModalState {
+    motion: Option<String>,
+    units: Option<String>,
+    distance: Option<String>,
+    plane: Option<String>,
+    feed_mode: Option<String>,
+    wcs: Option<String>,
+    feed: Option<String>,
+    speed: Option<String>,
+    spindle: Option<String>,
+    coolant: Vec<String>,
+    // Work position the skipped lines end at, in the program's units, unknown until an axis moves
+    position: [Option<f64>; 3],
+    // G92 offsets are lost with a reset, and aren't restored
+    offset: bool,
+}
+
+impl ModalState {
+    pub fn scan<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Self {
+        let mut state = ModalState::default();
+
+        for line in lines {
+            let line = strip_comments(line.as_ref()).to_uppercase();
+            let words: Vec<(String, f64, String)> = WORDS
+                .captures_iter(&line)
+                .filter_map(|captures| {
+                    let value = &captures[2];
+                    let number = value.parse::<f64>().ok()?;
+                    // Codes are written the same way however the program pads them, e.g. G01
+                    let word = match &captures[1] {
+                        "G" | "M" => format!("{}{}", &captures[1], number),
+                        letter => format!("{}{}", letter, value),
+                    };
+                    Some((captures[1].to_string(), number, word))
+                })
+                .collect();
+
+            // Non-modal commands whose axis words aren't a move in the motion mode
+            let mut command = None;
+            for (letter, number, word) in &words {
+                match (letter.as_str(), *number) {
+                    ("G", 0.0 | 1.0 | 2.0 | 3.0 | 38.2..=38.5 | 80.0) => {
+                        state.motion = Some(word.clone())
+                    }
+                    ("G", 20.0 | 21.0) => {
+                        state.set_units(word);
+                    }
+                    ("G", 90.0 | 91.0) => state.distance = Some(word.clone()),
+                    ("G", 17.0 | 18.0 | 19.0) => state.plane = Some(word.clone()),
+                    ("G", 93.0 | 94.0) => state.feed_mode = Some(word.clone()),
+                    ("G", 54.0..=59.3) => state.wcs = Some(word.clone()),
+                    ("G", 4.0 | 10.0 | 28.0 | 30.0 | 43.1 | 53.0 | 92.0) => command = Some(*number),
+                    ("M", 3.0 | 4.0 | 5.0) => state.spindle = Some(word.clone()),
+                    ("M", 7.0 | 8.0) if !state.coolant.contains(word) => {
+                        state.coolant.push(word.clone())
+                    }
+                    ("M", 9.0) => state.coolant.clear(),
+                    ("F", _) => state.feed = Some(word.clone()),
+                    ("S", _) => state.speed = Some(word.clone()),
+                    _ => {}
+                }
+            }
+
+            let relative = state.distance.as_deref() == Some("G91");
+            let probing = state
+                .motion
+                .as_deref()
+                .is_some_and(|motion| motion.starts_with("G38"));
+            for (axis, position) in AXES.iter().zip(state.position.iter_mut()) {
+                let Some((_, value, _)) = words.iter().find(|(letter, ..)| letter == axis) else {
+                    continue;
+                };
+                *position = match command {
+                    // Dwells, offsets and tool lengths don't move
+                    Some(4.0 | 10.0 | 43.1) => *position,
+                    // Machine coordinates, or stored positions
+                    Some(28.0 | 30.0 | 53.0) => None,
+                    Some(_) => {
+                        state.offset = true;
+                        Some(*value)
+                    }
+                    // A probe stops wherever it makes contact
+                    None if probing => None,
+                    None if relative => position.map(|position| position + value),
+                    None => Some(*value),
+                };
+            }
+            // G28 and G30 end at the stored position, whichever axes they pass through
+            if matches!(command, Some(28.0 | 30.0)) {
+                state.position = [None; 3];
+            }
+        }
+
+        state
+    }
+
+    // Positions already known are converted, so they stay where they are on the machine
+    fn set_units(&mut self, units: &str) {
+        let factor = match (self.units.as_deref(), units) {
+            (Some("G21"), "G20") => 1.0 / 25.4,
+            (Some("G20"), "G21") => 25.4,
+            _ => 1.0,
+        };
+        for position in self.position.iter_mut().flatten() {
+            *position *= factor;
+        }
+        self.units = Some(units.to_string());
+    }
+
+    /// Lines that pick the program up again before `line`: retract to `safe_z` (machine
+    /// coordinates, mm), start the spindle and coolant and dwell `spindle_delay_s` for it to
+    /// reach speed, rapid over the position the skipped lines end at, plunge to it at the feed
+    /// rate, then restore the distance, feed and motion modes
+    pub fn preamble(
+        &self,
+        line: usize,
+        safe_z: f64,
+        spindle_delay_s: f64,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut preamble = vec![format!("G21 G53 G0 Z{:.3}", safe_z)];
+
+        // Feed per minute for the plunge, inverse time is restored after it
+        let modes: Vec<&str> = [&self.units, &self.plane]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(["G94"])
+            .collect();
+        preamble.push(modes.join(" "));
+
+        if let Some(wcs) = &self.wcs {
+            preamble.push(wcs.clone());
+        }
+
+        match (&self.spindle, &self.speed) {
+            (Some(spindle), Some(speed)) => preamble.push(format!("{} {}", spindle, speed)),
+            (Some(spindle), None) => preamble.push(spindle.clone()),
+            (None, Some(speed)) => preamble.push(speed.clone()),
+            (None, None) => {}
+        }
+        preamble.extend(self.coolant.iter().cloned());
+        if matches!(self.spindle.as_deref(), Some("M3" | "M4")) && spindle_delay_s > 0.0 {
+            preamble.push(format!("G4 P{:.1}", spindle_delay_s));
+        }
+
+        let [x, y, z] = self.position;
+        let xy: String = [("X", x), ("Y", y)]
+            .into_iter()
+            .filter_map(|(axis, position)| Some(format!(" {}{:.4}", axis, position?)))
+            .collect();
+        if !xy.is_empty() {
+            preamble.push(format!("G90 G0{}", xy));
+        }
+        for (axis, position) in AXES.iter().zip(self.position) {
+            if position.is_none() {
+                warn!(
+                    "The {} position before line {} isn't known, it is left where it is",
+                    axis, line
+                );
+            }
+        }
+        if let Some(z) = z {
+            let feed = match (&self.feed, self.feed_mode.as_deref()) {
+                (Some(feed), None | Some("G94")) => feed,
+                _ => {
+                    return Err(format!(
+                        "No feed rate per minute is set before line {} to plunge at",
+                        line
+                    )
+                    .into());
+                }
+            };
+            preamble.push(format!("G90 G1 Z{:.4} {}", z, feed));
+        }
+        if self.offset {
+            warn!(
+                "The skipped lines set a G92 offset, which isn't restored. Make sure it is in effect before resuming"
+            );
+        }
+
+        let mut restore: Vec<&str> = [&self.distance, &self.feed_mode]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if z.is_none()
+            && let Some(feed) = &self.feed
+        {
+            restore.push(feed);
+        }
+        // A probe is given again by the line that uses it, and has to have axis words
+        if let Some(motion) = &self.motion
+            && !motion.starts_with("G38")
+        {
+            restore.push(motion);
+        }
+        if !restore.is_empty() {
+            preamble.push(restore.join(" "));
+        }
+
+        Ok(preamble)
+    }
+}
+
+pub fn read_checkpoint(path: &str) -> Result<Option<usize>, Box<dyn std::error::Error>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let line = contents
+                .trim()
+                .parse()
+                .map_err(|error| format!("Invalid checkpoint file '{}': {}", path, error))?;
+            Ok(Some(line))
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(format!("Failed to read checkpoint file '{}': {}", path, error).into()),
+    }
+}
+
+pub fn write_checkpoint(path: &str, line: i32) -> io::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, format!("{}\n", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preamble(program: &str) -> Vec<String> {
+        ModalState::scan(program.lines())
+            .preamble(100, -2.0, 3.0)
+            .unwrap()
+    }
+
+    #[test]
+    fn modal_feed_moves() {
+        let program = "G21 G90 G17 G54\nM3 S12000\nG0 X0 Y0 Z5\nG01 Z-1 F300\nX10\nY20 (cut)\n";
+        assert_eq!(
+            preamble(program),
+            [
+                "G21 G53 G0 Z-2.000",
+                "G21 G17 G94",
+                "G54",
+                "M3 S12000",
+                "G4 P3.0",
+                "G90 G0 X10.0000 Y20.0000",
+                "G90 G1 Z-1.0000 F300",
+                "G90 G1",
+            ]
+        );
+    }
+
+    #[test]
+    fn arcs() {
+        let program = "G20 G90 G18\nM4 S8000 M8\nG0 X1 Y1 Z0.5\nG1 Z-0.1 F20\nG2 X2 Z0 I0.5 K0\nX1 Y1.25 I-0.5\n";
+        assert_eq!(
+            preamble(program),
+            [
+                "G21 G53 G0 Z-2.000",
+                "G20 G18 G94",
+                "M4 S8000",
+                "M8",
+                "G4 P3.0",
+                "G90 G0 X1.0000 Y1.2500",
+                "G90 G1 Z0.0000 F20",
+                "G90 G2",
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_moves() {
+        let state = ModalState::scan(["G90 G0 X0 Y0 Z0", "G91 G1 X1 F100", "X1 Y-2"]);
+        assert_eq!(state.position, [Some(2.0), Some(-2.0), Some(0.0)]);
+        assert_eq!(
+            state.preamble(4, 0.0, 0.0).unwrap().last().unwrap(),
+            "G91 G1"
+        );
+
+        // Relative moves from an unknown position stay unknown
+        let state = ModalState::scan(["G91 G0 X1"]);
+        assert_eq!(state.position, [None; 3]);
+    }
+
+    #[test]
+    fn untracked_moves() {
+        // Probes stop at contact, G53 moves are in machine coordinates and G28 goes home
+        let state = ModalState::scan(["G0 X1 Y2 Z3", "G38.2 Z-10 F50", "G53 G0 X5"]);
+        assert_eq!(state.position, [None, Some(2.0), None]);
+        assert_eq!(state.motion.as_deref(), Some("G0"));
+        let state = ModalState::scan(["G0 X1 Y2 Z3", "G28 G91 Z0"]);
+        assert_eq!(state.position, [None; 3]);
+        let state = ModalState::scan(["G0 X1 Y2 Z3", "G4 P1", "G10 L20 P1 X0"]);
+        assert_eq!(state.position, [Some(1.0), Some(2.0), Some(3.0)]);
+    }
+
+    #[test]
+    fn units_change() {
+        let state = ModalState::scan(["G21 G0 X25.4", "G20 G0 Y1"]);
+        let [x, y, z] = state.position;
+        assert!((x.unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!((y, z), (Some(1.0), None));
+    }
+
+    #[test]
+    fn plunge_needs_a_feed() {
+        let state = ModalState::scan(["G0 X1 Y1 Z1"]);
+        assert!(state.preamble(2, 0.0, 0.0).is_err());
+        let state = ModalState::scan(["G93", "G1 X1 Y1 Z1 F2"]);
+        assert!(state.preamble(2, 0.0, 0.0).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use log::{info, warn};
+
+use crate::config::CncConfig;
+use crate::controller::Controller;
+use crate::controller::message::Response;
+use crate::controller::serial::checked_stream;
+
+const WORK_COORDINATE_SYSTEMS: [&str; 6] = ["G54", "G55", "G56", "G57", "G58", "G59"];
+
+pub type Offset = (String, (f64, f64, f64));
+
+pub fn read_offsets(
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<Vec<Offset>, Box<dyn std::error::Error>> {
+    let offsets = checked_stream(controller, vec!["$#"], &config.grbl)
+        .map_err(|error| format!("Failed to read work offsets: {}", error))?
+        .into_iter()
+        .filter_map(|(_, response)| match response {
+            Response::Offset { name, coords, .. } => Some((name, coords)),
+            _ => None,
+        })
+        .collect();
+
+    Ok(offsets)
+}
+
+// Put back the work offsets from before the first attempt, in case the program changed them
+pub fn restore_offsets(
+    controller: &Controller,
+    config: &CncConfig,
+    offsets: &[Offset],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut commands = Vec::new();
+
+    for (name, (x, y, z)) in offsets {
+        if let Some(index) = WORK_COORDINATE_SYSTEMS.iter().position(|wcs| wcs == name) {
+            commands.push(format!(
+                "G21 G10 L2 P{} X{:.3} Y{:.3} Z{:.3}",
+                index + 1,
+                x,
+                y,
+                z
+            ));
+        } else if name == "G92" {
+            if (*x, *y, *z) == (0.0, 0.0, 0.0) {
+                commands.push("G92.1".to_string());
+            } else {
+                warn!("G92 offset can't be restored, check the work position before continuing");
+            }
+        }
+    }
+
+    checked_stream(
+        controller,
+        commands.iter().map(String::as_str).collect(),
+        &config.grbl,
+    )
+    .map_err(|error| format!("Failed to restore work offsets: {}", error))?;
+
+    info!("Restored work offsets");
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use super::words::strip_comments;
+
+// A line with its whitespace removed that is nothing but words
+static LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z][-+]?(\d+\.?\d*|\.\d+))*$").unwrap());
+
+// Checks that lines are made of words Grbl can parse, a letter followed by a number
+pub struct SyntaxChecker;
+
+impl SyntaxChecker {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn is_valid(&self, line: &str) -> bool {
+        let code: String = strip_comments(line)
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+
+        // System commands and program delimiters aren't made of words
+        code.starts_with('$') || code == "%" || LINE.is_match(&code)
+    }
+}
@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Parenthesised and `;` comments
+static COMMENTS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\([^)]*\)|;.*$").unwrap());
+
+/// A word, the letter and the number as captures 1 and 2, e.g. `G 1` or `x-.5`
+pub static WORDS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([A-Za-z])\s*([-+]?\d*\.?\d+)").unwrap());
+
+pub fn strip_comments(line: &str) -> Cow<'_, str> {
+    COMMENTS.replace_all(line, "")
+}
+
+/// Whether `line` has `query` in it, ignoring case. A query that is a single word, e.g. `T3`,
+/// has to match a word outside the comments by value, so it finds `T03` but not `T30`
+pub fn line_matches(line: &str, query: &str) -> bool {
+    let query = query.trim();
+    if let Some(word) = WORDS.captures(query)
+        && word[0].len() == query.len()
+        && let Ok(value) = word[2].parse::<f64>()
+    {
+        return WORDS.captures_iter(&strip_comments(line)).any(|captures| {
+            captures[1].eq_ignore_ascii_case(&word[1])
+                && captures[2]
+                    .parse::<f64>()
+                    .is_ok_and(|number| number == value)
+        });
+    }
+
+    line.to_uppercase().contains(&query.to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_match_by_value() {
+        assert!(line_matches("M6 T3", "T3"));
+        assert!(line_matches("m6 t03 (tool 30)", "T3"));
+        assert!(!line_matches("M6 T30", "T3"));
+        assert!(!line_matches("M6 (T3)", "T3"));
+        assert!(line_matches("G1 X-.5", "x-0.5"));
+    }
+
+    #[test]
+    fn text_matches_anywhere() {
+        assert!(line_matches("(Contour pass 2)", "contour"));
+        assert!(line_matches("G0 X1 Y2", "x1 y2"));
+        assert!(!line_matches("G0 X1", "pocket"));
+    }
+}
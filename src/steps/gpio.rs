@@ -0,0 +1,33 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{GpioAction, GpioStepConfig, Level};
+use crate::gpio;
+use log::info;
+
+pub fn execute_gpio_step(step: &GpioStepConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // The output keeps its level once the step completes, so accessories stay switched until a later step
+    let mut pin = gpio::open()?.output(step.pin)?;
+
+    match step.action {
+        GpioAction::Set => {
+            info!("Setting GPIO pin {} high", step.pin);
+            pin.set(Level::High)?;
+        }
+        GpioAction::Clear => {
+            info!("Setting GPIO pin {} low", step.pin);
+            pin.set(Level::Low)?;
+        }
+        GpioAction::Pulse => {
+            info!(
+                "Pulsing GPIO pin {} high for {} ms",
+                step.pin, step.pulse_ms
+            );
+            pin.set(Level::High)?;
+            thread::sleep(Duration::from_millis(step.pulse_ms));
+            pin.set(Level::Low)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use log::info;
+
+use crate::config::{Axis, CncConfig, GrblConfig, HomeStepConfig};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Status};
+use crate::controller::serial::{checked_stream, wait_for_report};
+use crate::display;
+use crate::limits::component;
+
+pub fn execute_home_step(
+    step: &HomeStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Homing");
+
+    // Homing only responds once the cycle completes, so don't apply the response timeout
+    let grbl = GrblConfig {
+        response_timeout_ms: None,
+        ..config.grbl.clone()
+    };
+    checked_stream(controller, vec!["$H"], &grbl)
+        .map_err(|error| format!("Failed to home: {}", error))?;
+
+    let report = wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )?
+    .ok_or("Stopped while homing")?;
+
+    let expected: Vec<(Axis, f64)> = [(Axis::X, step.x), (Axis::Y, step.y), (Axis::Z, step.z)]
+        .into_iter()
+        .filter_map(|(axis, position)| position.map(|position| (axis, step.units.to_mm(position))))
+        .collect();
+
+    if !expected.is_empty() {
+        let mpos = report
+            .mpos
+            .ok_or("Status reports don't include the machine position (MPos:), check $10")?;
+        let tolerance = step.units.to_mm(step.tolerance);
+
+        for (axis, position) in expected {
+            let actual = component(mpos, axis);
+            if (actual - position).abs() > tolerance {
+                return Err(format!(
+                    "{} axis homed to {}, expected {} (tolerance {})",
+                    axis,
+                    display::length(actual),
+                    display::length(position),
+                    display::length(tolerance)
+                )
+                .into());
+            }
+        }
+
+        info!("Home position verified");
+    }
+
+    info!("Homing complete");
+
+    Ok(())
+}
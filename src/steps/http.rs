@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use log::info;
+use ureq::Agent;
+
+use crate::config::{HttpMethod, HttpStepConfig, TemplateVars, apply_template};
+
+pub fn execute_http_step(
+    step: &HttpStepConfig,
+    vars: &TemplateVars,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = apply_template(&step.url, vars);
+    let body = step.body.as_ref().map(|body| apply_template(body, vars));
+
+    info!(
+        "Sending HTTP {} request to '{}'",
+        format!("{:?}", step.method).to_uppercase(),
+        url
+    );
+
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(Duration::from_millis(step.timeout_ms)))
+        .build()
+        .into();
+
+    // Responses with an error status are returned as errors, failing the step
+    let result = match step.method {
+        HttpMethod::Get => {
+            let mut request = agent.get(&url);
+            for (name, value) in &step.headers {
+                request = request.header(name, apply_template(value, vars));
+            }
+            request.call()
+        }
+        HttpMethod::Post => {
+            let mut request = agent.post(&url);
+            for (name, value) in &step.headers {
+                request = request.header(name, apply_template(value, vars));
+            }
+            request.send(body.unwrap_or_default())
+        }
+    };
+
+    let mut response =
+        result.map_err(|error| format!("HTTP request to '{}' failed: {}", url, error))?;
+
+    info!("HTTP request completed with status {}", response.status());
+
+    let text = response.body_mut().read_to_string()?;
+    if !text.trim().is_empty() {
+        info!("Response body: {}", text.trim());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::Ordering;
+
+use log::{info, warn};
+
+use crate::config::{Axis, CncConfig, JogStepConfig};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Status};
+use crate::controller::serial::wait_for_report;
+use crate::display;
+
+use super::check_move;
+
+pub fn execute_jog_step(
+    step: &JogStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let moves: Vec<(Axis, f64)> = [(Axis::X, step.x), (Axis::Y, step.y), (Axis::Z, step.z)]
+        .into_iter()
+        .filter_map(|(axis, distance)| distance.map(|distance| (axis, step.units.to_mm(distance))))
+        .collect();
+    let feed = step.units.to_mm(step.feed);
+
+    if moves.is_empty() {
+        return Err("Jog step has no axes to move".into());
+    }
+
+    info!(
+        "Jogging {} ({})",
+        moves
+            .iter()
+            .map(|(axis, distance)| format!("{} {}", axis, display::length(*distance)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        if step.relative {
+            "relative"
+        } else {
+            "absolute"
+        }
+    );
+
+    check_move(controller, config, &moves, feed, step.relative)
+        .map_err(|error| format!("Refusing to jog: {}", error))?;
+
+    controller
+        .jog(&moves, feed, step.relative, &config.grbl)
+        .map_err(|error| format!("Failed to jog: {}", error))?;
+
+    wait_for_report(
+        controller,
+        Some(|report: &Report| {
+            matches!(report.status, Some(Status::Idle))
+                || controller.stopping.load(Ordering::Relaxed)
+        }),
+    )?;
+
+    if controller.stopping.load(Ordering::Relaxed) {
+        warn!("Cancelling jog");
+        controller.cancel_jog()?;
+        return Err("Jog cancelled".into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use log::info;
+
+use crate::config::{CncConfig, RepeatStepConfig, TemplateVars};
+use crate::controller::Controller;
+use crate::start_signal::StartSignal;
+
+pub fn execute_repeat_step(
+    step: &RepeatStepConfig,
+    controller: &Controller,
+    vars: &mut TemplateVars,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Nested repeats shadow the iteration, so restore the outer one afterwards
+    let outer_iteration = vars.iteration;
+    let outer_step = vars.step.clone();
+
+    for iteration in 1..=step.count {
+        vars.iteration = iteration;
+        info!("Starting iteration {} of {}", iteration, step.count);
+
+        for (i, nested) in step.steps.iter().enumerate() {
+            if nested.should_wait() && !nested.wait(controller, config, signal, None)? {
+                return Err("Stopped while waiting for start signal".into());
+            }
+
+            info!(
+                "Executing iteration {} step {} (part: {})",
+                iteration,
+                i + 1,
+                vars.count
+            );
+
+            vars.step = nested.label(i + 1);
+            nested
+                .execute(controller, vars, config, signal)
+                .map_err(|error| {
+                    format!("Iteration {} step {} failed: {}", iteration, i + 1, error)
+                })?;
+        }
+    }
+
+    vars.iteration = outer_iteration;
+    vars.step = outer_step;
+
+    info!("Completed {} iterations", step.count);
+
+    Ok(())
+}
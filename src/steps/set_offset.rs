@@ -0,0 +1,65 @@
+use log::info;
+
+use crate::config::{Axis, CncConfig, SetOffsetStepConfig, Wcs};
+use crate::controller::Controller;
+use crate::controller::serial::checked_stream;
+
+impl Wcs {
+    // P number used by G10 to select the coordinate system
+    fn number(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+pub fn execute_set_offset_step(
+    step: &SetOffsetStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let values: Vec<(Axis, f64)> = [(Axis::X, step.x), (Axis::Y, step.y), (Axis::Z, step.z)]
+        .into_iter()
+        .filter_map(|(axis, value)| value.map(|value| (axis, step.units.to_mm(value))))
+        .collect();
+
+    if values.is_empty() {
+        return Err("Set offset step needs at least one of x, y or z".into());
+    }
+
+    let command = if step.from_probe {
+        let contact = controller
+            .last_probe
+            .lock()
+            .map_err(|_| "Failed to read the last probe contact")?
+            .ok_or("No successful probe has been recorded yet")?;
+
+        // L2 sets the offset itself, so place the probe contact at the given work position
+        let words: Vec<String> = values
+            .iter()
+            .map(|&(axis, value)| {
+                let position = match axis {
+                    Axis::X => contact.0,
+                    Axis::Y => contact.1,
+                    Axis::Z => contact.2,
+                };
+                format!("{}{:.3}", axis, position - value)
+            })
+            .collect();
+
+        format!("G21 G10 L2 P{} {}", step.wcs.number(), words.join(" "))
+    } else {
+        // L20 makes the current position the given work position
+        let words: Vec<String> = values
+            .iter()
+            .map(|(axis, value)| format!("{}{:.3}", axis, value))
+            .collect();
+
+        format!("G21 G10 L20 P{} {}", step.wcs.number(), words.join(" "))
+    };
+
+    checked_stream(controller, vec![&command], &config.grbl)
+        .map_err(|error| format!("Failed to set {:?} offset: {}", step.wcs, error))?;
+
+    info!("Set {:?} offset with '{}'", step.wcs, command);
+
+    Ok(())
+}
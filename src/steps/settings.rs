@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+
+use config::{Config, File};
+use log::{info, warn};
+
+use crate::config::{CncConfig, SettingsStepConfig, TemplateVars, apply_template, expand_path};
+use crate::controller::Controller;
+use crate::controller::message::Response;
+use crate::controller::serial::checked_stream;
+
+pub fn execute_settings_step(
+    step: &SettingsStepConfig,
+    controller: &Controller,
+    vars: &TemplateVars,
+    config: &CncConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = apply_template(&expand_path(&step.path), vars);
+    let settings = read_settings(&path)
+        .map_err(|error| format!("Failed to read settings from '{}': {}", path, error))?;
+
+    if settings.is_empty() {
+        warn!("No settings found in '{}'", path);
+        return Ok(());
+    }
+
+    info!("Applying {} settings from '{}'", settings.len(), path);
+
+    let commands: Vec<String> = settings
+        .iter()
+        .map(|(id, value)| format!("${}={}", id, value))
+        .collect();
+
+    // Settings writes are sent one at a time, so the first rejected setting stops the rest
+    checked_stream(
+        controller,
+        commands.iter().map(String::as_str).collect(),
+        &config.grbl,
+    )
+    .map_err(|error| format!("Failed to apply settings: {}", error))?;
+
+    if step.verify {
+        let current: HashMap<u16, String> = checked_stream(controller, vec!["$$"], &config.grbl)
+            .map_err(|error| format!("Failed to read settings: {}", error))?
+            .into_iter()
+            .filter_map(|(_, response)| match response {
+                Response::Setting { id, value } => Some((id, value)),
+                _ => None,
+            })
+            .collect();
+
+        let mismatches: Vec<String> = settings
+            .iter()
+            .filter_map(|(id, value)| {
+                let id: u16 = id.parse().ok()?;
+                match current.get(&id) {
+                    Some(actual) if same_value(actual, value) => None,
+                    Some(actual) => Some(format!("${} is {}, expected {}", id, actual, value)),
+                    None => Some(format!("${} is missing, expected {}", id, value)),
+                }
+            })
+            .collect();
+
+        if !mismatches.is_empty() {
+            return Err(format!("Settings didn't verify: {}", mismatches.join(", ")).into());
+        }
+
+        info!("Settings verified");
+    }
+
+    info!("Settings applied");
+
+    Ok(())
+}
+
+// Either `$N=value` lines, or a TOML profile of `N = value` pairs for files ending in `.toml`
+fn read_settings(path: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    if path.ends_with(".toml") {
+        let profile: HashMap<String, String> = Config::builder()
+            .add_source(File::with_name(path))
+            .build()?
+            .try_deserialize()?;
+
+        let mut settings: Vec<(String, String)> = profile
+            .into_iter()
+            .map(|(id, value)| (id.trim_start_matches('$').to_string(), value))
+            .collect();
+        settings.sort_by_key(|(id, _)| id.parse::<u16>().unwrap_or(u16::MAX));
+
+        return Ok(settings);
+    }
+
+    fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.strip_prefix('$')
+                .and_then(|setting| setting.split_once('='))
+                .map(|(id, value)| (id.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| format!("Invalid setting '{}'", line).into())
+        })
+        .collect()
+}
+
+// Grbl reports numbers with its own precision, so compare them numerically
+fn same_value(actual: &str, expected: &str) -> bool {
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(actual), Ok(expected)) => (actual - expected).abs() < 1e-3,
+        _ => actual == expected,
+    }
+}
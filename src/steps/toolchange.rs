@@ -0,0 +1,148 @@
+use log::info;
+
+use crate::config::{Axis, CncConfig, Confirm, ToolchangeStepConfig};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Response, Status};
+use crate::controller::serial::{checked_stream, wait_for_report};
+use crate::display;
+use crate::job::wait_for_signal;
+use crate::prompt;
+use crate::start_signal::StartSignal;
+
+pub fn execute_toolchange_step(
+    step: &ToolchangeStepConfig,
+    controller: &Controller,
+    config: &CncConfig,
+    signal: &mut StartSignal,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mm = |value: Option<f64>| value.map(|value| step.units.to_mm(value));
+    let (park_x, park_y, park_z) = (mm(step.park.x), mm(step.park.y), mm(step.park.z));
+
+    // Park and probe positions are machine coordinates, so they are checked without a work offset
+    if let Some(machine) = &config.machine {
+        let mut targets: Vec<(Axis, f64)> =
+            [(Axis::X, park_x), (Axis::Y, park_y), (Axis::Z, park_z)]
+                .into_iter()
+                .filter_map(|(axis, position)| position.map(|position| (axis, position)))
+                .collect();
+        if let Some(probe) = &step.probe {
+            targets.extend([
+                (Axis::X, step.units.to_mm(probe.x)),
+                (Axis::Y, step.units.to_mm(probe.y)),
+                (Axis::Z, step.units.to_mm(probe.z - probe.depth)),
+            ]);
+        }
+
+        machine
+            .check(&targets, 0.0)
+            .map_err(|error| format!("Refusing to change tool: {}", error))?;
+    }
+
+    info!("Parking for tool change");
+
+    // Stop the spindle and raise Z before moving across the work
+    let mut commands = vec!["M5".to_string()];
+    if let Some(z) = park_z {
+        commands.push(format!("G21 G53 G0 Z{:.3}", z));
+    }
+    if park_x.is_some() || park_y.is_some() {
+        commands.push(format!(
+            "G21 G53 G0{}{}",
+            word("X", park_x),
+            word("Y", park_y)
+        ));
+    }
+    move_and_wait(controller, config, &commands)
+        .map_err(|error| format!("Failed to park for tool change: {}", error))?;
+
+    match step.confirm {
+        Confirm::Signal => {
+            info!("Change the tool, then trigger the start signal to continue");
+            if !wait_for_signal(controller, config, signal)? {
+                return Err("Stopped during tool change".into());
+            }
+        }
+        Confirm::Console => {
+            if !prompt::confirm("Change the tool. Continue?")? {
+                return Err("Tool change cancelled".into());
+            }
+        }
+    }
+
+    if let Some(probe) = &step.probe {
+        info!("Measuring tool length");
+
+        let (x, y, z) = (
+            step.units.to_mm(probe.x),
+            step.units.to_mm(probe.y),
+            step.units.to_mm(probe.z),
+        );
+        move_and_wait(
+            controller,
+            config,
+            &[
+                format!("G21 G53 G0 X{:.3} Y{:.3}", x, y),
+                format!("G21 G53 G0 Z{:.3}", z),
+            ],
+        )
+        .map_err(|error| format!("Failed to move to the tool setter: {}", error))?;
+
+        let motion = format!(
+            "G21 G91 G38.2 Z-{:.3} F{:.3}",
+            step.units.to_mm(probe.depth),
+            step.units.to_mm(probe.feed)
+        );
+        let contact = checked_stream(controller, vec![&motion, "G90"], &config.grbl)
+            .map_err(|error| format!("Failed to probe tool length: {}", error))?
+            .into_iter()
+            .find_map(|(_, response)| match response {
+                Response::Probe { coords, .. } => Some(coords.2),
+                _ => None,
+            })
+            .ok_or("Probe didn't report a contact position")?;
+
+        // The offset is relative to the reference tool touching off at the same machine Z
+        let offset = contact - step.units.to_mm(probe.reference);
+        let mut commands = vec![
+            format!("G21 G43.1 Z{:.3}", offset),
+            format!("G21 G53 G0 Z{:.3}", z),
+        ];
+        if let Some(z) = park_z {
+            commands.push(format!("G21 G53 G0 Z{:.3}", z));
+        }
+        move_and_wait(controller, config, &commands)
+            .map_err(|error| format!("Failed to apply tool length offset: {}", error))?;
+
+        info!("Tool length offset set to {}", display::length(offset));
+    }
+
+    info!("Tool change complete");
+
+    Ok(())
+}
+
+fn word(letter: &str, value: Option<f64>) -> String {
+    value
+        .map(|value| format!(" {}{:.3}", letter, value))
+        .unwrap_or_default()
+}
+
+fn move_and_wait(
+    controller: &Controller,
+    config: &CncConfig,
+    commands: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    checked_stream(
+        controller,
+        commands.iter().map(String::as_str).collect(),
+        &config.grbl,
+    )?;
+
+    wait_for_report(
+        controller,
+        Some(|report: &Report| matches!(report.status, Some(Status::Idle))),
+    )?
+    .ok_or("Stopped while moving")?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::config::{Pull, WaitCondition, WaitStepConfig, expand_path};
+use crate::controller::Controller;
+use crate::controller::message::{Report, Status};
+use crate::controller::serial::wait_for_report;
+use crate::gpio;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl fmt::Display for WaitCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitCondition::Idle => write!(f, "the machine is idle"),
+            WaitCondition::Gpio { pin, level } => write!(f, "GPIO {} is {:?}", pin, level),
+            WaitCondition::File { path } => write!(f, "'{}' exists", path),
+        }
+    }
+}
+
+pub fn execute_wait_step(
+    step: &WaitStepConfig,
+    controller: &Controller,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if step.duration_ms.is_none() && step.until.is_none() {
+        return Err("Wait step needs a duration_ms or an until condition".into());
+    }
+
+    // A skip requested before the wait began was meant for something else
+    controller.skip_wait.store(false, Ordering::Relaxed);
+
+    if let Some(duration_ms) = step.duration_ms {
+        info!("Waiting for {} ms", duration_ms);
+
+        let end = Instant::now() + Duration::from_millis(duration_ms);
+        while Instant::now() < end {
+            if controller.stopping.load(Ordering::Relaxed) {
+                return Err("Stopped while waiting".into());
+            }
+            if controller.skip_wait.swap(false, Ordering::Relaxed) {
+                info!("Wait skipped");
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL.min(end.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    if let Some(condition) = &step.until {
+        info!("Waiting until {}", condition);
+
+        let deadline = step
+            .timeout_ms
+            .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms));
+
+        // Claim the input once instead of on every poll
+        let input = match condition {
+            WaitCondition::Gpio { pin, .. } => Some(gpio::open()?.input(*pin, Pull::None)?),
+            _ => None,
+        };
+
+        loop {
+            let met = match condition {
+                WaitCondition::Idle => wait_for_report(controller, None::<fn(&Report) -> bool>)?
+                    .is_some_and(|report| matches!(report.status, Some(Status::Idle))),
+                WaitCondition::Gpio { level, .. } => match &input {
+                    Some(input) => input.read()? == *level,
+                    None => false,
+                },
+                WaitCondition::File { path } => Path::new(&expand_path(path)).exists(),
+            };
+
+            if met {
+                break;
+            }
+            if controller.stopping.load(Ordering::Relaxed) {
+                return Err("Stopped while waiting".into());
+            }
+            if controller.skip_wait.swap(false, Ordering::Relaxed) {
+                info!("Wait skipped");
+                return Ok(());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(format!("Timed out waiting until {}", condition).into());
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    info!("Wait complete");
+
+    Ok(())
+}
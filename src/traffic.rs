@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Local;
+use log::error;
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+/// A serial port that copies every byte sent and received to a dump file, a line per read or
+/// write: `<timestamp> > <bytes>` for sent and `<timestamp> < <bytes>` for received
+pub struct Tap {
+    port: Box<dyn SerialPort>,
+    // Shared with the clones of the port, so the dump keeps the order bytes went over the wire
+    dump: Arc<Mutex<File>>,
+}
+
+impl Tap {
+    pub fn new(port: Box<dyn SerialPort>, dump: File) -> Self {
+        Self {
+            port,
+            dump: Arc::new(Mutex::new(dump)),
+        }
+    }
+
+    fn record(&self, direction: char, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let line = format!(
+            "{} {} {}\n",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.6f"),
+            direction,
+            escape(bytes)
+        );
+
+        let mut dump = match self.dump.lock() {
+            Ok(dump) => dump,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(error) = dump.write_all(line.as_bytes()) {
+            error!("Failed to write traffic dump: {}", error);
+        }
+    }
+}
+
+// Printable ASCII is kept, anything else (line endings, realtime commands) is escaped
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => escaped += "\\n",
+            b'\r' => escaped += "\\r",
+            b'\\' => escaped += "\\\\",
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped += &format!("\\x{:02x}", byte),
+        }
+    }
+    escaped
+}
+
+impl Read for Tap {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.port.read(buf)?;
+        self.record('<', &buf[..read]);
+        Ok(read)
+    }
+}
+
+impl Write for Tap {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.port.write(buf)?;
+        self.record('>', &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl SerialPort for Tap {
+    fn name(&self) -> Option<String> {
+        self.port.name()
+    }
+
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        self.port.baud_rate()
+    }
+
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        self.port.data_bits()
+    }
+
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        self.port.flow_control()
+    }
+
+    fn parity(&self) -> serialport::Result<Parity> {
+        self.port.parity()
+    }
+
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        self.port.stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> serialport::Result<()> {
+        self.port.set_baud_rate(baud_rate)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> serialport::Result<()> {
+        self.port.set_data_bits(data_bits)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> serialport::Result<()> {
+        self.port.set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> serialport::Result<()> {
+        self.port.set_parity(parity)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> serialport::Result<()> {
+        self.port.set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serialport::Result<()> {
+        self.port.set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
+        self.port.write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        self.port.read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        self.port.read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        self.port.read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        self.port.read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_read()
+    }
+
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        self.port.bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> serialport::Result<()> {
+        self.port.clear(buffer_to_clear)
+    }
+
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        Ok(Box::new(Self {
+            port: self.port.try_clone()?,
+            dump: self.dump.clone(),
+        }))
+    }
+
+    fn set_break(&self) -> serialport::Result<()> {
+        self.port.set_break()
+    }
+
+    fn clear_break(&self) -> serialport::Result<()> {
+        self.port.clear_break()
+    }
+}
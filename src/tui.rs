@@ -0,0 +1,637 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Local;
+use crossbeam::channel::{self, Sender};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use log::{LevelFilter, Log, Metadata, Record, info, warn};
+use simplelog::{Config, SharedLogger};
+
+use crate::controller::Monitor;
+use crate::controller::message::Report;
+use crate::job::JobHandle;
+use crate::status::{self, Job};
+use crate::steps::line_matches;
+
+// Log lines kept for the log pane, and printed once the screen closes
+const LOG_LINES: usize = 500;
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+const KEY_POLL: Duration = Duration::from_millis(100);
+// Program lines shown around the one in view
+const PROGRAM_ROWS: usize = 7;
+const KEYS: &str = "h hold  r resume  space toggle  w skip wait  x stop  g go to  / search  n next  m mark  f follow";
+
+struct Prompt {
+    question: String,
+    input: String,
+    answer: Sender<String>,
+}
+
+// The G-code program streaming, for finding a line to restart from
+struct Program {
+    lines: Vec<String>,
+    checkpoint: Option<String>,
+    // Line in the middle of the pane, None follows the line last acknowledged
+    view: Option<usize>,
+    search: Option<String>,
+    mark: Option<usize>,
+}
+
+enum EntryKind {
+    Line,
+    Search,
+}
+
+// A line number or search typed at the bottom of the screen
+struct Entry {
+    kind: EntryKind,
+    input: String,
+}
+
+struct Screen {
+    active: bool,
+    log: VecDeque<String>,
+    prompt: Option<Prompt>,
+    confirm_stop: bool,
+    program: Option<Program>,
+    entry: Option<Entry>,
+}
+
+static SCREEN: Mutex<Screen> = Mutex::new(Screen {
+    active: false,
+    log: VecDeque::new(),
+    prompt: None,
+    confirm_stop: false,
+    program: None,
+    entry: None,
+});
+
+/// Whether the terminal UI has the screen, so input has to go through it
+pub fn active() -> bool {
+    lock_screen().active
+}
+
+/// Asks `question` at the bottom of the screen and waits for the answer
+pub fn read_line(question: &str) -> io::Result<String> {
+    let (tx, rx) = channel::bounded(1);
+    lock_screen().prompt = Some(Prompt {
+        question: question.to_string(),
+        input: String::new(),
+        answer: tx,
+    });
+    rx.recv()
+        .map_err(|_| io::Error::other("Terminal UI closed before the question was answered"))
+}
+
+/// Shows `lines` as the program streaming, so a line can be marked to restart from. The mark is
+/// only kept with a `checkpoint` to save it to
+pub fn show_program(lines: Vec<String>, checkpoint: Option<String>) {
+    let mut screen = lock_screen();
+    if screen.active {
+        screen.program = Some(Program {
+            lines,
+            checkpoint,
+            view: None,
+            search: None,
+            mark: None,
+        });
+    }
+}
+
+/// Stops showing the program once it's done streaming, returning the line marked to restart from
+pub fn close_program() -> Option<usize> {
+    let mut screen = lock_screen();
+    screen.entry = None;
+    screen.program.take()?.mark
+}
+
+/// Shows log records in the log pane while the screen is up, and on the terminal otherwise
+pub struct TuiLogger {
+    level: LevelFilter,
+}
+
+impl TuiLogger {
+    pub fn new(level: LevelFilter) -> Box<Self> {
+        Box::new(Self { level })
+    }
+}
+
+impl Log for TuiLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!(
+            "{} [{}] {}",
+            Local::now().format("%H:%M:%S"),
+            record.level(),
+            record.args()
+        );
+
+        let mut screen = lock_screen();
+        if !screen.active {
+            eprintln!("{}", line);
+            return;
+        }
+        if screen.log.len() == LOG_LINES {
+            screen.log.pop_front();
+        }
+        screen.log.push_back(line);
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for TuiLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// Restores the terminal when dropped, then prints what was logged while the screen was up
+pub struct Tui {
+    render: Option<thread::JoinHandle<()>>,
+}
+
+/// Takes over the terminal with the DRO, progress and log of the running job, and the keys
+/// to control it
+pub fn start(job: Arc<JobHandle>, monitor: Monitor, name: Option<String>) -> io::Result<Tui> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+    lock_screen().active = true;
+
+    thread::spawn(move || {
+        while active() {
+            match event::poll(KEY_POLL) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+            if let Ok(Event::Key(key)) = event::read()
+                && key.kind == KeyEventKind::Press
+            {
+                handle_key(key, &job);
+            }
+        }
+    });
+
+    let render = thread::spawn(move || {
+        while active() {
+            let report = monitor.report();
+            // A failed draw, e.g. while the terminal resizes, is fixed by the next one
+            let _ = draw(
+                name.as_deref(),
+                &status::job(),
+                report.as_ref(),
+                monitor.paused(),
+            );
+            thread::sleep(REFRESH_INTERVAL);
+        }
+    });
+
+    Ok(Tui {
+        render: Some(render),
+    })
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let log = {
+            let mut screen = lock_screen();
+            screen.active = false;
+            // Unblocks a question that is still waiting for an answer
+            screen.prompt = None;
+            std::mem::take(&mut screen.log)
+        };
+        if let Some(render) = self.render.take() {
+            let _ = render.join();
+        }
+
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+
+        for line in log {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+fn handle_key(key: KeyEvent, job: &JobHandle) {
+    // Raw mode stops Ctrl-C from raising SIGINT, so it's handled here instead
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        job.abort();
+        return;
+    }
+
+    // Read before the screen is locked, as drawing does
+    let current = status::job().line;
+
+    let mut screen = lock_screen();
+    if let Some(prompt) = &mut screen.prompt {
+        match key.code {
+            KeyCode::Char(character) => prompt.input.push(character),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Enter => {
+                if let Some(prompt) = screen.prompt.take() {
+                    let _ = prompt.answer.send(prompt.input);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    if std::mem::take(&mut screen.confirm_stop) {
+        drop(screen);
+        if key.code == KeyCode::Char('y') {
+            job.abort();
+        }
+        return;
+    }
+
+    // Messages are logged once the screen is unlocked, as the logger needs it
+    match navigate(&mut screen, key.code, current) {
+        Navigation::Ignored => {}
+        Navigation::Handled => return,
+        Navigation::Info(message) => {
+            drop(screen);
+            info!("{}", message);
+            return;
+        }
+        Navigation::Warn(message) => {
+            drop(screen);
+            warn!("{}", message);
+            return;
+        }
+    }
+
+    match key.code {
+        KeyCode::Char('x') => screen.confirm_stop = true,
+        code => {
+            // The job logs, which needs the screen
+            drop(screen);
+            match code {
+                KeyCode::Char('h') => job.pause(),
+                KeyCode::Char('r') => job.resume(),
+                KeyCode::Char(' ') => job.toggle_pause(),
+                KeyCode::Char('w') => job.skip_wait(),
+                KeyCode::Char('s') => job.toggle_spindle_stop(),
+                KeyCode::Char('c') => job.toggle_flood_coolant(),
+                KeyCode::Char('C') => job.toggle_mist_coolant(),
+                _ => {}
+            }
+        }
+    }
+}
+
+enum Navigation {
+    // Not a key for the program pane, or there is no program shown
+    Ignored,
+    Handled,
+    Info(String),
+    Warn(String),
+}
+
+fn navigate(screen: &mut Screen, code: KeyCode, current: Option<i32>) -> Navigation {
+    if let Some(entry) = &mut screen.entry {
+        match code {
+            KeyCode::Char(character) => entry.input.push(character),
+            KeyCode::Backspace => {
+                entry.input.pop();
+            }
+            KeyCode::Esc => screen.entry = None,
+            KeyCode::Enter => {
+                let (Some(entry), Some(program)) = (screen.entry.take(), &mut screen.program)
+                else {
+                    return Navigation::Handled;
+                };
+                let input = entry.input.trim();
+                return match entry.kind {
+                    EntryKind::Line => program.go_to(input),
+                    EntryKind::Search => {
+                        // An empty search finds the next line for the last one
+                        if !input.is_empty() {
+                            program.search = Some(input.to_string());
+                        }
+                        program.find_next(current)
+                    }
+                };
+            }
+            _ => {}
+        }
+        return Navigation::Handled;
+    }
+
+    let Some(program) = &mut screen.program else {
+        return Navigation::Ignored;
+    };
+    let page = PROGRAM_ROWS as isize;
+    match code {
+        KeyCode::Char('g') => {
+            screen.entry = Some(Entry {
+                kind: EntryKind::Line,
+                input: String::new(),
+            })
+        }
+        KeyCode::Char('/') => {
+            screen.entry = Some(Entry {
+                kind: EntryKind::Search,
+                input: String::new(),
+            })
+        }
+        KeyCode::Char('n') => return program.find_next(current),
+        KeyCode::Char('m') => return program.toggle_mark(current),
+        KeyCode::Char('f') => program.view = None,
+        KeyCode::Up => program.scroll(current, -1),
+        KeyCode::Down => program.scroll(current, 1),
+        KeyCode::PageUp => program.scroll(current, -page),
+        KeyCode::PageDown => program.scroll(current, page),
+        _ => return Navigation::Ignored,
+    }
+    Navigation::Handled
+}
+
+impl Program {
+    // The line in view, the one last acknowledged until the view is moved
+    fn viewed(&self, current: Option<i32>) -> usize {
+        let line = self
+            .view
+            .or(current.map(|line| line.max(1) as usize))
+            .unwrap_or(1);
+        line.clamp(1, self.lines.len().max(1))
+    }
+
+    fn scroll(&mut self, current: Option<i32>, by: isize) {
+        let line = self.viewed(current).saturating_add_signed(by);
+        self.view = Some(line.clamp(1, self.lines.len().max(1)));
+    }
+
+    fn go_to(&mut self, input: &str) -> Navigation {
+        match input.parse() {
+            Ok(line) if (1..=self.lines.len()).contains(&line) => {
+                self.view = Some(line);
+                Navigation::Handled
+            }
+            _ => Navigation::Warn(format!(
+                "'{}' isn't a line of the program (1 to {})",
+                input,
+                self.lines.len()
+            )),
+        }
+    }
+
+    // Moves the view to the next line with the search in it, going round to the start at the end
+    fn find_next(&mut self, current: Option<i32>) -> Navigation {
+        let Some(search) = &self.search else {
+            return Navigation::Warn("Nothing to search for yet, press / first".to_string());
+        };
+
+        // Indexes from the viewed line's are the lines after it
+        let viewed = self.viewed(current);
+        let count = self.lines.len();
+        match (0..count)
+            .map(|offset| (viewed + offset) % count)
+            .find(|&index| line_matches(&self.lines[index], search))
+        {
+            Some(index) => {
+                self.view = Some(index + 1);
+                Navigation::Handled
+            }
+            None => Navigation::Warn(format!("No line of the program has '{}'", search)),
+        }
+    }
+
+    fn toggle_mark(&mut self, current: Option<i32>) -> Navigation {
+        if self.checkpoint.is_none() {
+            return Navigation::Warn(
+                "Marking a line to restart from needs `checkpoint` set on the gcode step"
+                    .to_string(),
+            );
+        }
+
+        let line = self.viewed(current);
+        if self.mark == Some(line) {
+            self.mark = None;
+            Navigation::Info(format!("Cleared the mark on line {}", line))
+        } else {
+            self.mark = Some(line);
+            Navigation::Info(format!(
+                "Marked line {}, the next run of the step with `resume` starts there",
+                line
+            ))
+        }
+    }
+}
+
+fn draw(name: Option<&str>, job: &Job, report: Option<&Report>, paused: bool) -> io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let width = width as usize;
+
+    let mut rows = Vec::new();
+    rows.push(format!(
+        "cnc-ctrl{}  |  {}  |  Grbl: {}{}",
+        name.map(|name| format!(" {}", name)).unwrap_or_default(),
+        job.state.name().to_uppercase(),
+        report.map(Report::state).unwrap_or("?"),
+        if paused { "  |  PAUSED" } else { "" }
+    ));
+    rows.push(match &job.step {
+        Some(step) => format!(
+            "Step {}: {} ({}){}",
+            step.number,
+            step.name,
+            step.kind,
+            job.line
+                .map(|line| format!("  |  line {}", line))
+                .unwrap_or_default()
+        ),
+        None => "No step has run yet".to_string(),
+    });
+    rows.push(match job.progress {
+        Some(percent) => {
+            let bar = width.saturating_sub(10);
+            let done = ((percent / 100.0) * bar as f64).round() as usize;
+            format!(
+                "[{}{}] {:5.1}%",
+                "#".repeat(done.min(bar)),
+                "-".repeat(bar.saturating_sub(done)),
+                percent
+            )
+        }
+        None => String::new(),
+    });
+    rows.push(String::new());
+
+    let machine = report.and_then(Report::machine_position);
+    let work = report.and_then(Report::work_position);
+    rows.push(format!("{:4}{:>12}{:>12}", "", "Machine", "Work"));
+    for (index, axis) in ["X", "Y", "Z"].iter().enumerate() {
+        let value = |position: Option<(f32, f32, f32)>| match position {
+            Some((x, y, z)) => format!("{:.3}", [x, y, z][index]),
+            None => "-".to_string(),
+        };
+        rows.push(format!(
+            "{:4}{:>12}{:>12}",
+            axis,
+            value(machine),
+            value(work)
+        ));
+    }
+    rows.push(String::new());
+
+    let value = |value: Option<String>| value.unwrap_or("-".to_string());
+    rows.push(format!(
+        "Feed {}  Spindle {}  |  Overrides: feed {}%  rapid {}%  spindle {}%",
+        value(
+            report
+                .and_then(|report| report.feed)
+                .map(|feed| feed.to_string())
+        ),
+        value(
+            report
+                .and_then(|report| report.spindle)
+                .map(|speed| speed.to_string())
+        ),
+        value(
+            report
+                .and_then(|report| report.ov)
+                .map(|(feed, _, _)| feed.to_string())
+        ),
+        value(
+            report
+                .and_then(|report| report.ov)
+                .map(|(_, rapid, _)| rapid.to_string())
+        ),
+        value(
+            report
+                .and_then(|report| report.ov)
+                .map(|(_, _, spindle)| spindle.to_string())
+        ),
+    ));
+    rows.push(format!(
+        "Planner {} blocks free  |  RX {} bytes free",
+        value(
+            report
+                .and_then(|report| report.bf)
+                .map(|(planner, _)| planner.to_string())
+        ),
+        value(
+            report
+                .and_then(|report| report.bf)
+                .map(|(_, rx)| rx.to_string())
+        ),
+    ));
+    rows.push("-".repeat(width));
+
+    let screen = lock_screen();
+    if let Some(program) = &screen.program {
+        let viewed = program.viewed(job.line);
+        rows.push(format!(
+            "Program line {} of {}{}{}",
+            viewed,
+            program.lines.len(),
+            if program.view.is_none() {
+                "  |  following"
+            } else {
+                ""
+            },
+            program
+                .mark
+                .map(|mark| format!("  |  restart from {}", mark))
+                .unwrap_or_default()
+        ));
+        let first = viewed.saturating_sub(PROGRAM_ROWS / 2).clamp(
+            1,
+            program.lines.len().saturating_sub(PROGRAM_ROWS - 1).max(1),
+        );
+        for number in first..first + PROGRAM_ROWS {
+            let Some(line) = program.lines.get(number - 1) else {
+                rows.push(String::new());
+                continue;
+            };
+            let marker = if job.line == Some(number as i32) {
+                '>'
+            } else if program.mark == Some(number) {
+                '*'
+            } else {
+                ' '
+            };
+            rows.push(format!("{}{:>7}  {}", marker, number, line));
+        }
+        rows.push("-".repeat(width));
+    }
+
+    let log_rows = (height as usize).saturating_sub(rows.len() + 2);
+    rows.extend(
+        screen
+            .log
+            .iter()
+            .skip(screen.log.len().saturating_sub(log_rows))
+            .cloned(),
+    );
+    rows.resize(height.saturating_sub(2) as usize, String::new());
+    rows.push(match (&screen.prompt, screen.confirm_stop, &screen.entry) {
+        (Some(prompt), _, _) => format!("{} {}_", prompt.question, prompt.input),
+        (None, true, _) => "Stop the job? [y/N]".to_string(),
+        (None, false, Some(entry)) => format!(
+            "{} {}_",
+            match entry.kind {
+                EntryKind::Line => "Go to line:",
+                EntryKind::Search => "Search:",
+            },
+            entry.input
+        ),
+        (None, false, None) => String::new(),
+    });
+    drop(screen);
+
+    let mut stdout = io::stdout();
+    for (index, row) in rows.iter().enumerate() {
+        let row: String = row.chars().take(width).collect();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, index as u16),
+            Print(row),
+            terminal::Clear(ClearType::UntilNewLine)
+        )?;
+    }
+    queue!(
+        stdout,
+        cursor::MoveTo(0, height.saturating_sub(1)),
+        SetAttribute(Attribute::Reverse),
+        Print(format!(
+            "{:width$}",
+            KEYS.chars().take(width).collect::<String>()
+        )),
+        SetAttribute(Attribute::Reset)
+    )?;
+    stdout.flush()
+}
+
+fn lock_screen() -> std::sync::MutexGuard<'static, Screen> {
+    match SCREEN.lock() {
+        Ok(screen) => screen,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
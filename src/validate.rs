@@ -0,0 +1,430 @@
+use std::fs::{self, File};
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::config::{
+    CncConfig, GpioBackend, InterlockOutput, NotifyChannel, RequirementCheck, SmtpSecurity, Step,
+    StepConfig, WaitCondition, expand_path,
+};
+use crate::controller::command::Realtime;
+
+// Highest GPIO (BCM numbering) on the Raspberry Pi header
+const MAX_GPIO_PIN: u8 = 27;
+const MIN_RX_BUFFER_BYTES: usize = 5;
+const WRITE_CHECK_FILE: &str = ".cnc-ctrl-write-check";
+
+impl CncConfig {
+    /// Checks for problems that would otherwise only show up part way through a job,
+    /// each one is reported with the key it was found at
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut problems = Problems {
+            found: Vec::new(),
+            // Other boards number their lines differently
+            max_pin: (self.gpio.backend == GpioBackend::Rppal).then_some(MAX_GPIO_PIN),
+            machine_z: self
+                .machine
+                .as_ref()
+                .is_some_and(|machine| machine.z.is_some()),
+        };
+
+        if self.logs.save {
+            problems.output("logs.path", &self.logs.path);
+        }
+        if let Some(operator) = &self.logs.operator {
+            problems.output("logs.operator.path", &operator.path);
+        }
+        // With no time to wait, a missing device file fails straight away
+        if self.serial.connect.wait_for_device && self.serial.connect.max_wait_ms == 0 {
+            problems.add(
+                "serial.connect.max_wait_ms",
+                "must be more than 0 with wait_for_device",
+            );
+        }
+        // Character counting keeps a byte of the buffer free, so anything smaller can't take
+        // even a short line like `G0`
+        if self.grbl.rx_buffer_size_bytes < MIN_RX_BUFFER_BYTES {
+            problems.add(
+                "grbl.rx_buffer_size_bytes",
+                &format!("must be at least {}", MIN_RX_BUFFER_BYTES),
+            );
+        }
+        if let Some(dump_traffic) = &self.serial.dump_traffic {
+            problems.output("serial.dump_traffic", dump_traffic);
+        }
+        // The name goes into the title of notifications, e.g. an email's subject
+        if let Some(name) = &self.job.name
+            && name.chars().any(char::is_control)
+        {
+            problems.add("job.name", "shouldn't contain control characters");
+        }
+        if let Some(report) = &self.job.report {
+            problems.output("job.report", report);
+        }
+        // A job made for another machine is refused before anything runs
+        if let Some(requirements) = &self.job.requirements {
+            for (key, mismatch) in requirements.mismatches(self.machine.as_ref()) {
+                let mismatch = match &self.profile {
+                    Some(profile) => format!("{} (profile '{}')", mismatch, profile),
+                    None => mismatch,
+                };
+                problems.add(&format!("job.requirements.{}", key), &mismatch);
+            }
+        }
+
+        problems.pin("inputs.signal.pin", self.inputs.signal.pin);
+        if let Some(pause) = &self.inputs.pause {
+            problems.pin("inputs.pause.pin", pause.pin);
+            if pause.presses.is_some() {
+                problems.add(
+                    "inputs.pause.presses",
+                    "only the signal input takes presses",
+                );
+            }
+        }
+        if let Some(door) = &self.inputs.door {
+            problems.pin("inputs.door.pin", door.pin);
+        }
+
+        for (index, requirement) in self.requires.iter().enumerate() {
+            let key = format!("requires[{}]", index);
+            match &requirement.check {
+                RequirementCheck::Tcp { address } | RequirementCheck::Mqtt { address } => {
+                    problems.address(&format!("{}.address", key), address)
+                }
+                RequirementCheck::Gpio { pin, .. } => problems.pin(&format!("{}.pin", key), *pin),
+                RequirementCheck::File { .. } => {}
+            }
+        }
+
+        if let Some(status) = &self.status {
+            for (key, pin) in [
+                ("status.idle", status.idle),
+                ("status.waiting", status.waiting),
+                ("status.running", status.running),
+                ("status.error", status.error),
+            ] {
+                if let Some(pin) = pin {
+                    problems.pin(key, pin);
+                }
+            }
+        }
+
+        if let Some(pendant) = &self.pendant {
+            problems.pin("pendant.encoder.a", pendant.encoder.a);
+            problems.pin("pendant.encoder.b", pendant.encoder.b);
+            for (index, axis) in pendant.axes.iter().enumerate() {
+                problems.pin(&format!("pendant.axes[{}].pin", index), axis.pin);
+            }
+            for (index, step) in pendant.steps.iter().enumerate() {
+                problems.pin(&format!("pendant.steps[{}].pin", index), step.pin);
+            }
+            if pendant.axes.is_empty() {
+                problems.add("pendant.axes", "at least one axis is required");
+            }
+            if pendant.steps.is_empty() {
+                problems.add("pendant.steps", "at least one step is required");
+            }
+        }
+
+        if let Some(mqtt) = &self.mqtt {
+            problems.address("mqtt.address", &mqtt.address);
+            if mqtt.topic.is_empty() || mqtt.topic.contains(['+', '#']) {
+                problems.add("mqtt.topic", "should be a topic without wildcards");
+            }
+            // MQTT 3.1.1 only sends a password after a username
+            if mqtt.password.is_some() && mqtt.username.is_none() {
+                problems.add("mqtt.password", "needs a username");
+            }
+        }
+
+        if let Some(notify) = &self.notify {
+            if notify.channels.is_empty() {
+                problems.add("notify.channels", "at least one channel is required");
+            }
+            for (index, channel) in notify.channels.iter().enumerate() {
+                let key = |field: &str| format!("notify.channels[{}].{}", index, field);
+                match channel {
+                    NotifyChannel::Ntfy { server, topic, .. } => {
+                        if !server.starts_with("http://") && !server.starts_with("https://") {
+                            problems.add(&key("server"), "should start with http:// or https://");
+                        }
+                        if topic.is_empty() {
+                            problems.add(&key("topic"), "should not be empty");
+                        }
+                    }
+                    NotifyChannel::Telegram { bot_token, chat_id } => {
+                        if bot_token.is_empty() {
+                            problems.add(&key("bot_token"), "should not be empty");
+                        }
+                        if chat_id.is_empty() {
+                            problems.add(&key("chat_id"), "should not be empty");
+                        }
+                    }
+                    NotifyChannel::Email {
+                        server,
+                        security,
+                        username,
+                        password,
+                        to,
+                        ..
+                    } => {
+                        problems.address(&key("server"), server);
+                        if username.is_some() != password.is_some() {
+                            problems.add(&key("password"), "username and password go together");
+                        }
+                        if username.is_some() && *security == SmtpSecurity::None {
+                            problems.add(
+                                &key("security"),
+                                "none would send the password in plain text, use starttls or tls",
+                            );
+                        }
+                        if to.is_empty() {
+                            problems.add(&key("to"), "at least one recipient is required");
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(api) = &self.api {
+            problems.address("api.address", &api.address);
+            if api.token.is_none() && !is_loopback(&api.address) {
+                problems.add(
+                    "api.token",
+                    "is required to listen on an address other than localhost",
+                );
+            }
+            if api.stream_interval_ms == 0 {
+                problems.add("api.stream_interval_ms", "should be more than 0");
+            }
+            if let Some(uploads) = &api.uploads {
+                let directory = Path::new(&uploads.path).join(WRITE_CHECK_FILE);
+                problems.output("api.uploads.path", &directory.to_string_lossy());
+                if uploads.max_size_mb == 0 {
+                    problems.add("api.uploads.max_size_mb", "should be more than 0");
+                }
+            }
+        }
+
+        for (index, interlock) in self.interlocks.iter().enumerate() {
+            if let InterlockOutput::Gpio { pin } = interlock.output {
+                problems.pin(&format!("interlocks[{}].output.pin", index), pin);
+            }
+        }
+
+        if self.steps.is_empty() {
+            problems.add("steps", "at least one step is required");
+        }
+        check_steps(&self.steps, "steps", &mut problems);
+
+        if problems.found.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.found.join("; ").into())
+        }
+    }
+}
+
+fn check_steps(steps: &[StepConfig], key: &str, problems: &mut Problems) {
+    for (index, step) in steps.iter().enumerate() {
+        let key = format!("{}[{}]", key, index);
+        let key = |name: &str| format!("{}.{}", key, name);
+
+        match &step.step {
+            Step::Gcode(gcode) => {
+                // Stdin and templated paths can only be checked when the step runs
+                if gcode.path != "-" && !gcode.path.contains("{%") {
+                    problems.input(&key("path"), &gcode.path);
+                }
+                if let Some(save_path) = gcode
+                    .probe
+                    .as_ref()
+                    .and_then(|probe| probe.save_path.as_ref())
+                {
+                    problems.output(&key("probe.save_path"), save_path);
+                }
+                if let Some(summary_path) = gcode
+                    .probe
+                    .as_ref()
+                    .and_then(|probe| probe.summary_path.as_ref())
+                {
+                    problems.output(&key("probe.summary_path"), summary_path);
+                }
+                if let Some(responses) = &gcode.responses {
+                    problems.output(&key("responses.save_path"), &responses.save_path);
+                }
+                if let Some(reports) = &gcode.reports {
+                    problems.output(&key("reports.save_path"), &reports.save_path);
+                    if reports.interval_ms == 0 {
+                        problems.add(&key("reports.interval_ms"), "must be greater than 0");
+                    }
+                }
+                if let Some(checkpoint) = &gcode.checkpoint {
+                    problems.output(&key("checkpoint"), checkpoint);
+                }
+                if (gcode.resume || gcode.start_line.is_some_and(|line| line > 1))
+                    && gcode.resume_moves.safe_z.is_none()
+                    && !problems.machine_z
+                {
+                    problems.add(
+                        &key("resume_moves.safe_z"),
+                        "is needed to resume from a later line, unless machine.z is set",
+                    );
+                }
+                if gcode.resume_moves.spindle_delay_s < 0.0 {
+                    problems.add(&key("resume_moves.spindle_delay_s"), "can't be negative");
+                }
+                if let Some(overrides) = &gcode.overrides {
+                    // Grbl limits the feed and spindle overrides, and has three rapid settings
+                    for (name, percent) in
+                        [("feed", overrides.feed), ("spindle", overrides.spindle)]
+                    {
+                        if let Some(percent) = percent
+                            && !(10..=200).contains(&percent)
+                        {
+                            problems.add(
+                                &key(&format!("overrides.{}", name)),
+                                &format!("{}% isn't between 10% and 200%", percent),
+                            );
+                        }
+                    }
+                    if let Some(rapid) = overrides.rapid
+                        && Realtime::rapid_override(rapid).is_none()
+                    {
+                        problems.add(
+                            &key("overrides.rapid"),
+                            &format!("{}% isn't 100%, 50% or 25%", rapid),
+                        );
+                    }
+                }
+            }
+            Step::Bash(bash) => {
+                if let Some(cwd) = &bash.cwd
+                    && !Path::new(&expand_path(cwd)).is_dir()
+                {
+                    problems.add(&key("cwd"), &format!("'{}' isn't a directory", cwd));
+                }
+                if let Some(stdout) = &bash.stdout {
+                    problems.output(&key("stdout"), stdout);
+                }
+                if let Some(stderr) = &bash.stderr {
+                    problems.output(&key("stderr"), stderr);
+                }
+            }
+            // Like a gcode step's, a templated path can only be checked when the step runs
+            Step::Settings(settings) if !settings.path.contains("{%") => {
+                problems.input(&key("path"), &settings.path)
+            }
+            Step::Gpio(gpio) => problems.pin(&key("pin"), gpio.pin),
+            Step::Wait(wait) => {
+                if let Some(WaitCondition::Gpio { pin, .. }) = wait.until {
+                    problems.pin(&key("until.pin"), pin);
+                }
+            }
+            Step::Http(http) => match http
+                .url
+                .strip_prefix("http://")
+                .or_else(|| http.url.strip_prefix("https://"))
+            {
+                Some(rest) => {
+                    let host = rest.split(['/', '?']).next().unwrap_or_default();
+                    if host.contains(':') {
+                        problems.address(&key("url"), host);
+                    }
+                }
+                None => problems.add(&key("url"), "must start with http:// or https://"),
+            },
+            Step::Repeat(repeat) => {
+                if repeat.steps.is_empty() {
+                    problems.add(&key("steps"), "at least one step is required");
+                }
+                check_steps(&repeat.steps, &key("steps"), problems);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Whether only this machine can reach an address, e.g. `127.0.0.1:8080` or `[::1]:8080`
+fn is_loopback(address: &str) -> bool {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_loopback())
+}
+
+struct Problems {
+    found: Vec<String>,
+    max_pin: Option<u8>,
+    // Resuming retracts to the top of the machine's Z travel unless a step sets its own safe Z
+    machine_z: bool,
+}
+
+impl Problems {
+    fn add(&mut self, key: &str, message: &str) {
+        self.found.push(format!("{}: {}", key, message));
+    }
+
+    fn pin(&mut self, key: &str, pin: u8) {
+        if let Some(max_pin) = self.max_pin
+            && pin > max_pin
+        {
+            self.add(
+                key,
+                &format!("GPIO {} is out of range (0 to {})", pin, max_pin),
+            );
+        }
+    }
+
+    fn address(&mut self, key: &str, address: &str) {
+        match address
+            .rsplit_once(':')
+            .map(|(_, port)| port.parse::<u16>())
+        {
+            Some(Ok(port)) if port > 0 => {}
+            Some(_) => self.add(
+                key,
+                &format!("'{}' doesn't have a port between 1 and 65535", address),
+            ),
+            None => self.add(key, &format!("'{}' should be host:port", address)),
+        }
+    }
+
+    fn input(&mut self, key: &str, path: &str) {
+        if !Path::new(&expand_path(path)).is_file() {
+            self.add(key, &format!("'{}' doesn't exist", path));
+        }
+    }
+
+    // Missing directories are created when the file is written, so check the nearest one that exists
+    fn output(&mut self, key: &str, path: &str) {
+        let path = expand_path(path);
+        let Some(directory) = Path::new(&path)
+            .ancestors()
+            .skip(1)
+            .map(|directory| {
+                if directory.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    directory
+                }
+            })
+            .find(|directory| directory.is_dir())
+        else {
+            return;
+        };
+
+        let check = directory.join(WRITE_CHECK_FILE);
+        match File::create(&check) {
+            Ok(_) => {
+                let _ = fs::remove_file(&check);
+            }
+            Err(error) => self.add(
+                key,
+                &format!("'{}' isn't writable: {}", directory.display(), error),
+            ),
+        }
+    }
+}